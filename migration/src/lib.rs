@@ -17,6 +17,21 @@ mod m20241229_072649_tiers;
 mod m20241229_094545_si_for_stripe;
 mod m20241229_102530_adds_back_tier_col;
 mod m20241229_103359_adds_tier;
+mod m20241230_091512_messages_pagination_index;
+mod m20241230_143027_message_edits_and_deletes;
+mod m20241231_101744_message_threads;
+mod m20241231_134210_message_reactions;
+mod m20250102_084512_message_search;
+mod m20250103_091204_channel_membership;
+mod m20250104_102318_participants_belong_to_org;
+mod m20250105_083051_participant_profiles;
+mod m20250106_094417_api_key_scopes;
+mod m20250107_081236_api_key_hmac;
+mod m20250108_102455_api_key_rotation;
+mod m20250109_083317_api_key_expiry;
+mod m20250110_091845_api_key_allowlists;
+mod m20250111_140522_stripe_webhooks;
+mod m20250112_093207_stripe_usage_reports;
 
 pub struct Migrator;
 
@@ -41,6 +56,21 @@ impl MigratorTrait for Migrator {
             Box::new(m20241229_094545_si_for_stripe::Migration),
             Box::new(m20241229_102530_adds_back_tier_col::Migration),
             Box::new(m20241229_103359_adds_tier::Migration),
+            Box::new(m20241230_091512_messages_pagination_index::Migration),
+            Box::new(m20241230_143027_message_edits_and_deletes::Migration),
+            Box::new(m20241231_101744_message_threads::Migration),
+            Box::new(m20241231_134210_message_reactions::Migration),
+            Box::new(m20250102_084512_message_search::Migration),
+            Box::new(m20250103_091204_channel_membership::Migration),
+            Box::new(m20250104_102318_participants_belong_to_org::Migration),
+            Box::new(m20250105_083051_participant_profiles::Migration),
+            Box::new(m20250106_094417_api_key_scopes::Migration),
+            Box::new(m20250107_081236_api_key_hmac::Migration),
+            Box::new(m20250108_102455_api_key_rotation::Migration),
+            Box::new(m20250109_083317_api_key_expiry::Migration),
+            Box::new(m20250110_091845_api_key_allowlists::Migration),
+            Box::new(m20250111_140522_stripe_webhooks::Migration),
+            Box::new(m20250112_093207_stripe_usage_reports::Migration),
         ]
     }
 }
@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Backs keyset pagination on (created_at, id) within a channel
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_messages_channel_created_at_id")
+                    .table(Messages::Table)
+                    .col(Messages::ChannelId)
+                    .col(Messages::CreatedAt)
+                    .col(Messages::Id)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_messages_channel_created_at_id")
+                    .table(Messages::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Messages {
+    Table,
+    Id,
+    ChannelId,
+    CreatedAt,
+}
@@ -0,0 +1,104 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Edit marker and soft delete tombstone on messages
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Messages::Table)
+                    .add_column(ColumnDef::new(Messages::EditedAt).timestamp().null())
+                    .add_column(ColumnDef::new(Messages::DeletedAt).timestamp().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Previous contents of a message, one row per edit
+        manager
+            .create_table(
+                Table::create()
+                    .table(MessageRevisions::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(MessageRevisions::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(MessageRevisions::MessageId).uuid().not_null())
+                    .col(ColumnDef::new(MessageRevisions::Content).string().not_null())
+                    .col(
+                        ColumnDef::new(MessageRevisions::EditedByParticipantId)
+                            .uuid()
+                            .null(), // Null when the edit was made with an API key
+                    )
+                    .col(
+                        ColumnDef::new(MessageRevisions::CreatedAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_message_revisions_message")
+                            .from(MessageRevisions::Table, MessageRevisions::MessageId)
+                            .to(Messages::Table, Messages::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_message_revisions_message")
+                    .table(MessageRevisions::Table)
+                    .col(MessageRevisions::MessageId)
+                    .col(MessageRevisions::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MessageRevisions::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Messages::Table)
+                    .drop_column(Messages::EditedAt)
+                    .drop_column(Messages::DeletedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Messages {
+    Table,
+    Id,
+    EditedAt,
+    DeletedAt,
+}
+
+#[derive(DeriveIden)]
+enum MessageRevisions {
+    Table,
+    Id,
+    MessageId,
+    Content,
+    EditedByParticipantId,
+    CreatedAt,
+}
@@ -0,0 +1,74 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Replies point at the top level message that started the thread
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Messages::Table)
+                    .add_column(ColumnDef::new(Messages::ParentMessageId).uuid().null())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk_messages_parent_message")
+                            .from_tbl(Messages::Table)
+                            .from_col(Messages::ParentMessageId)
+                            .to_tbl(Messages::Table)
+                            .to_col(Messages::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Serves both the replies listing and the reply counts per parent
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_messages_parent_created_at_id")
+                    .table(Messages::Table)
+                    .col(Messages::ParentMessageId)
+                    .col(Messages::CreatedAt)
+                    .col(Messages::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_messages_parent_created_at_id")
+                    .table(Messages::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Messages::Table)
+                    .drop_foreign_key(Alias::new("fk_messages_parent_message"))
+                    .drop_column(Messages::ParentMessageId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Messages {
+    Table,
+    Id,
+    ParentMessageId,
+    CreatedAt,
+}
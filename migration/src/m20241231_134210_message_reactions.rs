@@ -0,0 +1,82 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // One row per participant, message and emoji, so the same reaction can't be added twice
+        manager
+            .create_table(
+                Table::create()
+                    .table(MessageReactions::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(MessageReactions::MessageId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(MessageReactions::ParticipantId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(MessageReactions::Emoji).string().not_null())
+                    .col(
+                        ColumnDef::new(MessageReactions::CreatedAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(MessageReactions::MessageId)
+                            .col(MessageReactions::ParticipantId)
+                            .col(MessageReactions::Emoji),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_message_reactions_message")
+                            .from(MessageReactions::Table, MessageReactions::MessageId)
+                            .to(Messages::Table, Messages::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_message_reactions_participant")
+                            .from(MessageReactions::Table, MessageReactions::ParticipantId)
+                            .to(Participant::Table, Participant::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MessageReactions::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum MessageReactions {
+    Table,
+    MessageId,
+    ParticipantId,
+    Emoji,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Messages {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Participant {
+    Table,
+    Id,
+}
@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Generated column, so Postgres keeps it in sync on insert and edit
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"
+                ALTER TABLE messages
+                ADD COLUMN search_vector tsvector
+                GENERATED ALWAYS AS (to_tsvector('english', content)) STORED;
+                "#,
+            )
+            .await?;
+
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"
+                CREATE INDEX idx_messages_search_vector
+                ON messages USING GIN (search_vector);
+                "#,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared("DROP INDEX IF EXISTS idx_messages_search_vector;")
+            .await?;
+
+        manager
+            .get_connection()
+            .execute_unprepared("ALTER TABLE messages DROP COLUMN IF EXISTS search_vector;")
+            .await?;
+
+        Ok(())
+    }
+}
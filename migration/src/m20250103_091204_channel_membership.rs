@@ -0,0 +1,115 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Existing channels stay public
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Channels::Table)
+                    .add_column(
+                        ColumnDef::new(Channels::IsPrivate)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Memberships go away together with their channel or participant
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ChannelParticipant::Table)
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk_channel_participant_channel")
+                            .from_tbl(ChannelParticipant::Table)
+                            .from_col(ChannelParticipant::ChannelId)
+                            .to_tbl(Channels::Table)
+                            .to_col(Channels::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk_channel_participant_participant")
+                            .from_tbl(ChannelParticipant::Table)
+                            .from_col(ChannelParticipant::ParticipantId)
+                            .to_tbl(Participant::Table)
+                            .to_col(Participant::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_channel_participant_participant")
+                    .table(ChannelParticipant::Table)
+                    .col(ChannelParticipant::ParticipantId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_channel_participant_participant")
+                    .table(ChannelParticipant::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ChannelParticipant::Table)
+                    .drop_foreign_key(Alias::new("fk_channel_participant_channel"))
+                    .drop_foreign_key(Alias::new("fk_channel_participant_participant"))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Channels::Table)
+                    .drop_column(Channels::IsPrivate)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Channels {
+    Table,
+    Id,
+    IsPrivate,
+}
+
+#[derive(DeriveIden)]
+enum ChannelParticipant {
+    Table,
+    ChannelId,
+    ParticipantId,
+}
+
+#[derive(DeriveIden)]
+enum Participant {
+    Table,
+    Id,
+}
@@ -0,0 +1,176 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, Statement};
+
+// Every organization a participant posted, reacted or edited in, or was added to
+const PARTICIPANT_ORGS: &str = r#"
+    SELECT m.participant_id, c.organization_id
+    FROM messages m
+    JOIN channels c ON c.id = m.channel_id
+    UNION
+    SELECT cp.participant_id, c.organization_id
+    FROM channel_participant cp
+    JOIN channels c ON c.id = cp.channel_id
+    UNION
+    SELECT r.participant_id, c.organization_id
+    FROM message_reactions r
+    JOIN messages m ON m.id = r.message_id
+    JOIN channels c ON c.id = m.channel_id
+    UNION
+    SELECT mr.edited_by_participant_id, c.organization_id
+    FROM message_revisions mr
+    JOIN messages m ON m.id = mr.message_id
+    JOIN channels c ON c.id = m.channel_id
+    WHERE mr.edited_by_participant_id IS NOT NULL
+"#;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Participant::Table)
+                    .add_column(ColumnDef::new(Participant::OrganizationId).uuid().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Existing participants inherit the organization of the channels they were active
+        // in, if those all belong to the same one
+        let db = manager.get_connection();
+        db.execute_unprepared(&format!(
+            r#"
+            WITH participant_orgs AS ({PARTICIPANT_ORGS}),
+            attributable AS (
+                SELECT participant_id, (array_agg(organization_id))[1] AS organization_id
+                FROM participant_orgs
+                GROUP BY participant_id
+                HAVING COUNT(DISTINCT organization_id) = 1
+            )
+            UPDATE participant p
+            SET organization_id = a.organization_id
+            FROM attributable a
+            WHERE a.participant_id = p.id;
+            "#
+        ))
+        .await?;
+
+        // Participants that were created but never used can't be attributed, and there
+        // is nothing of theirs to keep
+        db.execute_unprepared(&format!(
+            r#"
+            DELETE FROM participant p
+            WHERE p.organization_id IS NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM ({PARTICIPANT_ORGS}) o WHERE o.participant_id = p.id
+            );
+            "#
+        ))
+        .await?;
+
+        // The rest were used in several organizations. Nothing of theirs is deleted, they
+        // have to be split up per organization before migrating, see the README.
+        let unattributed = db
+            .query_all(Statement::from_string(
+                manager.get_database_backend(),
+                format!(
+                    r#"
+                    WITH participant_orgs AS ({PARTICIPANT_ORGS})
+                    SELECT p.id::text AS id, COUNT(DISTINCT o.organization_id) AS organizations
+                    FROM participant p
+                    JOIN participant_orgs o ON o.participant_id = p.id
+                    WHERE p.organization_id IS NULL
+                    GROUP BY p.id
+                    ORDER BY p.id
+                    "#
+                ),
+            ))
+            .await?;
+
+        if !unattributed.is_empty() {
+            let participants = unattributed
+                .iter()
+                .map(|row| {
+                    let id: String = row.try_get("", "id")?;
+                    let organizations: i64 = row.try_get("", "organizations")?;
+                    Ok(format!("{} ({} organizations)", id, organizations))
+                })
+                .collect::<Result<Vec<_>, DbErr>>()?;
+
+            return Err(DbErr::Migration(format!(
+                "{} participants are used in several organizations and have to be split up first \
+                 (see \"Participants used in several organizations\" in the README): {}",
+                participants.len(),
+                participants.join(", ")
+            )));
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Participant::Table)
+                    .modify_column(ColumnDef::new(Participant::OrganizationId).uuid().not_null())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk_participant_organization")
+                            .from_tbl(Participant::Table)
+                            .from_col(Participant::OrganizationId)
+                            .to_tbl(Organizations::Table)
+                            .to_col(Organizations::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_participant_organization")
+                    .table(Participant::Table)
+                    .col(Participant::OrganizationId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_participant_organization")
+                    .table(Participant::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Participant::Table)
+                    .drop_foreign_key(Alias::new("fk_participant_organization"))
+                    .drop_column(Participant::OrganizationId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Participant {
+    Table,
+    OrganizationId,
+}
+
+#[derive(DeriveIden)]
+enum Organizations {
+    Table,
+    Id,
+}
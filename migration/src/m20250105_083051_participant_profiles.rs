@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Participant::Table)
+                    .add_column(ColumnDef::new(Participant::ExternalId).string().null())
+                    .add_column(ColumnDef::new(Participant::DisplayName).string().null())
+                    .add_column(ColumnDef::new(Participant::AvatarUrl).string().null())
+                    .add_column(ColumnDef::new(Participant::Metadata).json_binary().null())
+                    .add_column(ColumnDef::new(Participant::DeletedAt).timestamp().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Customers look participants up by their own user id, which only has to
+        // be unique inside their organization
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_participant_organization_external_id")
+                    .table(Participant::Table)
+                    .col(Participant::OrganizationId)
+                    .col(Participant::ExternalId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_participant_organization_external_id")
+                    .table(Participant::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Participant::Table)
+                    .drop_column(Participant::ExternalId)
+                    .drop_column(Participant::DisplayName)
+                    .drop_column(Participant::AvatarUrl)
+                    .drop_column(Participant::Metadata)
+                    .drop_column(Participant::DeletedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Participant {
+    Table,
+    Id,
+    OrganizationId,
+    ExternalId,
+    DisplayName,
+    AvatarUrl,
+    Metadata,
+    DeletedAt,
+}
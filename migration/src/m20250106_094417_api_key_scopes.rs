@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // NULL means unrestricted, so existing keys keep working as before
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApiKeys::Table)
+                    .add_column(
+                        ColumnDef::new(ApiKeys::Scopes)
+                            .array(ColumnType::String(StringLen::None))
+                            .null(),
+                    )
+                    .add_column(ColumnDef::new(ApiKeys::ChannelIds).array(ColumnType::Uuid).null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApiKeys::Table)
+                    .drop_column(ApiKeys::Scopes)
+                    .drop_column(ApiKeys::ChannelIds)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ApiKeys {
+    Table,
+    Scopes,
+    ChannelIds,
+}
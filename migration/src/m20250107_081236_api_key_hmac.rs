@@ -0,0 +1,66 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // New keys only store `key_hmac`. Keys created before keep their bcrypt hash
+        // in `key` until they are used, then the server swaps it for `key_hmac`.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApiKeys::Table)
+                    .add_column(ColumnDef::new(ApiKeys::KeyHmac).string().null())
+                    .modify_column(ColumnDef::new(ApiKeys::Key).string().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_api_keys_key_hmac")
+                    .table(ApiKeys::Table)
+                    .col(ApiKeys::KeyHmac)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Keys that only have an HMAC can't be verified by the old code anymore
+        manager
+            .get_connection()
+            .execute_unprepared("DELETE FROM api_keys WHERE key IS NULL")
+            .await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_api_keys_key_hmac")
+                    .table(ApiKeys::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApiKeys::Table)
+                    .drop_column(ApiKeys::KeyHmac)
+                    .modify_column(ColumnDef::new(ApiKeys::Key).string().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ApiKeys {
+    Table,
+    Key,
+    KeyHmac,
+}
@@ -0,0 +1,63 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // After a rotation the previous secret keeps working until
+        // `previous_key_expires_at`, so clients can switch without downtime
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApiKeys::Table)
+                    .add_column(ColumnDef::new(ApiKeys::PreviousKeyHmac).string().null())
+                    .add_column(ColumnDef::new(ApiKeys::PreviousKeyExpiresAt).timestamp().null())
+                    .add_column(ColumnDef::new(ApiKeys::PreviousKeyLastUsedAt).timestamp().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_api_keys_previous_key_hmac")
+                    .table(ApiKeys::Table)
+                    .col(ApiKeys::PreviousKeyHmac)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_api_keys_previous_key_hmac")
+                    .table(ApiKeys::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApiKeys::Table)
+                    .drop_column(ApiKeys::PreviousKeyHmac)
+                    .drop_column(ApiKeys::PreviousKeyExpiresAt)
+                    .drop_column(ApiKeys::PreviousKeyLastUsedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ApiKeys {
+    Table,
+    PreviousKeyHmac,
+    PreviousKeyExpiresAt,
+    PreviousKeyLastUsedAt,
+}
@@ -0,0 +1,86 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Set by the sweeper once a key has expired
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApiKeys::Table)
+                    .add_column(ColumnDef::new(ApiKeys::RevokedAt).timestamp().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // The sweeper looks for keys that expired but haven't been revoked yet
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_api_keys_expires_at")
+                    .table(ApiKeys::Table)
+                    .col(ApiKeys::ExpiresAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Null means keys may live forever
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Organizations::Table)
+                    .add_column(
+                        ColumnDef::new(Organizations::MaxApiKeyLifetimeDays)
+                            .integer()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Organizations::Table)
+                    .drop_column(Organizations::MaxApiKeyLifetimeDays)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_api_keys_expires_at")
+                    .table(ApiKeys::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApiKeys::Table)
+                    .drop_column(ApiKeys::RevokedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ApiKeys {
+    Table,
+    ExpiresAt,
+    RevokedAt,
+}
+
+#[derive(DeriveIden)]
+enum Organizations {
+    Table,
+    MaxApiKeyLifetimeDays,
+}
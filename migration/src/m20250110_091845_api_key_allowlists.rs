@@ -0,0 +1,133 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // NULL means requests may come from anywhere
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApiKeys::Table)
+                    .add_column(
+                        ColumnDef::new(ApiKeys::AllowedIps)
+                            .array(ColumnType::String(StringLen::None))
+                            .null(),
+                    )
+                    .add_column(
+                        ColumnDef::new(ApiKeys::AllowedOrigins)
+                            .array(ColumnType::String(StringLen::None))
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Requests rejected by a key's allowlists, kept for auditing. Repeated rejections
+        // from the same client are written as one row with the number of times it happened.
+        manager
+            .create_table(
+                Table::create()
+                    .table(ApiKeyAuditEvents::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ApiKeyAuditEvents::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ApiKeyAuditEvents::ApiKeyId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(ApiKeyAuditEvents::OrganizationId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ApiKeyAuditEvents::Event).string().not_null())
+                    .col(ColumnDef::new(ApiKeyAuditEvents::ClientIp).string().null())
+                    .col(ColumnDef::new(ApiKeyAuditEvents::Origin).string().null())
+                    .col(
+                        ColumnDef::new(ApiKeyAuditEvents::Occurrences)
+                            .integer()
+                            .not_null()
+                            .default(1),
+                    )
+                    .col(
+                        ColumnDef::new(ApiKeyAuditEvents::CreatedAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_api_key_audit_events_api_key")
+                            .from(ApiKeyAuditEvents::Table, ApiKeyAuditEvents::ApiKeyId)
+                            .to(ApiKeys::Table, ApiKeys::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_api_key_audit_events_organization")
+                            .from(ApiKeyAuditEvents::Table, ApiKeyAuditEvents::OrganizationId)
+                            .to(Organizations::Table, Organizations::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_api_key_audit_events_api_key_created_at")
+                    .table(ApiKeyAuditEvents::Table)
+                    .col(ApiKeyAuditEvents::ApiKeyId)
+                    .col(ApiKeyAuditEvents::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ApiKeyAuditEvents::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApiKeys::Table)
+                    .drop_column(ApiKeys::AllowedIps)
+                    .drop_column(ApiKeys::AllowedOrigins)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ApiKeys {
+    Table,
+    Id,
+    AllowedIps,
+    AllowedOrigins,
+}
+
+#[derive(DeriveIden)]
+enum ApiKeyAuditEvents {
+    Table,
+    Id,
+    ApiKeyId,
+    OrganizationId,
+    Event,
+    ClientIp,
+    Origin,
+    Occurrences,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Organizations {
+    Table,
+    Id,
+}
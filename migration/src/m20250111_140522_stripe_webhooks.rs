@@ -0,0 +1,86 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Mirrors the Stripe subscription status, e.g. `active`, `past_due` or `canceled`
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Organizations::Table)
+                    .add_column(
+                        ColumnDef::new(Organizations::StripeSubscriptionStatus)
+                            .string()
+                            .null(),
+                    )
+                    // When the last applied event was created, Stripe doesn't deliver them in order
+                    .add_column(
+                        ColumnDef::new(Organizations::StripeEventCreatedAt)
+                            .timestamp()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Every webhook event that was handled, so that redeliveries are skipped
+        manager
+            .create_table(
+                Table::create()
+                    .table(StripeWebhookEvents::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(StripeWebhookEvents::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(StripeWebhookEvents::EventType)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(StripeWebhookEvents::ProcessedAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(StripeWebhookEvents::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Organizations::Table)
+                    .drop_column(Organizations::StripeSubscriptionStatus)
+                    .drop_column(Organizations::StripeEventCreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Organizations {
+    Table,
+    StripeSubscriptionStatus,
+    StripeEventCreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum StripeWebhookEvents {
+    Table,
+    Id,
+    EventType,
+    ProcessedAt,
+}
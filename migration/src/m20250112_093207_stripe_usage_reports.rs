@@ -0,0 +1,160 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Usage counted since the last flush, per Stripe subscription item
+        manager
+            .create_table(
+                Table::create()
+                    .table(StripeUsageCounters::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(StripeUsageCounters::SubscriptionItemId)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(StripeUsageCounters::OrganizationId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(StripeUsageCounters::PendingQuantity)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(StripeUsageCounters::UpdatedAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_stripe_usage_counters_organization")
+                            .from(StripeUsageCounters::Table, StripeUsageCounters::OrganizationId)
+                            .to(Organizations::Table, Organizations::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Flushed quantities. The id doubles as Stripe's idempotency key, so a report
+        // that is sent again after a crash isn't billed twice.
+        manager
+            .create_table(
+                Table::create()
+                    .table(StripeUsageReports::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(StripeUsageReports::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(StripeUsageReports::OrganizationId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(StripeUsageReports::SubscriptionItemId)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(StripeUsageReports::Quantity)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(StripeUsageReports::Attempts)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(StripeUsageReports::LastError).text().null())
+                    .col(
+                        ColumnDef::new(StripeUsageReports::NextAttemptAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(StripeUsageReports::CreatedAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(StripeUsageReports::ReportedAt)
+                            .timestamp()
+                            .null(),
+                    )
+                    .col(ColumnDef::new(StripeUsageReports::FailedAt).timestamp().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_stripe_usage_reports_organization")
+                            .from(StripeUsageReports::Table, StripeUsageReports::OrganizationId)
+                            .to(Organizations::Table, Organizations::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_stripe_usage_reports_next_attempt_at")
+                    .table(StripeUsageReports::Table)
+                    .col(StripeUsageReports::NextAttemptAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(StripeUsageReports::Table).to_owned())
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(StripeUsageCounters::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum StripeUsageCounters {
+    Table,
+    SubscriptionItemId,
+    OrganizationId,
+    PendingQuantity,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum StripeUsageReports {
+    Table,
+    Id,
+    OrganizationId,
+    SubscriptionItemId,
+    Quantity,
+    Attempts,
+    LastError,
+    NextAttemptAt,
+    CreatedAt,
+    ReportedAt,
+    FailedAt,
+}
+
+#[derive(DeriveIden)]
+enum Organizations {
+    Table,
+    Id,
+}
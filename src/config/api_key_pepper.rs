@@ -0,0 +1,57 @@
+use crate::utils::hash_api_key;
+use std::fmt;
+use std::sync::Arc;
+
+// As long as the HMAC-SHA256 output, shorter secrets are easier to guess than the keys
+pub const MIN_API_KEY_PEPPER_BYTES: usize = 32;
+
+// The secret API keys are hashed with. Read once at startup, the server doesn't start
+// without a usable one.
+#[derive(Clone)]
+pub struct ApiKeyPepper(Arc<str>);
+
+impl ApiKeyPepper {
+    pub fn new(pepper: &str) -> Result<Self, String> {
+        if pepper.len() < MIN_API_KEY_PEPPER_BYTES {
+            return Err(format!(
+                "API_KEY_PEPPER must be at least {} bytes long",
+                MIN_API_KEY_PEPPER_BYTES
+            ));
+        }
+        Ok(Self(pepper.into()))
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let pepper =
+            std::env::var("API_KEY_PEPPER").map_err(|_| "API_KEY_PEPPER must be set".to_string())?;
+        Self::new(&pepper)
+    }
+
+    // What keys are stored and looked up by
+    pub fn hash(&self, api_key: &str) -> Result<String, String> {
+        hash_api_key(api_key, &self.0).map_err(|e| e.to_string())
+    }
+}
+
+// Keeps the secret out of logs
+impl fmt::Debug for ApiKeyPepper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiKeyPepper(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_or_empty_peppers_are_rejected() {
+        let lengths = [0, 1, MIN_API_KEY_PEPPER_BYTES - 1, MIN_API_KEY_PEPPER_BYTES];
+        let accepted: Vec<bool> = lengths
+            .iter()
+            .map(|length| ApiKeyPepper::new(&"p".repeat(*length)).is_ok())
+            .collect();
+
+        assert_eq!(accepted, vec![false, false, false, true]);
+    }
+}
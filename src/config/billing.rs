@@ -0,0 +1,147 @@
+use crate::config::{InMemoryBilling, StripeClient};
+use axum::async_trait;
+use serde::Deserialize;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BillingError {
+    // Rate limits, outages and network errors, worth another try
+    #[error("Billing provider unavailable: {0}")]
+    Unavailable(String),
+    #[error("Billing provider rejected the request: {0}")]
+    Rejected(String),
+    #[error("Billing is misconfigured: {0}")]
+    Config(String),
+}
+
+impl BillingError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Unavailable(_))
+    }
+}
+
+// Ids of a new customer's subscription, stored on the organization
+#[derive(Debug, Clone)]
+pub struct NewSubscription {
+    pub customer_id: String,
+    pub subscription_id: String,
+    pub subscription_item_id: String,
+}
+
+// Subscriptions in these states are over and can't be changed any more
+pub const ENDED_SUBSCRIPTION_STATUSES: [&str; 2] = ["canceled", "incomplete_expired"];
+
+#[derive(Debug, Clone)]
+pub struct SubscriptionInfo {
+    pub customer_id: String,
+    // Stripe's status names, e.g. `active` or `past_due`
+    pub status: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct PriceInfo {
+    pub id: String,
+    pub unit_amount: Option<i64>,
+    pub unit_amount_decimal: Option<String>,
+    pub currency: Option<String>,
+    pub interval: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BilledUsage {
+    pub quantity: i64,
+    // Unix time the current billing period started, if the provider has periods
+    pub period_start: Option<i64>,
+}
+
+// The parts of an upcoming invoice we show before a plan change
+#[derive(Debug, Clone, Deserialize)]
+pub struct InvoicePreview {
+    pub amount_due: i64,
+    pub currency: String,
+}
+
+// Everything the service needs from its payment provider. Ids are the provider's,
+// the caller looks them up on the organization.
+#[async_trait]
+pub trait BillingProvider: Send + Sync {
+    // Signs a new organization up for the free tier
+    async fn create_customer_with_subscription(
+        &self,
+        email: &str,
+        organization_name: &str,
+    ) -> Result<NewSubscription, BillingError>;
+
+    async fn get_subscription(&self, subscription_id: &str) -> Result<SubscriptionInfo, BillingError>;
+
+    // Adds `quantity` to the item's usage. Reports with an idempotency key that was
+    // already used are accepted without counting them again.
+    async fn report_usage(
+        &self,
+        subscription_item_id: &str,
+        quantity: i64,
+        timestamp: i64,
+        idempotency_key: &str,
+    ) -> Result<(), BillingError>;
+
+    // Usage of the item in the current billing period
+    async fn get_usage(&self, subscription_item_id: &str) -> Result<BilledUsage, BillingError>;
+
+    async fn get_price(&self, price_id: &str) -> Result<PriceInfo, BillingError>;
+
+    // The next invoice if the item moved to `price_id` now, prorations included
+    async fn preview_price_change(
+        &self,
+        customer_id: &str,
+        subscription_id: &str,
+        subscription_item_id: &str,
+        price_id: &str,
+    ) -> Result<InvoicePreview, BillingError>;
+
+    // Moves the item to another price and records the tier on the subscription. A change
+    // with an idempotency key that was already used isn't applied again.
+    async fn change_subscription_price(
+        &self,
+        subscription_id: &str,
+        subscription_item_id: &str,
+        price_id: &str,
+        tier: &str,
+        idempotency_key: &str,
+    ) -> Result<(), BillingError>;
+
+    // A hosted page to start a subscription, returns its URL
+    async fn create_checkout_session(
+        &self,
+        customer_id: &str,
+        price_id: &str,
+        tier: &str,
+        success_url: &str,
+        cancel_url: &str,
+    ) -> Result<String, BillingError>;
+
+    // A hosted page for cards and invoices, returns its URL
+    async fn create_billing_portal_session(
+        &self,
+        customer_id: &str,
+        return_url: &str,
+    ) -> Result<String, BillingError>;
+}
+
+// `BILLING_PROVIDER` picks the implementation: `stripe` (the default) needs the
+// Stripe env vars, `memory` keeps everything in process for development and CI
+pub fn billing_provider_from_env() -> Result<Arc<dyn BillingProvider>, BillingError> {
+    let provider = std::env::var("BILLING_PROVIDER").unwrap_or_else(|_| "stripe".to_string());
+
+    match provider.trim().to_ascii_lowercase().as_str() {
+        "stripe" => Ok(Arc::new(StripeClient::new()?)),
+        "memory" => {
+            tracing::warn!("Using the in-memory billing provider, nothing is billed");
+            Ok(Arc::new(InMemoryBilling::default()))
+        }
+        other => Err(BillingError::Config(format!(
+            "Unknown BILLING_PROVIDER '{}', expected 'stripe' or 'memory'",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,245 @@
+use crate::config::billing::{
+    BilledUsage, BillingError, BillingProvider, InvoicePreview, NewSubscription, PriceInfo, SubscriptionInfo,
+};
+use axum::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Debug, Clone)]
+struct MemorySubscription {
+    customer_id: String,
+    item_id: String,
+    price_id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    next_id: u64,
+    subscriptions: HashMap<String, MemorySubscription>,
+    usage: HashMap<String, i64>,
+    idempotency_keys: HashSet<String>,
+}
+
+// Billing without a payment provider, for development and tests. Customers and
+// subscriptions only live as long as the process, prices are free and the hosted
+// pages send the user straight back.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryBilling {
+    state: Arc<Mutex<MemoryState>>,
+    // Makes every call fail like an outage would
+    unavailable: Arc<AtomicBool>,
+}
+
+impl InMemoryBilling {
+    #[cfg(test)]
+    pub fn set_unavailable(&self, unavailable: bool) {
+        self.unavailable.store(unavailable, Ordering::SeqCst);
+    }
+
+    // Adds a subscription that was created elsewhere, e.g. a test fixture
+    #[cfg(test)]
+    pub fn add_subscription(&self, customer_id: &str, subscription_id: &str, item_id: &str) {
+        self.lock().subscriptions.insert(
+            subscription_id.to_string(),
+            MemorySubscription {
+                customer_id: customer_id.to_string(),
+                item_id: item_id.to_string(),
+                price_id: None,
+            },
+        );
+    }
+
+    // The price the subscription is on, if it was ever changed
+    #[cfg(test)]
+    pub fn subscription_price(&self, subscription_id: &str) -> Option<String> {
+        self.lock()
+            .subscriptions
+            .get(subscription_id)
+            .and_then(|subscription| subscription.price_id.clone())
+    }
+
+    fn check_available(&self) -> Result<(), BillingError> {
+        if self.unavailable.load(Ordering::SeqCst) {
+            return Err(BillingError::Unavailable(
+                "in-memory billing is set to be unavailable".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl BillingProvider for InMemoryBilling {
+    async fn create_customer_with_subscription(
+        &self,
+        _email: &str,
+        _organization_name: &str,
+    ) -> Result<NewSubscription, BillingError> {
+        self.check_available()?;
+
+        let mut state = self.lock();
+        state.next_id += 1;
+        let id = state.next_id;
+        let subscription = NewSubscription {
+            customer_id: format!("cus_memory_{}", id),
+            subscription_id: format!("sub_memory_{}", id),
+            subscription_item_id: format!("si_memory_{}", id),
+        };
+
+        state.subscriptions.insert(
+            subscription.subscription_id.clone(),
+            MemorySubscription {
+                customer_id: subscription.customer_id.clone(),
+                item_id: subscription.subscription_item_id.clone(),
+                price_id: None,
+            },
+        );
+        Ok(subscription)
+    }
+
+    async fn get_subscription(&self, subscription_id: &str) -> Result<SubscriptionInfo, BillingError> {
+        self.check_available()?;
+
+        let state = self.lock();
+        let subscription = state
+            .subscriptions
+            .get(subscription_id)
+            .ok_or_else(|| BillingError::Rejected(format!("No subscription {}", subscription_id)))?;
+
+        Ok(SubscriptionInfo {
+            customer_id: subscription.customer_id.clone(),
+            status: "active".to_string(),
+        })
+    }
+
+    async fn report_usage(
+        &self,
+        subscription_item_id: &str,
+        quantity: i64,
+        _timestamp: i64,
+        idempotency_key: &str,
+    ) -> Result<(), BillingError> {
+        self.check_available()?;
+
+        let mut state = self.lock();
+        if state.idempotency_keys.insert(idempotency_key.to_string()) {
+            *state.usage.entry(subscription_item_id.to_string()).or_insert(0) += quantity;
+        }
+        Ok(())
+    }
+
+    // There are no billing periods, usage adds up for as long as the process runs
+    async fn get_usage(&self, subscription_item_id: &str) -> Result<BilledUsage, BillingError> {
+        self.check_available()?;
+
+        Ok(BilledUsage {
+            quantity: self.lock().usage.get(subscription_item_id).copied().unwrap_or(0),
+            period_start: None,
+        })
+    }
+
+    async fn get_price(&self, price_id: &str) -> Result<PriceInfo, BillingError> {
+        self.check_available()?;
+
+        Ok(PriceInfo {
+            id: price_id.to_string(),
+            unit_amount: Some(0),
+            unit_amount_decimal: None,
+            currency: Some("usd".to_string()),
+            interval: Some("month".to_string()),
+        })
+    }
+
+    async fn preview_price_change(
+        &self,
+        _customer_id: &str,
+        _subscription_id: &str,
+        _subscription_item_id: &str,
+        _price_id: &str,
+    ) -> Result<InvoicePreview, BillingError> {
+        self.check_available()?;
+
+        Ok(InvoicePreview {
+            amount_due: 0,
+            currency: "usd".to_string(),
+        })
+    }
+
+    async fn change_subscription_price(
+        &self,
+        subscription_id: &str,
+        subscription_item_id: &str,
+        price_id: &str,
+        _tier: &str,
+        idempotency_key: &str,
+    ) -> Result<(), BillingError> {
+        self.check_available()?;
+
+        let mut state = self.lock();
+        if !state.idempotency_keys.insert(idempotency_key.to_string()) {
+            return Ok(());
+        }
+        let subscription = state
+            .subscriptions
+            .get_mut(subscription_id)
+            .filter(|subscription| subscription.item_id == subscription_item_id)
+            .ok_or_else(|| BillingError::Rejected(format!("No subscription {}", subscription_id)))?;
+
+        subscription.price_id = Some(price_id.to_string());
+        Ok(())
+    }
+
+    async fn create_checkout_session(
+        &self,
+        _customer_id: &str,
+        _price_id: &str,
+        _tier: &str,
+        success_url: &str,
+        _cancel_url: &str,
+    ) -> Result<String, BillingError> {
+        self.check_available()?;
+        Ok(success_url.to_string())
+    }
+
+    async fn create_billing_portal_session(
+        &self,
+        _customer_id: &str,
+        return_url: &str,
+    ) -> Result<String, BillingError> {
+        self.check_available()?;
+        Ok(return_url.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_with_a_used_idempotency_key_are_not_counted_again() -> Result<(), BillingError> {
+        let billing = InMemoryBilling::default();
+
+        billing.report_usage("si_1", 3, 0, "report-1").await?;
+        billing.report_usage("si_1", 3, 0, "report-1").await?;
+        billing.report_usage("si_1", 2, 0, "report-2").await?;
+
+        assert_eq!(billing.get_usage("si_1").await?.quantity, 5);
+        assert_eq!(billing.get_usage("si_2").await?.quantity, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unavailable_billing_fails_with_a_retryable_error() {
+        let billing = InMemoryBilling::default();
+        billing.set_unavailable(true);
+
+        let result = billing.report_usage("si_1", 1, 0, "report-1").await;
+
+        assert!(result.is_err_and(|err| err.is_retryable()));
+    }
+}
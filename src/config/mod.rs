@@ -1,9 +1,16 @@
+pub mod api_key_pepper;
 pub mod app;
+pub mod billing;
 pub mod database;
+pub mod plans;
+mod memory_billing;
 mod redis;
 mod stripe;
 
+pub use api_key_pepper::ApiKeyPepper;
 pub use app::AppConfig;
 pub use database::Database;
 pub use redis::{RedisStore, RedisConfig};
+pub use billing::{billing_provider_from_env, BillingProvider};
+pub use memory_billing::InMemoryBilling;
 pub use stripe::StripeClient;
\ No newline at end of file
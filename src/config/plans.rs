@@ -0,0 +1,59 @@
+use crate::entities::sea_orm_active_enums::OrganizationTier;
+
+// What each tier allows. Organizations start on `Free`.
+pub struct Plan {
+    pub tier: OrganizationTier,
+    pub name: &'static str,
+    pub monthly_request_limit: i64,
+    // Env var holding the Stripe price id, plans without one can't be switched to
+    pub price_env: &'static str,
+}
+
+pub const PLANS: [Plan; 4] = [
+    Plan {
+        tier: OrganizationTier::Free,
+        name: "free",
+        monthly_request_limit: 5_000,
+        price_env: "STRIPE_PRICE_ID",
+    },
+    Plan {
+        tier: OrganizationTier::Basic,
+        name: "basic",
+        monthly_request_limit: 50_000,
+        price_env: "STRIPE_PRICE_ID_BASIC",
+    },
+    Plan {
+        tier: OrganizationTier::Pro,
+        name: "pro",
+        monthly_request_limit: 500_000,
+        price_env: "STRIPE_PRICE_ID_PRO",
+    },
+    Plan {
+        tier: OrganizationTier::Enterprise,
+        name: "enterprise",
+        monthly_request_limit: 5_000_000,
+        price_env: "STRIPE_PRICE_ID_ENTERPRISE",
+    },
+];
+
+impl Plan {
+    pub fn price_id(&self) -> Option<String> {
+        std::env::var(self.price_env)
+            .ok()
+            .filter(|price_id| !price_id.trim().is_empty())
+    }
+}
+
+pub fn plan_for_tier(tier: &OrganizationTier) -> &'static Plan {
+    match tier {
+        OrganizationTier::Free => &PLANS[0],
+        OrganizationTier::Basic => &PLANS[1],
+        OrganizationTier::Pro => &PLANS[2],
+        OrganizationTier::Enterprise => &PLANS[3],
+    }
+}
+
+// Matches the names used in Stripe metadata and lookup keys, e.g. `pro`
+pub fn plan_by_name(name: &str) -> Option<&'static Plan> {
+    PLANS.iter().find(|plan| plan.name.eq_ignore_ascii_case(name.trim()))
+}
@@ -2,6 +2,13 @@ use redis::{AsyncCommands, Client, RedisError, RedisResult};
 use std::env;
 use chrono::Timelike;
 use uuid::Uuid;
+use crate::entities::api_keys;
+
+// Short enough that changes to a key (e.g. its scopes) don't need an invalidation
+const API_KEY_CACHE_TTL_SECONDS: u64 = 60;
+// Sockets refresh their presence every 30 seconds, ones that stopped (e.g. their
+// instance crashed) stop counting after this
+const PRESENCE_TTL_SECONDS: i64 = 90;
 
 #[derive(Clone)]
 pub struct RedisConfig {
@@ -89,6 +96,81 @@ impl RedisStore {
         Ok(())
     }
 
+    /// Get a validated API key by its HMAC
+    pub async fn get_cached_api_key(&self, key_hmac: &str) -> Result<Option<api_keys::Model>, RedisError> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let cached: Option<String> = conn.get(Self::api_key_cache_key(key_hmac)).await?;
+
+        // An entry we can't read is treated like a miss and overwritten
+        Ok(cached.and_then(|cached| serde_json::from_str(&cached).ok()))
+    }
+
+    /// Cache a validated API key for a short time
+    pub async fn cache_api_key(&self, key_hmac: &str, key: &api_keys::Model) -> Result<(), RedisError> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let value = serde_json::to_string(key).map_err(|e| {
+            RedisError::from((redis::ErrorKind::TypeError, "Failed to serialize API key", e.to_string()))
+        })?;
+
+        let _: () = conn
+            .set_ex(Self::api_key_cache_key(key_hmac), value, API_KEY_CACHE_TTL_SECONDS)
+            .await?;
+        Ok(())
+    }
+
+    /// Drop a cached API key, e.g. after it was deleted
+    pub async fn invalidate_api_key(&self, key_hmac: &str) -> Result<(), RedisError> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let _: () = conn.del(Self::api_key_cache_key(key_hmac)).await?;
+        Ok(())
+    }
+
+    /// Mark a socket as connected to a room, returns the sockets connected to it on all instances
+    pub async fn refresh_presence(&self, room_id: &Uuid, socket_id: &Uuid) -> Result<i64, RedisError> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let key = Self::presence_key(room_id);
+        let now = chrono::Utc::now().timestamp();
+
+        let (count,): (i64,) = redis::pipe()
+            .atomic()
+            .zrembyscore(&key, "-inf", now - PRESENCE_TTL_SECONDS)
+            .ignore()
+            .zadd(&key, socket_id.to_string(), now)
+            .ignore()
+            .zcard(&key)
+            .expire(&key, PRESENCE_TTL_SECONDS)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(count)
+    }
+
+    /// Remove a socket from a room, returns the sockets still connected to it
+    pub async fn remove_presence(&self, room_id: &Uuid, socket_id: &Uuid) -> Result<i64, RedisError> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let key = Self::presence_key(room_id);
+        let now = chrono::Utc::now().timestamp();
+
+        let (count,): (i64,) = redis::pipe()
+            .atomic()
+            .zrem(&key, socket_id.to_string())
+            .ignore()
+            .zrembyscore(&key, "-inf", now - PRESENCE_TTL_SECONDS)
+            .ignore()
+            .zcard(&key)
+            .query_async(&mut conn)
+            .await?;
+        Ok(count)
+    }
+
+    fn presence_key(room_id: &Uuid) -> String {
+        format!("presence:{}", room_id)
+    }
+
+    fn api_key_cache_key(key_hmac: &str) -> String {
+        format!("api_key:{}", key_hmac)
+    }
+
     fn get_ttl_until_month_end() -> u64 {
         use chrono::{Datelike, Utc};
 
@@ -1,55 +1,114 @@
-use crate::entities::prelude::{OrganizationTiers, Organizations};
-use crate::utils::GeneralError;
-use chrono::Utc;
+use crate::config::billing::{
+    BilledUsage, BillingError, BillingProvider, InvoicePreview, NewSubscription, PriceInfo, SubscriptionInfo,
+};
+use axum::async_trait;
 use reqwest::Client as HttpClient;
-use sea_orm::{DatabaseConnection, DbErr, EntityTrait};
 use std::str::FromStr;
+use stripe::generated::billing::subscription::SubscriptionProrationBehavior;
 use stripe::{
-    Client, CreateCustomer, CreateSubscription, CreateSubscriptionItems, Customer, StripeError,
-    Subscription, SubscriptionId,
+    BillingPortalSession, CheckoutSession, CheckoutSessionMode, Client, CreateBillingPortalSession,
+    CreateCheckoutSession, CreateCheckoutSessionLineItems, CreateCheckoutSessionSubscriptionData,
+    CreateCustomer, CreateSubscription, CreateSubscriptionItems, Customer, CustomerId, ParseIdError,
+    Price, PriceId, RequestStrategy, StripeError, Subscription, SubscriptionId, UpdateSubscription,
+    UpdateSubscriptionItems,
 };
 use thiserror::Error;
-use uuid::Uuid;
 
 // Custom error type for Stripe operations
 #[derive(Debug, Error)]
 pub enum StripeClientError {
     #[error("Stripe error: {0}")]
     Stripe(#[from] StripeError),
-    #[error("Configuration error: {0}")]
+    #[error("HTTP error: {0}")]
     Http(#[from] reqwest::Error),
     #[error("JSON parsing error: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("Invalid Stripe id: {0}")]
+    InvalidId(#[from] ParseIdError),
+    #[error("Stripe API error ({status}): {body}")]
+    Api { status: u16, body: String },
 }
 
-#[derive(Clone)]
-pub struct StripeClient {
-    pub(crate) client: Client,
+// Conflicts are concurrent requests with the same idempotency key
+fn is_transient_status(status: u16) -> bool {
+    status == 409 || status == 429 || status >= 500
 }
 
-pub struct SubscriptionInfo {
-    pub id: String,
-    pub monthly_limit: i64,
-    pub status: stripe::SubscriptionStatus,
-    pub metadata: std::collections::HashMap<String, String>,
+impl From<StripeClientError> for BillingError {
+    fn from(err: StripeClientError) -> Self {
+        let transient = match &err {
+            StripeClientError::Stripe(StripeError::Stripe(request)) => {
+                is_transient_status(request.http_status)
+            }
+            StripeClientError::Stripe(StripeError::ClientError(_) | StripeError::Timeout) => true,
+            StripeClientError::Http(_) => true,
+            StripeClientError::Api { status, .. } => is_transient_status(*status),
+            _ => false,
+        };
+
+        if transient {
+            BillingError::Unavailable(err.to_string())
+        } else {
+            BillingError::Rejected(err.to_string())
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct StripeClient {
+    client: Client,
+    // Shared so calls over plain HTTP reuse connections
+    http: HttpClient,
+    secret_key: String,
+    // What new organizations subscribe to
+    signup_price_id: String,
 }
 
 impl StripeClient {
-    pub fn new() -> Result<Self, DbErr> {
-        let secret_key =
-            std::env::var("STRIPE_SECRET_KEY").expect("Missing STRIPE_SECRET_KEY in env");
+    pub fn new() -> Result<Self, BillingError> {
+        let secret_key = std::env::var("STRIPE_SECRET_KEY")
+            .map_err(|_| BillingError::Config("Missing STRIPE_SECRET_KEY in env".to_string()))?;
+        let signup_price_id = std::env::var("STRIPE_PRICE_ID")
+            .map_err(|_| BillingError::Config("Missing STRIPE_PRICE_ID in env".to_string()))?;
 
         Ok(Self {
             client: Client::new(secret_key.clone()),
+            http: HttpClient::new(),
+            secret_key,
+            signup_price_id,
         })
     }
 
-    /// Creates a customer and subscription (unchanged from your snippet)
-    pub async fn create_customer_with_subscription(
+    // Calls endpoints async-stripe doesn't cover
+    async fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, StripeClientError> {
+        let resp = request
+            .header("Authorization", format!("Bearer {}", self.secret_key))
+            .send()
+            .await?;
+
+        let status = resp.status();
+        let body = resp.text().await?;
+        if !status.is_success() {
+            return Err(StripeClientError::Api {
+                status: status.as_u16(),
+                body,
+            });
+        }
+
+        Ok(serde_json::from_str(&body)?)
+    }
+}
+
+#[async_trait]
+impl BillingProvider for StripeClient {
+    async fn create_customer_with_subscription(
         &self,
         email: &str,
         organization_name: &str,
-    ) -> Result<(String, String, String), StripeError> {
+    ) -> Result<NewSubscription, BillingError> {
         // Create customer
         let customer = Customer::create(
             &self.client,
@@ -63,235 +122,221 @@ impl StripeClient {
                 ..Default::default()
             },
         )
-        .await?;
+        .await
+        .map_err(StripeClientError::from)?;
 
         // Creates subscription for customer
-        let price_id = std::env::var("STRIPE_PRICE_ID").expect("Missing STRIPE_PRICE_ID in env");
         let mut params = CreateSubscription::new(customer.id.clone());
         params.items = Some(vec![CreateSubscriptionItems {
-            price: Some(price_id),
+            price: Some(self.signup_price_id.clone()),
             ..Default::default()
         }]);
         params.expand = &["items", "items.data.price.product", "schedule"];
 
-        let subscription = Subscription::create(&self.client, params).await?;
-        let subscription_item_id = subscription.items.data[0].id.clone();
-
-        Ok((
-            customer.id.to_string(),
-            subscription.id.to_string(),
-            subscription_item_id.to_string(),
-        ))
-    }
-
-    pub async fn get_subscription(
-        &self,
-        org_id: &Uuid,
-        db: &DatabaseConnection,
-    ) -> Result<SubscriptionInfo, GeneralError> {
-        // Get organization from database
-        let org = Organizations::find_by_id(*org_id)
-            .one(db)
+        let subscription = Subscription::create(&self.client, params)
             .await
-            .map_err(|e| GeneralError::Internal(format!("Database error: {}", e)))?
-            .ok_or_else(|| GeneralError::NotFound(format!("Organization {} not found", org_id)))?;
-
-        // Check if org has a subscription
-        let subscription_id = org.stripe_subscription_id.ok_or_else(|| {
-            GeneralError::BadRequest(format!(
-                "Organization {} has no active subscription",
-                org_id
-            ))
+            .map_err(StripeClientError::from)?;
+        let subscription_item = subscription.items.data.first().ok_or_else(|| {
+            BillingError::Rejected(format!("Subscription {} has no items", subscription.id))
         })?;
 
-        // Get subscription from Stripe
-        let subscription = match Subscription::retrieve(
-            &self.client,
-            &SubscriptionId::from_str(&subscription_id).map_err(|_| {
-                GeneralError::Internal("Invalid subscription ID format".to_string())
-            })?,
-            &["items.data.price.product"],
-        )
-        .await
-        {
-            Ok(sub) => sub,
-            Err(e) => {
-                tracing::error!("Stripe API error: {:?}", e);
-                return Err(GeneralError::Internal(
-                    "Failed to fetch subscription from Stripe".to_string(),
-                ));
-            }
-        };
+        Ok(NewSubscription {
+            customer_id: customer.id.to_string(),
+            subscription_id: subscription.id.to_string(),
+            subscription_item_id: subscription_item.id.to_string(),
+        })
+    }
 
-        // Get monthly limit from organization_tiers table
-        let org_tier = OrganizationTiers::find_by_id(*org_id)
-            .one(db)
+    async fn get_subscription(&self, subscription_id: &str) -> Result<SubscriptionInfo, BillingError> {
+        let subscription_id =
+            SubscriptionId::from_str(subscription_id).map_err(StripeClientError::from)?;
+        let subscription = Subscription::retrieve(&self.client, &subscription_id, &[])
             .await
-            .map_err(|e| GeneralError::Internal(format!("Database error: {}", e)))?
-            .ok_or_else(|| {
-                GeneralError::NotFound(format!("Organization tier {} not found", org_id))
-            })?;
+            .map_err(StripeClientError::from)?;
 
         Ok(SubscriptionInfo {
-            id: subscription.id.to_string(),
-            monthly_limit: org_tier.monthly_request_limit,
-            status: subscription.status,
-            metadata: subscription.metadata,
+            customer_id: subscription.customer.id().to_string(),
+            status: subscription.status.to_string(),
         })
     }
 
-    pub async fn report_api_usage(
+    // Stripe remembers idempotency keys for 24 hours, resending a report within that window is a no-op
+    async fn report_usage(
         &self,
-        stripe_subscription_item_id: &str,
-    ) -> Result<(), StripeClientError> {
-        tracing::info!(
-            "Reporting usage to Stripe for stripe_subscription_item_id {}",
-            stripe_subscription_item_id
-        );
-        // Handle env var error gracefully
-        let secret_key = match std::env::var("STRIPE_SECRET_KEY") {
-            Ok(key) => key,
-            Err(e) => {
-                tracing::error!("Failed to get STRIPE_SECRET_KEY: {}", e);
-                return Ok(());
-            }
-        };
-
-        let timestamp = Utc::now().timestamp().to_string();
-
+        subscription_item_id: &str,
+        quantity: i64,
+        timestamp: i64,
+        idempotency_key: &str,
+    ) -> Result<(), BillingError> {
+        let quantity = quantity.to_string();
+        let timestamp = timestamp.to_string();
         let params = [
             ("action", "increment"),
-            ("quantity", "1"),
+            ("quantity", quantity.as_str()),
             ("timestamp", timestamp.as_str()),
         ];
 
-        let http_client = HttpClient::new();
-        let resp = match http_client
-            .post(&format!(
+        let request = self
+            .http
+            .post(format!(
                 "https://api.stripe.com/v1/subscription_items/{}/usage_records",
-                stripe_subscription_item_id
+                subscription_item_id
             ))
-            .header("Authorization", format!("Bearer {}", secret_key))
-            .header("Content-Type", "application/x-www-form-urlencoded")
-            .form(&params)
-            .send()
-            .await
-        {
-            Ok(resp) => resp,
-            Err(e) => {
-                tracing::error!("Failed to send request to Stripe: {}", e);
-                return Ok(());
-            }
-        };
+            .header("Idempotency-Key", idempotency_key)
+            .form(&params);
+        self.send::<serde_json::Value>(request).await?;
 
-        let status = resp.status();
-        let response_text = match resp.text().await {
-            Ok(text) => text,
-            Err(e) => {
-                tracing::error!("Failed to read response text: {}", e);
-                return Ok(());
-            }
-        };
+        Ok(())
+    }
 
-        if !status.is_success() {
-            tracing::error!(
-                "Failed to create usage record for subscription item {}, status: {}, body: {}",
-                stripe_subscription_item_id,
-                status,
-                response_text
-            );
-            return Ok(());
-        }
+    async fn get_usage(&self, subscription_item_id: &str) -> Result<BilledUsage, BillingError> {
+        let request = self.http.get(format!(
+            "https://api.stripe.com/v1/subscription_items/{}/usage_record_summaries",
+            subscription_item_id
+        ));
+        let response: serde_json::Value = self.send(request).await?;
 
-        tracing::info!(
-            "Successfully created usage record for subscription item {}",
-            stripe_subscription_item_id
-        );
-        Ok(())
+        // The first summary is the current billing period
+        let summary = response
+            .get("data")
+            .and_then(|data| data.as_array())
+            .and_then(|arr| arr.first());
+
+        Ok(BilledUsage {
+            quantity: summary
+                .and_then(|first| first.get("total_usage"))
+                .and_then(|usage| usage.as_i64())
+                .unwrap_or(0),
+            period_start: summary
+                .and_then(|first| first.get("period"))
+                .and_then(|period| period.get("start"))
+                .and_then(|start| start.as_i64()),
+        })
     }
 
-    pub async fn get_subscription_usage(&self, db: &DatabaseConnection, org_id: &Uuid) -> i64 {
-        let org = match Organizations::find_by_id(*org_id).one(db).await {
-            Ok(Some(org)) => org,
-            Ok(None) => {
-                tracing::error!("Organization not found: {}", org_id);
-                return 0;
-            }
-            Err(e) => {
-                tracing::error!("Database error: {}", e);
-                return 0;
-            }
-        };
+    async fn get_price(&self, price_id: &str) -> Result<PriceInfo, BillingError> {
+        let price_id = PriceId::from_str(price_id).map_err(StripeClientError::from)?;
+        let price = Price::retrieve(&self.client, &price_id, &[])
+            .await
+            .map_err(StripeClientError::from)?;
 
-        let Some(subscription_item_id) = org.stripe_subscription_item_id else {
-            tracing::error!("No subscription item ID found for org: {}", org_id);
-            return 0;
-        };
+        Ok(PriceInfo {
+            id: price.id.to_string(),
+            unit_amount: price.unit_amount,
+            unit_amount_decimal: price.unit_amount_decimal,
+            currency: price.currency.map(|currency| currency.to_string()),
+            interval: price.recurring.map(|recurring| recurring.interval.to_string()),
+        })
+    }
 
-        tracing::info!(
-            "Fetching usage from Stripe for subscription_item_id {}",
-            subscription_item_id
-        );
+    // async-stripe only knows the deprecated upcoming invoice endpoint, so this goes over HTTP
+    async fn preview_price_change(
+        &self,
+        customer_id: &str,
+        subscription_id: &str,
+        subscription_item_id: &str,
+        price_id: &str,
+    ) -> Result<InvoicePreview, BillingError> {
+        let params = [
+            ("customer", customer_id),
+            ("subscription", subscription_id),
+            ("subscription_details[items][0][id]", subscription_item_id),
+            ("subscription_details[items][0][price]", price_id),
+            ("subscription_details[proration_behavior]", "create_prorations"),
+        ];
 
-        let secret_key = match std::env::var("STRIPE_SECRET_KEY") {
-            Ok(key) => key,
-            Err(e) => {
-                tracing::error!("Failed to get STRIPE_SECRET_KEY: {}", e);
-                return 0;
-            }
-        };
+        let request = self
+            .http
+            .post("https://api.stripe.com/v1/invoices/create_preview")
+            .form(&params);
+        Ok(self.send(request).await?)
+    }
 
-        let http_client = HttpClient::new();
-        let resp = match http_client
-            .get(&format!(
-                "https://api.stripe.com/v1/subscription_items/{}/usage_record_summaries",
-                subscription_item_id
-            ))
-            .header("Authorization", format!("Bearer {}", secret_key))
-            .send()
+    // The tier goes into the subscription's metadata so the webhooks agree with us,
+    // and any custom limit is dropped
+    async fn change_subscription_price(
+        &self,
+        subscription_id: &str,
+        subscription_item_id: &str,
+        price_id: &str,
+        tier: &str,
+        idempotency_key: &str,
+    ) -> Result<(), BillingError> {
+        let subscription_id =
+            SubscriptionId::from_str(subscription_id).map_err(StripeClientError::from)?;
+
+        let mut params = UpdateSubscription::new();
+        params.items = Some(vec![UpdateSubscriptionItems {
+            id: Some(subscription_item_id.to_string()),
+            price: Some(price_id.to_string()),
+            ..Default::default()
+        }]);
+        params.metadata = Some(std::collections::HashMap::from([
+            (String::from("tier"), tier.to_string()),
+            // Stripe removes keys set to an empty string
+            (String::from("monthly_request_limit"), String::new()),
+        ]));
+        params.proration_behavior = Some(SubscriptionProrationBehavior::CreateProrations);
+
+        let client = self
+            .client
+            .clone()
+            .with_strategy(RequestStrategy::Idempotent(idempotency_key.to_string()));
+        Subscription::update(&client, &subscription_id, params)
             .await
-        {
-            Ok(resp) => resp,
-            Err(e) => {
-                tracing::error!("Failed to send request to Stripe: {}", e);
-                return 0;
-            }
-        };
+            .map_err(StripeClientError::from)?;
+        Ok(())
+    }
 
-        let status = resp.status();
-        let response_text = match resp.text().await {
-            Ok(text) => text,
-            Err(e) => {
-                tracing::error!("Failed to read response text: {}", e);
-                return 0;
-            }
-        };
+    // For organizations without a subscription, e.g. after cancelling. Stripe collects the card
+    // and the webhooks pick up the new subscription.
+    async fn create_checkout_session(
+        &self,
+        customer_id: &str,
+        price_id: &str,
+        tier: &str,
+        success_url: &str,
+        cancel_url: &str,
+    ) -> Result<String, BillingError> {
+        let mut params = CreateCheckoutSession::new();
+        params.customer = Some(CustomerId::from_str(customer_id).map_err(StripeClientError::from)?);
+        params.mode = Some(CheckoutSessionMode::Subscription);
+        params.success_url = Some(success_url);
+        params.cancel_url = Some(cancel_url);
+        // Metered prices take no quantity
+        params.line_items = Some(vec![CreateCheckoutSessionLineItems {
+            price: Some(price_id.to_string()),
+            ..Default::default()
+        }]);
+        params.subscription_data = Some(CreateCheckoutSessionSubscriptionData {
+            metadata: Some(std::collections::HashMap::from([(
+                String::from("tier"),
+                tier.to_string(),
+            )])),
+            ..Default::default()
+        });
 
-        if !status.is_success() {
-            tracing::error!(
-                "Failed to fetch usage records, status: {}, body: {}",
-                status,
-                response_text
-            );
-            return 0;
-        }
+        let session = CheckoutSession::create(&self.client, params)
+            .await
+            .map_err(StripeClientError::from)?;
+        session
+            .url
+            .ok_or_else(|| BillingError::Rejected("Checkout session has no URL".to_string()))
+    }
 
-        // Parse the response
-        let response = match serde_json::from_str::<serde_json::Value>(&response_text) {
-            Ok(r) => r,
-            Err(e) => {
-                tracing::error!("Failed to parse Stripe response: {}", e);
-                return 0;
-            }
-        };
+    // Stripe's hosted page for cards, invoices and cancelling
+    async fn create_billing_portal_session(
+        &self,
+        customer_id: &str,
+        return_url: &str,
+    ) -> Result<String, BillingError> {
+        let customer_id = CustomerId::from_str(customer_id).map_err(StripeClientError::from)?;
+        let mut params = CreateBillingPortalSession::new(customer_id);
+        params.return_url = Some(return_url);
 
-        // Get usage value
-        response
-            .get("data")
-            .and_then(|data| data.as_array())
-            .and_then(|arr| arr.first())
-            .and_then(|first| first.get("total_usage"))
-            .and_then(|usage| usage.as_i64())
-            .unwrap_or(0)
+        let session = BillingPortalSession::create(&self.client, params)
+            .await
+            .map_err(StripeClientError::from)?;
+        Ok(session.url)
     }
 }
@@ -12,7 +12,7 @@ pub struct Model {
     pub organization_id: Uuid,
     pub name: String,
     #[sea_orm(unique)]
-    pub key: String,
+    pub key: Option<String>,
     pub key_type: ApiKeyType,
     pub created_by_user_id: Uuid,
     pub last_used_at: Option<DateTime>,
@@ -20,10 +20,23 @@ pub struct Model {
     pub created_at: DateTime,
     pub updated_at: DateTime,
     pub key_prefix: String,
+    pub scopes: Option<Vec<String>>,
+    pub channel_ids: Option<Vec<Uuid>>,
+    #[sea_orm(unique)]
+    pub key_hmac: Option<String>,
+    #[sea_orm(unique)]
+    pub previous_key_hmac: Option<String>,
+    pub previous_key_expires_at: Option<DateTime>,
+    pub previous_key_last_used_at: Option<DateTime>,
+    pub revoked_at: Option<DateTime>,
+    pub allowed_ips: Option<Vec<String>>,
+    pub allowed_origins: Option<Vec<String>>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::api_key_audit_events::Entity")]
+    ApiKeyAuditEvents,
     #[sea_orm(has_many = "super::api_key_usage::Entity")]
     ApiKeyUsage,
     #[sea_orm(
@@ -44,6 +57,12 @@ pub enum Relation {
     Users,
 }
 
+impl Related<super::api_key_audit_events::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ApiKeyAuditEvents.def()
+    }
+}
+
 impl Related<super::api_key_usage::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::ApiKeyUsage.def()
@@ -12,10 +12,13 @@ pub struct Model {
     pub created_at: DateTime,
     pub updated_at: DateTime,
     pub organization_id: Uuid,
+    pub is_private: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::channel_participant::Entity")]
+    ChannelParticipant,
     #[sea_orm(has_many = "super::messages::Entity")]
     Messages,
     #[sea_orm(
@@ -28,6 +31,12 @@ pub enum Relation {
     Organizations,
 }
 
+impl Related<super::channel_participant::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ChannelParticipant.def()
+    }
+}
+
 impl Related<super::messages::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Messages.def()
@@ -13,10 +13,17 @@ pub struct Model {
     pub participant_id: Uuid,
     pub created_at: DateTime,
     pub updated_at: DateTime,
+    pub edited_at: Option<DateTime>,
+    pub deleted_at: Option<DateTime>,
+    pub parent_message_id: Option<Uuid>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::message_reactions::Entity")]
+    MessageReactions,
+    #[sea_orm(has_many = "super::message_revisions::Entity")]
+    MessageRevisions,
     #[sea_orm(
         belongs_to = "super::channels::Entity",
         from = "Column::ChannelId",
@@ -33,6 +40,26 @@ pub enum Relation {
         on_delete = "NoAction"
     )]
     Participant,
+    #[sea_orm(
+        belongs_to = "Entity",
+        from = "Column::ParentMessageId",
+        to = "Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    SelfRef,
+}
+
+impl Related<super::message_reactions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MessageReactions.def()
+    }
+}
+
+impl Related<super::message_revisions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MessageRevisions.def()
+    }
 }
 
 impl Related<super::channels::Entity> for Entity {
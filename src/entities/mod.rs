@@ -2,14 +2,20 @@
 
 pub mod prelude;
 
+pub mod api_key_audit_events;
 pub mod api_key_usage;
 pub mod api_keys;
 pub mod channel_participant;
 pub mod channels;
+pub mod message_reactions;
+pub mod message_revisions;
 pub mod messages;
 pub mod organization_members;
 pub mod organization_tiers;
 pub mod organizations;
 pub mod participant;
 pub mod sea_orm_active_enums;
+pub mod stripe_usage_counters;
+pub mod stripe_usage_reports;
+pub mod stripe_webhook_events;
 pub mod users;
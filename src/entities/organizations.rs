@@ -14,10 +14,15 @@ pub struct Model {
     pub stripe_customer_id: Option<String>,
     pub stripe_subscription_id: Option<String>,
     pub stripe_subscription_item_id: Option<String>,
+    pub max_api_key_lifetime_days: Option<i32>,
+    pub stripe_subscription_status: Option<String>,
+    pub stripe_event_created_at: Option<DateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::api_key_audit_events::Entity")]
+    ApiKeyAuditEvents,
     #[sea_orm(has_many = "super::api_keys::Entity")]
     ApiKeys,
     #[sea_orm(has_many = "super::channels::Entity")]
@@ -26,6 +31,18 @@ pub enum Relation {
     OrganizationMembers,
     #[sea_orm(has_one = "super::organization_tiers::Entity")]
     OrganizationTiers,
+    #[sea_orm(has_many = "super::participant::Entity")]
+    Participant,
+    #[sea_orm(has_many = "super::stripe_usage_counters::Entity")]
+    StripeUsageCounters,
+    #[sea_orm(has_many = "super::stripe_usage_reports::Entity")]
+    StripeUsageReports,
+}
+
+impl Related<super::api_key_audit_events::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ApiKeyAuditEvents.def()
+    }
 }
 
 impl Related<super::api_keys::Entity> for Entity {
@@ -52,6 +69,24 @@ impl Related<super::organization_tiers::Entity> for Entity {
     }
 }
 
+impl Related<super::participant::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Participant.def()
+    }
+}
+
+impl Related<super::stripe_usage_counters::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::StripeUsageCounters.def()
+    }
+}
+
+impl Related<super::stripe_usage_reports::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::StripeUsageReports.def()
+    }
+}
+
 impl Related<super::users::Entity> for Entity {
     fn to() -> RelationDef {
         super::organization_members::Relation::Users.def()
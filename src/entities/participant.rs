@@ -11,12 +11,43 @@ pub struct Model {
     pub name: String,
     pub created_at: DateTime,
     pub updated_at: DateTime,
+    pub organization_id: Uuid,
+    pub external_id: Option<String>,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub metadata: Option<Json>,
+    pub deleted_at: Option<DateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::channel_participant::Entity")]
+    ChannelParticipant,
+    #[sea_orm(has_many = "super::message_reactions::Entity")]
+    MessageReactions,
     #[sea_orm(has_many = "super::messages::Entity")]
     Messages,
+    #[sea_orm(
+        belongs_to = "super::organizations::Entity",
+        from = "Column::OrganizationId",
+        to = "super::organizations::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Organizations,
+}
+
+impl Related<super::channel_participant::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ChannelParticipant.def()
+    }
+}
+
+impl Related<super::message_reactions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MessageReactions.def()
+    }
 }
 
 impl Related<super::messages::Entity> for Entity {
@@ -25,4 +56,10 @@ impl Related<super::messages::Entity> for Entity {
     }
 }
 
+impl Related<super::organizations::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Organizations.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
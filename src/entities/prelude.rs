@@ -1,12 +1,18 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.2
 
+pub use super::api_key_audit_events::Entity as ApiKeyAuditEvents;
 pub use super::api_key_usage::Entity as ApiKeyUsage;
 pub use super::api_keys::Entity as ApiKeys;
 pub use super::channel_participant::Entity as ChannelParticipant;
 pub use super::channels::Entity as Channels;
+pub use super::message_reactions::Entity as MessageReactions;
+pub use super::message_revisions::Entity as MessageRevisions;
 pub use super::messages::Entity as Messages;
 pub use super::organization_members::Entity as OrganizationMembers;
 pub use super::organization_tiers::Entity as OrganizationTiers;
 pub use super::organizations::Entity as Organizations;
 pub use super::participant::Entity as Participant;
+pub use super::stripe_usage_counters::Entity as StripeUsageCounters;
+pub use super::stripe_usage_reports::Entity as StripeUsageReports;
+pub use super::stripe_webhook_events::Entity as StripeWebhookEvents;
 pub use super::users::Entity as Users;
@@ -0,0 +1,173 @@
+use super::participants::find_org_participant;
+use crate::entities::{channel_participant, channels, prelude::*};
+use crate::middleware::api_key_authorizer::{
+    ApiKeyAuthorizer, ManageChannels, ReadChannels, ReadOnlyAccess, ReadWriteAccess,
+};
+use crate::state::AppState;
+use crate::utils::ServerResponse;
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use sea_orm::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct AddChannelMemberRequest {
+    participant_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChannelMemberResponse {
+    participant_id: Uuid,
+    name: String,
+    joined_at: chrono::DateTime<Utc>,
+}
+
+// Public channels are open to every participant, private ones only to their members
+pub(crate) async fn can_access_channel(
+    db: &DatabaseConnection,
+    channel: &channels::Model,
+    participant_id: Uuid,
+) -> Result<bool, DbErr> {
+    if !channel.is_private {
+        return Ok(true);
+    }
+
+    let membership = ChannelParticipant::find_by_id((channel.id, participant_id))
+        .one(db)
+        .await?;
+
+    Ok(membership.is_some())
+}
+
+async fn find_channel(
+    db: &DatabaseConnection,
+    organization_id: Uuid,
+    channel_id: Uuid,
+) -> Result<channels::Model, Response> {
+    match Channels::find_by_id(channel_id)
+        .filter(channels::Column::OrganizationId.eq(organization_id))
+        .one(db)
+        .await
+    {
+        Ok(Some(channel)) => Ok(channel),
+        Ok(None) => Err(ServerResponse::not_found("Channel not found")),
+        Err(err) => Err(ServerResponse::server_error(err, "Failed to fetch channel")),
+    }
+}
+
+pub async fn add_channel_member(
+    State(state): State<AppState>,
+    auth: ApiKeyAuthorizer<ReadWriteAccess, ManageChannels>,
+    Path((organization_id, channel_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<AddChannelMemberRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    if !auth.allows_channel(&channel_id) {
+        return ServerResponse::forbidden("Credentials are not valid for this channel");
+    }
+
+    let channel = match find_channel(db, organization_id, channel_id).await {
+        Ok(channel) => channel,
+        Err(response) => return response,
+    };
+
+    let participant = match find_org_participant(db, organization_id, payload.participant_id).await {
+        Ok(Some(participant)) => participant,
+        Ok(None) => return ServerResponse::bad_request("Participant not found in this organization"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to check participant"),
+    };
+
+    match ChannelParticipant::find_by_id((channel.id, participant.id)).one(db).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return ServerResponse::bad_request("Participant is already a member of this channel"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to check membership"),
+    }
+
+    let now = Utc::now().naive_utc();
+    let membership = channel_participant::ActiveModel {
+        channel_id: Set(channel.id),
+        participant_id: Set(participant.id),
+        created_at: Set(now),
+        updated_at: Set(now),
+    };
+
+    match membership.insert(db).await {
+        Ok(membership) => ServerResponse::created(ChannelMemberResponse {
+            participant_id: participant.id,
+            name: participant.name,
+            joined_at: membership.created_at.and_utc(),
+        }),
+        Err(err) => ServerResponse::server_error(err, "Failed to add channel member"),
+    }
+}
+
+pub async fn remove_channel_member(
+    State(state): State<AppState>,
+    auth: ApiKeyAuthorizer<ReadWriteAccess, ManageChannels>,
+    Path((organization_id, channel_id, participant_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    if !auth.allows_channel(&channel_id) {
+        return ServerResponse::forbidden("Credentials are not valid for this channel");
+    }
+
+    let channel = match find_channel(db, organization_id, channel_id).await {
+        Ok(channel) => channel,
+        Err(response) => return response,
+    };
+
+    match ChannelParticipant::delete_by_id((channel.id, participant_id))
+        .exec(db)
+        .await
+    {
+        Ok(result) if result.rows_affected == 0 => ServerResponse::not_found("Channel member not found"),
+        Ok(_) => ServerResponse::ok(()),
+        Err(err) => ServerResponse::server_error(err, "Failed to remove channel member"),
+    }
+}
+
+pub async fn get_channel_members(
+    State(state): State<AppState>,
+    auth: ApiKeyAuthorizer<ReadOnlyAccess, ReadChannels>,
+    Path((organization_id, channel_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    if !auth.allows_channel(&channel_id) {
+        return ServerResponse::forbidden("Credentials are not valid for this channel");
+    }
+
+    let channel = match find_channel(db, organization_id, channel_id).await {
+        Ok(channel) => channel,
+        Err(response) => return response,
+    };
+
+    match ChannelParticipant::find()
+        .filter(channel_participant::Column::ChannelId.eq(channel.id))
+        .find_also_related(Participant)
+        .order_by_asc(channel_participant::Column::CreatedAt)
+        .all(db)
+        .await
+    {
+        Ok(members) => ServerResponse::ok(
+            members
+                .into_iter()
+                .filter_map(|(membership, participant)| {
+                    participant.map(|participant| ChannelMemberResponse {
+                        participant_id: participant.id,
+                        name: participant.name,
+                        joined_at: membership.created_at.and_utc(),
+                    })
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(err) => ServerResponse::server_error(err, "Failed to fetch channel members"),
+    }
+}
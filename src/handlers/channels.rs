@@ -11,27 +11,38 @@ use crate::entities::prelude::Channels;
 use sea_orm::*;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use crate::middleware::api_key_authorizer::ApiKeyAuthorizer;
+use crate::middleware::api_key_authorizer::{
+    ApiKeyAuthorizer, ManageChannels, ReadChannels, ReadOnlyAccess, ReadWriteAccess,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct CreateChannelRequest {
     name: String,
+    // Private channels can only be read and posted to by their members
+    #[serde(default)]
+    is_private: bool,
 }
 
 #[derive(Debug, Serialize)]
 pub struct CreateChannelResponse {
     id: Uuid,
     name: String,
+    is_private: bool,
 }
 
 pub async fn create_channel(
     State(state): State<AppState>,
-    _: ApiKeyAuthorizer,
+    auth: ApiKeyAuthorizer<ReadWriteAccess, ManageChannels>,
     Path(organization_id): Path<Uuid>,
     Json(payload): Json<CreateChannelRequest>,
 ) -> impl IntoResponse {
     let db = &state.db.connection;
 
+    // The new channel would be outside of the key's channels right away
+    if auth.channel_ids.is_some() {
+        return ServerResponse::forbidden("API keys restricted to channels can't create channels");
+    }
+
     match Channels::find()
         .filter(channels::Column::Name.eq(&payload.name))
         .filter(channels::Column::OrganizationId.eq(organization_id))
@@ -49,12 +60,14 @@ pub async fn create_channel(
         created_at: Set(chrono::Utc::now().naive_utc()),
         updated_at: Set(chrono::Utc::now().naive_utc()),
         organization_id: Set(organization_id),
+        is_private: Set(payload.is_private),
     };
 
     match Channels::insert(new_channel).exec(db).await {
         Ok(channel) => ServerResponse::ok(CreateChannelResponse {
             id: channel.last_insert_id,
             name: payload.name,
+            is_private: payload.is_private,
         }),
         Err(err) => ServerResponse::server_error(err, "Failed to create channel"),
     }
@@ -62,11 +75,15 @@ pub async fn create_channel(
 
 pub async fn get_channel_by_id(
     state: State<AppState>,
-    _: ApiKeyAuthorizer,
+    auth: ApiKeyAuthorizer<ReadOnlyAccess, ReadChannels>,
     Path((organization_id, channel_id)): Path<(Uuid, Uuid)>,
 ) -> impl IntoResponse {
     let db = &state.db.connection;
 
+    if !auth.allows_channel(&channel_id) {
+        return ServerResponse::forbidden("Credentials are not valid for this channel");
+    }
+
     match Channels::find_by_id(channel_id)
         .filter(channels::Column::OrganizationId.eq(organization_id))
         .one(db)
@@ -76,6 +93,7 @@ pub async fn get_channel_by_id(
             let response = CreateChannelResponse {
                 id: channel.id,
                 name: channel.name,
+                is_private: channel.is_private,
             };
             ServerResponse::ok(response)
         }
@@ -86,11 +104,15 @@ pub async fn get_channel_by_id(
 
 pub async fn get_channels(
     State(state): State<AppState>,
-    _: ApiKeyAuthorizer,
+    auth: ApiKeyAuthorizer<ReadOnlyAccess, ReadChannels>,
     Path(organization_id): Path<Uuid>,
 ) -> impl IntoResponse {
-    match Channels::find()
-        .filter(channels::Column::OrganizationId.eq(organization_id))
+    let mut query = Channels::find().filter(channels::Column::OrganizationId.eq(organization_id));
+    if let Some(channel_ids) = auth.channel_ids {
+        query = query.filter(channels::Column::Id.is_in(channel_ids));
+    }
+
+    match query
         .order_by_desc(channels::Column::CreatedAt)
         .all(&state.db.connection)
         .await
@@ -101,6 +123,7 @@ pub async fn get_channels(
                 .map(|channel| CreateChannelResponse {
                     id: channel.id,
                     name: channel.name,
+                    is_private: channel.is_private,
                 })
                 .collect::<Vec<_>>(),
         ),
@@ -1,16 +1,25 @@
-use crate::entities::{channels, messages, organization_members, prelude::*};
-use crate::middleware::api_key_authorizer::ApiKeyAuthorizer;
+use super::channel_members::can_access_channel;
+use super::participants::find_org_participant;
+use super::reactions::{fetch_reaction_counts, ReactionCount};
+use crate::entities::{channels, message_revisions, messages, organization_members, prelude::*};
 use crate::middleware::authorization::AuthorizedOrganizationUser;
+use crate::middleware::api_key_authorizer::{
+    ReadMessages, ReadOnlyAccess, ReadWriteAccess, WriteMessages,
+};
+use crate::middleware::client_authorizer::{ClientAuthorizer, ClientCredential};
 use crate::middleware::usage_limiter::UsageLimiter;
 use crate::middleware::usage_tracker::UsageTracker;
+use crate::realtime::{publish_event, MessageDeletedPayload, MessagePayload, ServerEvent};
 use crate::state::AppState;
 use crate::utils::ServerResponse;
-use axum::{extract::Path, Json};
-use axum::{extract::State, response::IntoResponse};
+use axum::{extract::Path, extract::Query, Json};
+use axum::{extract::State, response::IntoResponse, response::Response};
+use sea_orm::sea_query::Expr;
 use sea_orm::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use uuid::Uuid;
-use chrono::{Datelike, Timelike, Utc};
+use chrono::{Datelike, NaiveDateTime, Timelike, Utc};
 
 #[derive(Debug, Serialize)]
 pub struct CreateMessageResponse {
@@ -18,6 +27,91 @@ pub struct CreateMessageResponse {
     content: String,
     participant_id: Uuid,
     channel_id: Uuid,
+    created_at: chrono::DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edited_at: Option<chrono::DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_message_id: Option<Uuid>,
+    // Only set for deleted thread parents, which stay listed without their content
+    // while they have replies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<chrono::DateTime<Utc>>,
+}
+
+impl From<messages::Model> for CreateMessageResponse {
+    fn from(message: messages::Model) -> Self {
+        let content = match message.deleted_at {
+            Some(_) => String::new(),
+            None => message.content,
+        };
+
+        Self {
+            id: message.id,
+            content,
+            participant_id: message.participant_id,
+            channel_id: message.channel_id,
+            created_at: message.created_at.and_utc(),
+            edited_at: message.edited_at.map(|edited_at| edited_at.and_utc()),
+            parent_message_id: message.parent_message_id,
+            deleted_at: message.deleted_at.map(|deleted_at| deleted_at.and_utc()),
+        }
+    }
+}
+
+// Top level message as returned by the channel listing, together with a
+// summary of its thread
+#[derive(Debug, Serialize)]
+pub struct ChannelMessageResponse {
+    #[serde(flatten)]
+    message: CreateMessageResponse,
+    reply_count: i64,
+    last_reply_at: Option<chrono::DateTime<Utc>>,
+    reactions: Vec<ReactionCount>,
+}
+
+const DEFAULT_MESSAGES_PAGE_SIZE: u64 = 50;
+const MAX_MESSAGES_PAGE_SIZE: u64 = 200;
+
+// Whether the message in the outer query still has replies that aren't deleted
+const HAS_LIVE_REPLIES: &str = r#"EXISTS (
+    SELECT 1 FROM "messages" AS "replies"
+    WHERE "replies"."parent_message_id" = "messages"."id" AND "replies"."deleted_at" IS NULL
+)"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageOrder {
+    NewestFirst,
+    OldestFirst,
+}
+
+// `before` pages backwards (newest first) and `after` pages forwards (oldest first).
+// Both accept a message id or an RFC 3339 timestamp. `order` only applies when no
+// cursor is given and defaults to newest first for the initial load.
+#[derive(Debug, Deserialize)]
+pub struct MessagesQuery {
+    before: Option<String>,
+    after: Option<String>,
+    limit: Option<u64>,
+    order: Option<MessageOrder>,
+}
+
+enum MessageCursor {
+    Message { created_at: NaiveDateTime, id: Uuid },
+    Timestamp(NaiveDateTime),
+}
+
+struct MessagePage {
+    messages: Vec<messages::Model>,
+    next_cursor: Option<String>,
+    prev_cursor: Option<String>,
+}
+
+#[derive(Debug, FromQueryResult)]
+struct ThreadSummary {
+    parent_message_id: Uuid,
+    reply_count: i64,
+    last_reply_at: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Serialize)]
@@ -29,19 +123,31 @@ pub struct CountMessageResponse {
 #[derive(Debug, Deserialize)]
 pub struct CreateMessageRequest {
     content: String,
-    participant_id: Uuid,
+    // Required for API keys, participant tokens always post as themselves
+    participant_id: Option<Uuid>,
     channel_name: String,
+    // Posts the message as a reply in the thread of this message
+    parent_message_id: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateMessageRequest {
+    content: String,
 }
 
 pub async fn create_message(
     State(state): State<AppState>,
-    _: ApiKeyAuthorizer,
+    auth: ClientAuthorizer<ReadWriteAccess, WriteMessages>,
     _: UsageTracker,
     _: UsageLimiter,
-    Path(org_id): Path<Uuid>,
     Json(payload): Json<CreateMessageRequest>,
 ) -> impl IntoResponse {
     let db = &state.db.connection;
+    let org_id = auth.organization_id;
+
+    let Some(participant_id) = auth.credential.participant_id().or(payload.participant_id) else {
+        return ServerResponse::bad_request("participant_id is required");
+    };
 
     let channel = match Channels::find()
         .filter(channels::Column::Name.eq(&payload.channel_name))
@@ -54,34 +160,269 @@ pub async fn create_message(
         Err(err) => return ServerResponse::server_error(err, "Failed to check channel"),
     };
 
+    if !auth.credential.allows_channel(&channel.id) {
+        return ServerResponse::forbidden("Credentials are not valid for this channel");
+    }
+
+    match find_org_participant(db, org_id, participant_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return ServerResponse::bad_request("Participant not found in this organization"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to check participant"),
+    }
+
+    if let Some(parent_message_id) = payload.parent_message_id {
+        match is_valid_thread_parent(db, channel.id, parent_message_id).await {
+            Ok(true) => {}
+            Ok(false) => return ServerResponse::bad_request("Parent message not found in this channel"),
+            Err(err) => return ServerResponse::server_error(err, "Failed to check parent message"),
+        }
+    }
+
+    match can_access_channel(db, &channel, participant_id).await {
+        Ok(true) => {}
+        Ok(false) => return ServerResponse::forbidden("Participant is not a member of this channel"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to check channel membership"),
+    }
+
+    match persist_message(
+        &state,
+        channel.id,
+        participant_id,
+        payload.content,
+        payload.parent_message_id,
+    )
+    .await
+    {
+        Ok(message) => ServerResponse::created(CreateMessageResponse::from(message)),
+        Err(err) => ServerResponse::server_error(err, "Failed to create message"),
+    }
+}
+
+// Threads are one level deep: replies must point at a live top level message
+// of the same channel
+pub(crate) async fn is_valid_thread_parent(
+    db: &DatabaseConnection,
+    channel_id: Uuid,
+    parent_message_id: Uuid,
+) -> Result<bool, DbErr> {
+    let parent = Messages::find_by_id(parent_message_id)
+        .filter(messages::Column::ChannelId.eq(channel_id))
+        .filter(messages::Column::ParentMessageId.is_null())
+        .filter(messages::Column::DeletedAt.is_null())
+        .one(db)
+        .await?;
+
+    Ok(parent.is_some())
+}
+
+// Single write path for chat messages, used by both the REST endpoint and the
+// WebSocket handler so that every message ends up in the `messages` table and
+// produces the same `message.created` event for live subscribers.
+// Callers validate `parent_message_id` with `is_valid_thread_parent` first.
+pub(crate) async fn persist_message(
+    state: &AppState,
+    channel_id: Uuid,
+    participant_id: Uuid,
+    content: String,
+    parent_message_id: Option<Uuid>,
+) -> Result<messages::Model, DbErr> {
+    let now = Utc::now().naive_utc();
+
     let new_message = messages::ActiveModel {
         id: Set(Uuid::new_v4()),
-        content: Set(payload.content.clone()),
-        participant_id: Set(payload.participant_id),
-        channel_id: Set(channel.id),
-        created_at: Set(chrono::Utc::now().naive_utc()),
-        updated_at: Set(chrono::Utc::now().naive_utc()),
+        content: Set(content),
+        participant_id: Set(participant_id),
+        channel_id: Set(channel_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        edited_at: Set(None),
+        deleted_at: Set(None),
+        parent_message_id: Set(parent_message_id),
     };
 
-    match Messages::insert(new_message).exec(db).await {
-        Ok(message) => {
-            let response = CreateMessageResponse {
-                id: message.last_insert_id,
-                content: payload.content,
-                participant_id: payload.participant_id,
-                channel_id: channel.id,
-            };
-            ServerResponse::created(response)
+    let message = new_message.insert(&state.db.connection).await?;
+
+    let event = ServerEvent::MessageCreated(MessagePayload::from(message.clone()));
+    if let Err(e) = publish_event(&state.redis, &channel_id, &event).await {
+        tracing::error!("Failed to publish message {} to live subscribers: {}", message.id, e);
+    }
+
+    Ok(message)
+}
+
+async fn resolve_cursor(
+    db: &DatabaseConnection,
+    channel_id: Uuid,
+    raw: &str,
+) -> Result<MessageCursor, Response> {
+    if let Ok(message_id) = Uuid::parse_str(raw) {
+        return match Messages::find_by_id(message_id)
+            .filter(messages::Column::ChannelId.eq(channel_id))
+            .one(db)
+            .await
+        {
+            Ok(Some(message)) => Ok(MessageCursor::Message {
+                created_at: message.created_at,
+                id: message.id,
+            }),
+            Ok(None) => Err(ServerResponse::bad_request("Cursor message not found in this channel")),
+            Err(err) => Err(ServerResponse::server_error(err, "Failed to resolve cursor")),
+        };
+    }
+
+    chrono::DateTime::parse_from_rfc3339(raw)
+        .map(|timestamp| MessageCursor::Timestamp(timestamp.naive_utc()))
+        .map_err(|_| ServerResponse::bad_request("Cursor must be a message ID or an RFC 3339 timestamp"))
+}
+
+// Keyset condition on (created_at, id) so that messages sharing a timestamp
+// are neither skipped nor repeated across pages
+fn cursor_condition(cursor: &MessageCursor, order: MessageOrder) -> Condition {
+    let (created_at, id) = match cursor {
+        MessageCursor::Message { created_at, id } => (*created_at, Some(*id)),
+        MessageCursor::Timestamp(created_at) => (*created_at, None),
+    };
+
+    let (past_timestamp, same_timestamp) = match order {
+        MessageOrder::NewestFirst => (
+            messages::Column::CreatedAt.lt(created_at),
+            id.map(|id| messages::Column::Id.lt(id)),
+        ),
+        MessageOrder::OldestFirst => (
+            messages::Column::CreatedAt.gt(created_at),
+            id.map(|id| messages::Column::Id.gt(id)),
+        ),
+    };
+
+    match same_timestamp {
+        Some(same_timestamp) => Condition::any().add(past_timestamp).add(
+            Condition::all()
+                .add(messages::Column::CreatedAt.eq(created_at))
+                .add(same_timestamp),
+        ),
+        None => Condition::all().add(past_timestamp),
+    }
+}
+
+// Applies the cursor, order and limit from `params` to `query`. `default_order`
+// is used when neither `before` nor `after` is given and no `order` was requested.
+async fn fetch_message_page(
+    db: &DatabaseConnection,
+    mut query: Select<Messages>,
+    channel_id: Uuid,
+    params: &MessagesQuery,
+    default_order: MessageOrder,
+) -> Result<MessagePage, Response> {
+    let (order, raw_cursor) = match (&params.before, &params.after) {
+        (Some(_), Some(_)) => {
+            return Err(ServerResponse::bad_request("Use either `before` or `after`, not both"))
         }
-        Err(err) => ServerResponse::server_error(err, "Failed to create message"),
+        (Some(before), None) => (MessageOrder::NewestFirst, Some(before)),
+        (None, Some(after)) => (MessageOrder::OldestFirst, Some(after)),
+        (None, None) => (params.order.unwrap_or(default_order), None),
+    };
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_MESSAGES_PAGE_SIZE)
+        .clamp(1, MAX_MESSAGES_PAGE_SIZE);
+
+    if let Some(raw_cursor) = raw_cursor {
+        let cursor = resolve_cursor(db, channel_id, raw_cursor).await?;
+        query = query.filter(cursor_condition(&cursor, order));
     }
+
+    query = match order {
+        MessageOrder::NewestFirst => query
+            .order_by_desc(messages::Column::CreatedAt)
+            .order_by_desc(messages::Column::Id),
+        MessageOrder::OldestFirst => query
+            .order_by_asc(messages::Column::CreatedAt)
+            .order_by_asc(messages::Column::Id),
+    };
+
+    // One extra row tells us whether there is another page without a COUNT query
+    let mut messages = query
+        .limit(limit + 1)
+        .all(db)
+        .await
+        .map_err(|err| ServerResponse::server_error(err, "Failed to fetch messages"))?;
+
+    let has_more = messages.len() as u64 > limit;
+    messages.truncate(limit as usize);
+
+    // `next_cursor` continues in the same direction, `prev_cursor` goes back the
+    // other way, e.g. `after=prev_cursor` to catch up on a newest first page
+    let next_cursor = messages
+        .last()
+        .filter(|_| has_more)
+        .map(|message| message.id.to_string());
+    let prev_cursor = messages.first().map(|message| message.id.to_string());
+
+    Ok(MessagePage {
+        messages,
+        next_cursor,
+        prev_cursor,
+    })
 }
 
+// Reply count and latest reply per thread, in a single grouped query
+async fn fetch_thread_summaries(
+    db: &DatabaseConnection,
+    parent_ids: Vec<Uuid>,
+) -> Result<HashMap<Uuid, ThreadSummary>, DbErr> {
+    if parent_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let summaries = Messages::find()
+        .select_only()
+        .column(messages::Column::ParentMessageId)
+        .column_as(messages::Column::Id.count(), "reply_count")
+        .column_as(messages::Column::CreatedAt.max(), "last_reply_at")
+        .filter(messages::Column::ParentMessageId.is_in(parent_ids))
+        .filter(messages::Column::DeletedAt.is_null())
+        .group_by(messages::Column::ParentMessageId)
+        .into_model::<ThreadSummary>()
+        .all(db)
+        .await?;
+
+    Ok(summaries
+        .into_iter()
+        .map(|summary| (summary.parent_message_id, summary))
+        .collect())
+}
+
+// Keys and tokens may only read the channels they are restricted to. Participants
+// also need to be a member of private channels, API keys act for the whole organization.
+async fn ensure_can_read(
+    db: &DatabaseConnection,
+    credential: &ClientCredential,
+    channel: &channels::Model,
+) -> Result<(), Response> {
+    if !credential.allows_channel(&channel.id) {
+        return Err(ServerResponse::forbidden("Credentials are not valid for this channel"));
+    }
+
+    let Some(participant_id) = credential.participant_id() else {
+        return Ok(());
+    };
+
+    match can_access_channel(db, channel, participant_id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(ServerResponse::forbidden("Participant is not a member of this channel")),
+        Err(err) => Err(ServerResponse::server_error(err, "Failed to check channel membership")),
+    }
+}
+
+// Lists the top level messages of a channel, replies are fetched per thread
+// through `get_message_replies`
 pub async fn get_messages_by_channel_id(
     State(state): State<AppState>,
-    _: ApiKeyAuthorizer,
+    auth: ClientAuthorizer<ReadOnlyAccess, ReadMessages>,
     _: UsageLimiter,
     Path((org_id, channel_id)): Path<(String, String)>,
+    Query(params): Query<MessagesQuery>,
 ) -> impl IntoResponse {
     let db = &state.db.connection;
     let channel_id = match Uuid::parse_str(&channel_id) {
@@ -105,28 +446,227 @@ pub async fn get_messages_by_channel_id(
         Err(err) => return ServerResponse::server_error(err, "Failed to verify channel"),
     };
 
-    match Messages::find()
+    if let Err(response) = ensure_can_read(db, &auth.credential, &channel).await {
+        return response;
+    }
+
+    // Deleted messages with replies stay as a tombstone to reach the thread through
+    let query = Messages::find()
         .filter(messages::Column::ChannelId.eq(channel.id))
-        .order_by_asc(messages::Column::CreatedAt)
-        .all(db)
+        .filter(messages::Column::ParentMessageId.is_null())
+        .filter(
+            Condition::any()
+                .add(messages::Column::DeletedAt.is_null())
+                .add(Expr::cust(HAS_LIVE_REPLIES)),
+        );
+
+    let page = match fetch_message_page(db, query, channel.id, &params, MessageOrder::NewestFirst).await {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let message_ids = page.messages.iter().map(|message| message.id).collect::<Vec<_>>();
+    let mut summaries = match fetch_thread_summaries(db, message_ids.clone()).await {
+        Ok(summaries) => summaries,
+        Err(err) => return ServerResponse::server_error(err, "Failed to fetch thread summaries"),
+    };
+    let mut reactions = match fetch_reaction_counts(db, message_ids).await {
+        Ok(reactions) => reactions,
+        Err(err) => return ServerResponse::server_error(err, "Failed to fetch reactions"),
+    };
+
+    let messages = page
+        .messages
+        .into_iter()
+        .map(|message| {
+            let summary = summaries.remove(&message.id);
+            ChannelMessageResponse {
+                reply_count: summary.as_ref().map_or(0, |summary| summary.reply_count),
+                last_reply_at: summary
+                    .and_then(|summary| summary.last_reply_at)
+                    .map(|last_reply_at| last_reply_at.and_utc()),
+                reactions: reactions.remove(&message.id).unwrap_or_default(),
+                message: CreateMessageResponse::from(message),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    ServerResponse::paginated(messages, page.next_cursor, page.prev_cursor)
+}
+
+// Replies of a thread, oldest first unless a cursor or `order` says otherwise
+pub async fn get_message_replies(
+    State(state): State<AppState>,
+    auth: ClientAuthorizer<ReadOnlyAccess, ReadMessages>,
+    _: UsageLimiter,
+    Path((_, message_id)): Path<(Uuid, Uuid)>,
+    Query(params): Query<MessagesQuery>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    // Deleted parents keep their thread readable
+    let parent = match find_org_message(auth.organization_id, message_id).one(db).await {
+        Ok(Some(message)) => message,
+        Ok(None) => return ServerResponse::not_found("Message not found"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to fetch message"),
+    };
+
+    let channel = match Channels::find_by_id(parent.channel_id).one(db).await {
+        Ok(Some(channel)) => channel,
+        Ok(None) => return ServerResponse::not_found("Channel not found"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to fetch channel"),
+    };
+
+    if let Err(response) = ensure_can_read(db, &auth.credential, &channel).await {
+        return response;
+    }
+
+    let query = Messages::find()
+        .filter(messages::Column::ParentMessageId.eq(parent.id))
+        .filter(messages::Column::DeletedAt.is_null());
+
+    match fetch_message_page(db, query, parent.channel_id, &params, MessageOrder::OldestFirst).await {
+        Ok(page) => {
+            let replies = page
+                .messages
+                .into_iter()
+                .map(CreateMessageResponse::from)
+                .collect::<Vec<_>>();
+            ServerResponse::paginated(replies, page.next_cursor, page.prev_cursor)
+        }
+        Err(response) => response,
+    }
+}
+
+// Looks up a message that hasn't been deleted yet, scoped to the organization
+// through its channel
+fn find_org_message(org_id: Uuid, message_id: Uuid) -> Select<Messages> {
+    Messages::find_by_id(message_id)
+        .join(JoinType::InnerJoin, messages::Relation::Channels.def())
+        .filter(channels::Column::OrganizationId.eq(org_id))
+}
+
+pub(crate) async fn find_live_message(
+    db: &DatabaseConnection,
+    org_id: Uuid,
+    message_id: Uuid,
+) -> Result<messages::Model, Response> {
+    match find_org_message(org_id, message_id)
+        .filter(messages::Column::DeletedAt.is_null())
+        .one(db)
         .await
     {
-        Ok(messages) => {
-            let messages = messages
-                .into_iter()
-                .map(|message| CreateMessageResponse {
-                    id: message.id,
-                    content: message.content,
-                    participant_id: message.participant_id,
-                    channel_id: message.channel_id,
+        Ok(Some(message)) => Ok(message),
+        Ok(None) => Err(ServerResponse::not_found("Message not found")),
+        Err(err) => Err(ServerResponse::server_error(err, "Failed to fetch message")),
+    }
+}
+
+// Only the author may change their own message. Integrations may change any
+// message in their channels, the extractor already made sure their key may write.
+fn can_modify_message(credential: &ClientCredential, message: &messages::Model) -> bool {
+    let is_author = match credential {
+        ClientCredential::Participant { participant_id, .. } => *participant_id == message.participant_id,
+        ClientCredential::ApiKey { .. } => true,
+    };
+
+    is_author && credential.allows_channel(&message.channel_id)
+}
+
+pub async fn update_message(
+    State(state): State<AppState>,
+    auth: ClientAuthorizer<ReadWriteAccess, WriteMessages>,
+    Path((_, message_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<UpdateMessageRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    let message = match find_live_message(db, auth.organization_id, message_id).await {
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+
+    if !can_modify_message(&auth.credential, &message) {
+        return ServerResponse::forbidden("Not allowed to edit this message");
+    }
+
+    let now = Utc::now().naive_utc();
+    let edited_by = auth.credential.participant_id();
+
+    // The previous content is kept as a revision, so the history survives the edit
+    let result = db
+        .transaction::<_, messages::Model, DbErr>(|txn| {
+            Box::pin(async move {
+                MessageRevisions::insert(message_revisions::ActiveModel {
+                    id: Set(Uuid::new_v4()),
+                    message_id: Set(message.id),
+                    content: Set(message.content.clone()),
+                    edited_by_participant_id: Set(edited_by),
+                    created_at: Set(now),
                 })
-                .collect::<Vec<_>>();
-            ServerResponse::ok(messages)
+                .exec(txn)
+                .await?;
+
+                let mut active: messages::ActiveModel = message.into();
+                active.content = Set(payload.content);
+                active.edited_at = Set(Some(now));
+                active.updated_at = Set(now);
+                active.update(txn).await
+            })
+        })
+        .await;
+
+    match result {
+        Ok(message) => {
+            let event = ServerEvent::MessageUpdated(MessagePayload::from(message.clone()));
+            if let Err(e) = publish_event(&state.redis, &message.channel_id, &event).await {
+                tracing::error!("Failed to publish update of message {}: {}", message.id, e);
+            }
+
+            ServerResponse::ok(CreateMessageResponse::from(message))
         }
-        Err(err) => ServerResponse::server_error(err, "Failed to fetch messages"),
+        Err(err) => ServerResponse::server_error(err, "Failed to update message"),
     }
 }
 
+// Soft delete, the row stays around as a tombstone together with its revisions
+pub async fn delete_message(
+    State(state): State<AppState>,
+    auth: ClientAuthorizer<ReadWriteAccess, WriteMessages>,
+    Path((_, message_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    let message = match find_live_message(db, auth.organization_id, message_id).await {
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+
+    if !can_modify_message(&auth.credential, &message) {
+        return ServerResponse::forbidden("Not allowed to delete this message");
+    }
+
+    let now = Utc::now().naive_utc();
+    let mut active: messages::ActiveModel = message.into();
+    active.deleted_at = Set(Some(now));
+    active.updated_at = Set(now);
+
+    match active.update(db).await {
+        Ok(message) => {
+            let event = ServerEvent::MessageDeleted(MessageDeletedPayload {
+                message_id: message.id,
+                channel_id: message.channel_id,
+                deleted_at: now.and_utc(),
+            });
+            if let Err(e) = publish_event(&state.redis, &message.channel_id, &event).await {
+                tracing::error!("Failed to publish deletion of message {}: {}", message.id, e);
+            }
+
+            ServerResponse::ok(())
+        }
+        Err(err) => ServerResponse::server_error(err, "Failed to delete message"),
+    }
+}
 
 pub async fn get_messages_count_for_current_month(
     State(state): State<AppState>,
@@ -1,26 +1,55 @@
 mod health;
 mod sockets;
 mod channels;
+mod channel_members;
 mod participants;
+mod participant_tokens;
 mod messages;
+mod reactions;
+mod search;
 mod organization_accounts;
 mod organizations;
+mod organization_settings;
+mod organization_billing;
+mod stripe_webhooks;
+mod usage_reconciliation;
 
 pub use health::health_check;
 pub use sockets::chat_ws_handler;
+#[cfg(test)]
+pub(crate) use sockets::check_access;
 
 
 pub use channels::create_channel;
 pub use channels::get_channel_by_id;
 pub use channels::get_channels;
 
+pub use channel_members::add_channel_member;
+pub use channel_members::get_channel_members;
+pub use channel_members::remove_channel_member;
+
 pub use participants::create_participant;
 pub use participants::get_participants_count;
+pub use participants::get_participants;
+pub use participants::get_participant;
+pub use participants::update_participant;
+pub use participants::delete_participant;
+pub use participants::get_participant_by_external_id;
+pub use participants::upsert_participant_by_external_id;
+pub use participant_tokens::create_participant_token;
 
 pub use messages::create_message;
+pub use messages::update_message;
+pub use messages::delete_message;
 pub use messages::get_messages_by_channel_id;
+pub use messages::get_message_replies;
 pub use messages::get_messages_count_for_current_month;
 
+pub use reactions::add_reaction;
+pub use reactions::remove_reaction;
+
+pub use search::search_messages;
+
 pub use organization_accounts::create_user_and_organization;
 pub use organization_accounts::sign_in;
 
@@ -30,4 +59,22 @@ pub use organizations::get_users_in_org_count;
 pub use organizations::get_api_key_count;
 pub use organizations::get_api_keys;
 pub use organizations::delete_api_key;
-pub use organizations::create_api_key;
\ No newline at end of file
+pub use organizations::rotate_api_key;
+pub use organizations::update_api_key;
+pub use organizations::create_api_key;
+pub use organizations::get_api_keys_usage;
+pub use organizations::get_api_key_usage;
+
+pub use organization_settings::get_organization_settings;
+pub use organization_settings::update_organization_settings;
+
+pub use organization_billing::get_billing_plans;
+pub use organization_billing::preview_tier_change;
+pub use organization_billing::change_tier;
+pub use organization_billing::create_checkout_session;
+pub use organization_billing::create_billing_portal_session;
+pub use organization_billing::get_organization_usage;
+
+pub use stripe_webhooks::stripe_webhook;
+
+pub use usage_reconciliation::{get_usage_reconciliation, retry_failed_stripe_reports};
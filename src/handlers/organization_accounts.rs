@@ -110,7 +110,7 @@ pub async fn create_user_and_organization(
     tracing::info!("executes: create_user");
 
     let db = &state.db.connection;
-    let stripe = &state.stripe;
+    let billing = &state.billing;
     let email = payload.email;
     let organization_name = payload.organization_name;
     let password_hash = match hash_password_and_salt(&payload.password) {
@@ -139,11 +139,11 @@ pub async fn create_user_and_organization(
     let org_id = Uuid::new_v4();
     let now = Utc::now().naive_utc();
 
-    let (customer_id, subscription_id, subscription_item_id) = match stripe
+    let subscription = match billing
         .create_customer_with_subscription(&email, &organization_name)
         .await
     {
-        Ok(ids) => ids,
+        Ok(subscription) => subscription,
         Err(err) => {
             let _ = txn.rollback().await;
             return ServerResponse::server_error(err, "Failed to create Stripe customer");
@@ -156,9 +156,13 @@ pub async fn create_user_and_organization(
         name: Set(organization_name),
         created_at: Set(now),
         updated_at: Set(now),
-        stripe_customer_id: Set(Some(customer_id)),
-        stripe_subscription_id: Set(Some(subscription_id)),
-        stripe_subscription_item_id: Set(Some(subscription_item_id)),
+        stripe_customer_id: Set(Some(subscription.customer_id)),
+        stripe_subscription_id: Set(Some(subscription.subscription_id)),
+        stripe_subscription_item_id: Set(Some(subscription.subscription_item_id)),
+        max_api_key_lifetime_days: Set(None),
+        // Filled in by the Stripe webhooks
+        stripe_subscription_status: Set(None),
+        stripe_event_created_at: Set(None),
     };
 
     if let Err(err) = Organizations::insert(new_org).exec(&txn).await {
@@ -0,0 +1,431 @@
+use crate::config::billing::{PriceInfo, ENDED_SUBSCRIPTION_STATUSES};
+use crate::config::plans::{plan_by_name, plan_for_tier, Plan, PLANS};
+use crate::entities::prelude::{OrganizationTiers, Organizations};
+use crate::entities::{organization_tiers, organizations};
+use crate::middleware::api_key_authorizer::{ApiKeyAuthorizer, ReadOnlyAccess, ReadUsage};
+use crate::middleware::authorization::OrganizationOwner;
+use crate::state::AppState;
+use crate::utils::ServerResponse;
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::Utc;
+use sea_orm::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use uuid::Uuid;
+
+// Switching changes the existing subscription, organizations without one go through checkout
+const NO_SUBSCRIPTION: &str = "Organization has no subscription, start one through checkout";
+
+#[derive(Debug, Serialize)]
+pub struct PlanResponse {
+    tier: &'static str,
+    monthly_request_limit: i64,
+    // Has a Stripe price, so owners can switch to it themselves
+    available: bool,
+    current: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BillingPlansResponse {
+    current_tier: &'static str,
+    monthly_request_limit: i64,
+    current_month_usage: i64,
+    subscription_status: Option<String>,
+    plans: Vec<PlanResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeTierRequest {
+    tier: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PriceResponse {
+    price_id: String,
+    unit_amount: Option<i64>,
+    unit_amount_decimal: Option<String>,
+    currency: Option<String>,
+    interval: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TierPreviewResponse {
+    current_tier: &'static str,
+    new_tier: &'static str,
+    current_monthly_request_limit: i64,
+    new_monthly_request_limit: i64,
+    current_month_usage: i64,
+    // Downgrading below this month's usage blocks requests until the month resets
+    usage_exceeds_new_limit: bool,
+    current_price: Option<PriceResponse>,
+    new_price: PriceResponse,
+    // The next invoice with the change applied, prorations included
+    amount_due: i64,
+    amount_due_currency: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrganizationTierResponse {
+    tier: &'static str,
+    monthly_request_limit: i64,
+    current_month_usage: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckoutSessionRequest {
+    tier: String,
+    success_url: String,
+    cancel_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BillingPortalSessionRequest {
+    return_url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BillingSessionResponse {
+    url: String,
+}
+
+impl From<organization_tiers::Model> for OrganizationTierResponse {
+    fn from(tier: organization_tiers::Model) -> Self {
+        Self {
+            tier: plan_for_tier(&tier.tier).name,
+            monthly_request_limit: tier.monthly_request_limit,
+            current_month_usage: tier.current_month_usage,
+        }
+    }
+}
+
+impl From<PriceInfo> for PriceResponse {
+    fn from(price: PriceInfo) -> Self {
+        Self {
+            price_id: price.id,
+            unit_amount: price.unit_amount,
+            unit_amount_decimal: price.unit_amount_decimal,
+            currency: price.currency,
+            interval: price.interval,
+        }
+    }
+}
+
+// Stripe redirects the owner here, so only absolute http(s) URLs
+fn validate_redirect_url(field: &str, value: &str) -> Result<(), String> {
+    match Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => Ok(()),
+        _ => Err(format!("{} must be an absolute http(s) URL", field)),
+    }
+}
+
+async fn find_organization_with_tier(
+    db: &DatabaseConnection,
+    organization_id: Uuid,
+) -> Result<(organizations::Model, organization_tiers::Model), Response> {
+    let organization = match Organizations::find_by_id(organization_id).one(db).await {
+        Ok(Some(organization)) => organization,
+        Ok(None) => return Err(ServerResponse::not_found("Organization not found")),
+        Err(err) => return Err(ServerResponse::server_error(err, "Failed to fetch organization")),
+    };
+
+    match OrganizationTiers::find_by_id(organization_id).one(db).await {
+        Ok(Some(tier)) => Ok((organization, tier)),
+        Ok(None) => Err(ServerResponse::not_found("Organization tier not found")),
+        Err(err) => Err(ServerResponse::server_error(err, "Failed to fetch organization tier")),
+    }
+}
+
+// The plan the owner asked for and its Stripe price
+fn requested_plan(
+    requested: &str,
+    current: &organization_tiers::Model,
+) -> Result<(&'static Plan, String), String> {
+    let Some(plan) = plan_by_name(requested) else {
+        return Err(format!("Unknown tier '{}'", requested));
+    };
+
+    if plan.tier == current.tier {
+        return Err(format!("Organization is already on the {} tier", plan.name));
+    }
+
+    match plan.price_id() {
+        Some(price_id) => Ok((plan, price_id)),
+        None => Err(format!(
+            "The {} tier can't be switched to, please contact us",
+            plan.name
+        )),
+    }
+}
+
+// Customer, subscription and item ids, switching needs all of them
+fn subscription_ids(organization: &organizations::Model) -> Option<(&str, &str, &str)> {
+    Some((
+        organization.stripe_customer_id.as_deref()?,
+        organization.stripe_subscription_id.as_deref()?,
+        organization.stripe_subscription_item_id.as_deref()?,
+    ))
+}
+
+pub async fn get_billing_plans(
+    State(state): State<AppState>,
+    auth: OrganizationOwner,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    let (organization, tier) = match find_organization_with_tier(db, auth.0.organization_id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    let plans = PLANS
+        .iter()
+        .map(|plan| PlanResponse {
+            tier: plan.name,
+            monthly_request_limit: plan.monthly_request_limit,
+            available: plan.price_id().is_some(),
+            current: plan.tier == tier.tier,
+        })
+        .collect();
+
+    ServerResponse::ok(BillingPlansResponse {
+        current_tier: plan_for_tier(&tier.tier).name,
+        monthly_request_limit: tier.monthly_request_limit,
+        current_month_usage: tier.current_month_usage,
+        subscription_status: organization.stripe_subscription_status,
+        plans,
+    })
+}
+
+pub async fn preview_tier_change(
+    State(state): State<AppState>,
+    auth: OrganizationOwner,
+    Json(payload): Json<ChangeTierRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    let (organization, tier) = match find_organization_with_tier(db, auth.0.organization_id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let (plan, price_id) = match requested_plan(&payload.tier, &tier) {
+        Ok(requested) => requested,
+        Err(detail) => return ServerResponse::bad_request(detail),
+    };
+    let (customer_id, subscription_id, item_id) = match subscription_ids(&organization) {
+        Some(ids) => ids,
+        None => return ServerResponse::bad_request(NO_SUBSCRIPTION),
+    };
+
+    let new_price = match state.billing.get_price(&price_id).await {
+        Ok(price) => PriceResponse::from(price),
+        Err(err) => return ServerResponse::server_error(err, "Failed to fetch the price from Stripe"),
+    };
+
+    // Only informative, the current subscription may be on an older price
+    let current_plan = plan_for_tier(&tier.tier);
+    let current_price = match current_plan.price_id() {
+        Some(current_price_id) => match state.billing.get_price(&current_price_id).await {
+            Ok(price) => Some(PriceResponse::from(price)),
+            Err(err) => {
+                tracing::warn!("Failed to fetch price {}: {}", current_price_id, err);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let invoice = match state
+        .billing
+        .preview_price_change(customer_id, subscription_id, item_id, &price_id)
+        .await
+    {
+        Ok(invoice) => invoice,
+        Err(err) => return ServerResponse::server_error(err, "Failed to preview the invoice"),
+    };
+
+    ServerResponse::ok(TierPreviewResponse {
+        current_tier: current_plan.name,
+        new_tier: plan.name,
+        current_monthly_request_limit: tier.monthly_request_limit,
+        new_monthly_request_limit: plan.monthly_request_limit,
+        current_month_usage: tier.current_month_usage,
+        usage_exceeds_new_limit: tier.current_month_usage > plan.monthly_request_limit,
+        current_price,
+        new_price,
+        amount_due: invoice.amount_due,
+        amount_due_currency: invoice.currency,
+    })
+}
+
+// Stripe is changed first and the tier only after it accepted the new price. Nothing
+// is locked during the call, the subscription webhook settles concurrent switches.
+pub async fn change_tier(
+    State(state): State<AppState>,
+    auth: OrganizationOwner,
+    Json(payload): Json<ChangeTierRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+    let organization_id = auth.0.organization_id;
+
+    let (organization, tier) = match find_organization_with_tier(db, organization_id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let (customer_id, subscription_id, item_id) = match subscription_ids(&organization) {
+        Some(ids) => ids,
+        None => return ServerResponse::bad_request(NO_SUBSCRIPTION),
+    };
+
+    // Our ids may be stale if a webhook hasn't arrived yet
+    match state.billing.get_subscription(subscription_id).await {
+        Ok(subscription) if subscription.customer_id != customer_id => {
+            return ServerResponse::bad_request("Subscription belongs to another customer");
+        }
+        Ok(subscription) if ENDED_SUBSCRIPTION_STATUSES.contains(&subscription.status.as_str()) => {
+            return ServerResponse::bad_request(NO_SUBSCRIPTION);
+        }
+        Ok(_) => {}
+        Err(err) => return ServerResponse::server_error(err, "Failed to fetch the subscription"),
+    }
+
+    let (plan, price_id) = match requested_plan(&payload.tier, &tier) {
+        Ok(requested) => requested,
+        Err(detail) => return ServerResponse::bad_request(detail),
+    };
+
+    // Retrying a switch whose tier update failed reuses the key, Stripe answers it
+    // without changing the subscription again
+    let idempotency_key = format!(
+        "tier-{}-{}-{}",
+        organization_id,
+        tier.updated_at.and_utc().timestamp_micros(),
+        plan.name
+    );
+    if let Err(err) = state
+        .billing
+        .change_subscription_price(subscription_id, item_id, &price_id, plan.name, &idempotency_key)
+        .await
+    {
+        return ServerResponse::server_error(err, "Failed to change the subscription in Stripe");
+    }
+
+    let updated = organization_tiers::ActiveModel {
+        organization_id: Set(organization_id),
+        tier: Set(plan.tier.clone()),
+        monthly_request_limit: Set(plan.monthly_request_limit),
+        updated_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .update(db)
+    .await;
+    let updated = match updated {
+        Ok(updated) => updated,
+        // Stripe already moved, its subscription webhook carries the tier and brings us in line
+        Err(err) => {
+            return ServerResponse::server_error(
+                err,
+                "The subscription was changed in Stripe but the tier wasn't updated yet, retry or wait for it to catch up",
+            )
+        }
+    };
+
+    tracing::info!("Organization {} switched to the {} tier", organization_id, plan.name);
+    ServerResponse::ok(OrganizationTierResponse::from(updated))
+}
+
+// The tier, its limit and this month's usage, for backends that watch their quota
+pub async fn get_organization_usage(
+    State(state): State<AppState>,
+    _: ApiKeyAuthorizer<ReadOnlyAccess, ReadUsage>,
+    Path(organization_id): Path<Uuid>,
+) -> impl IntoResponse {
+    match OrganizationTiers::find_by_id(organization_id)
+        .one(&state.db.connection)
+        .await
+    {
+        Ok(Some(tier)) => ServerResponse::ok(OrganizationTierResponse::from(tier)),
+        Ok(None) => ServerResponse::not_found("Organization tier not found"),
+        Err(err) => ServerResponse::server_error(err, "Failed to fetch organization tier"),
+    }
+}
+
+pub async fn create_checkout_session(
+    State(state): State<AppState>,
+    auth: OrganizationOwner,
+    Json(payload): Json<CheckoutSessionRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    if let Err(detail) = validate_redirect_url("success_url", &payload.success_url)
+        .and_then(|_| validate_redirect_url("cancel_url", &payload.cancel_url))
+    {
+        return ServerResponse::bad_request(detail);
+    }
+
+    let (organization, tier) = match find_organization_with_tier(db, auth.0.organization_id).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+
+    // A second subscription would be billed alongside the first one
+    if organization.stripe_subscription_id.is_some() {
+        return ServerResponse::bad_request(
+            "Organization already has a subscription, change its tier instead",
+        );
+    }
+    let Some(customer_id) = organization.stripe_customer_id.as_deref() else {
+        return ServerResponse::bad_request("Organization has no Stripe customer");
+    };
+
+    let (plan, price_id) = match requested_plan(&payload.tier, &tier) {
+        Ok(requested) => requested,
+        Err(detail) => return ServerResponse::bad_request(detail),
+    };
+
+    match state
+        .billing
+        .create_checkout_session(
+            customer_id,
+            &price_id,
+            plan.name,
+            &payload.success_url,
+            &payload.cancel_url,
+        )
+        .await
+    {
+        Ok(url) => ServerResponse::ok(BillingSessionResponse { url }),
+        Err(err) => ServerResponse::server_error(err, "Failed to create checkout session"),
+    }
+}
+
+pub async fn create_billing_portal_session(
+    State(state): State<AppState>,
+    auth: OrganizationOwner,
+    Json(payload): Json<BillingPortalSessionRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    if let Err(detail) = validate_redirect_url("return_url", &payload.return_url) {
+        return ServerResponse::bad_request(detail);
+    }
+
+    let organization = match Organizations::find_by_id(auth.0.organization_id).one(db).await {
+        Ok(Some(organization)) => organization,
+        Ok(None) => return ServerResponse::not_found("Organization not found"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to fetch organization"),
+    };
+    let Some(customer_id) = organization.stripe_customer_id.as_deref() else {
+        return ServerResponse::bad_request("Organization has no Stripe customer");
+    };
+
+    match state
+        .billing
+        .create_billing_portal_session(customer_id, &payload.return_url)
+        .await
+    {
+        Ok(url) => ServerResponse::ok(BillingSessionResponse { url }),
+        Err(err) => ServerResponse::server_error(err, "Failed to create billing portal session"),
+    }
+}
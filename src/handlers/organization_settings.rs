@@ -0,0 +1,75 @@
+use crate::entities::organizations;
+use crate::entities::prelude::Organizations;
+use crate::middleware::authorization::{AuthorizedOrganizationUser, OrganizationOwner};
+use crate::state::AppState;
+use crate::utils::ServerResponse;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::Utc;
+use sea_orm::*;
+use serde::{Deserialize, Serialize};
+
+// Ten years, anything longer might as well not expire
+const MAX_API_KEY_LIFETIME_DAYS: i32 = 3650;
+
+#[derive(Debug, Serialize)]
+pub struct OrganizationSettingsResponse {
+    max_api_key_lifetime_days: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateOrganizationSettingsRequest {
+    // Null or left out lets keys live forever. Only applies to keys created afterwards.
+    max_api_key_lifetime_days: Option<i32>,
+}
+
+impl From<organizations::Model> for OrganizationSettingsResponse {
+    fn from(organization: organizations::Model) -> Self {
+        Self {
+            max_api_key_lifetime_days: organization.max_api_key_lifetime_days,
+        }
+    }
+}
+
+pub async fn get_organization_settings(
+    State(state): State<AppState>,
+    auth: AuthorizedOrganizationUser,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    match Organizations::find_by_id(auth.organization_id).one(db).await {
+        Ok(Some(organization)) => ServerResponse::ok(OrganizationSettingsResponse::from(organization)),
+        Ok(None) => ServerResponse::not_found("Organization not found"),
+        Err(err) => ServerResponse::server_error(err, "Failed to get organization settings"),
+    }
+}
+
+pub async fn update_organization_settings(
+    State(state): State<AppState>,
+    auth: OrganizationOwner,
+    Json(payload): Json<UpdateOrganizationSettingsRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+    let auth_user = auth.0;
+
+    if payload
+        .max_api_key_lifetime_days
+        .is_some_and(|days| !(1..=MAX_API_KEY_LIFETIME_DAYS).contains(&days))
+    {
+        return ServerResponse::bad_request("max_api_key_lifetime_days must be between 1 and 3650");
+    }
+
+    let organization = organizations::ActiveModel {
+        id: Set(auth_user.organization_id),
+        max_api_key_lifetime_days: Set(payload.max_api_key_lifetime_days),
+        updated_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    };
+
+    match organization.update(db).await {
+        Ok(organization) => ServerResponse::ok(OrganizationSettingsResponse::from(organization)),
+        Err(DbErr::RecordNotUpdated) => ServerResponse::not_found("Organization not found"),
+        Err(err) => ServerResponse::server_error(err, "Failed to update organization settings"),
+    }
+}
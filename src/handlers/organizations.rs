@@ -1,17 +1,33 @@
-use crate::entities::prelude::{ApiKeys, OrganizationMembers, Users};
+use crate::entities::prelude::{ApiKeyUsage, ApiKeys, Channels, OrganizationMembers, Organizations, Users};
 use crate::entities::sea_orm_active_enums::{ApiKeyType, OrganizationRole};
-use crate::entities::{api_keys, organization_members, users};
+use crate::entities::{api_key_usage, api_keys, channels, organization_members, users};
+use crate::config::ApiKeyPepper;
 use crate::state::AppState;
-use crate::utils::{generate_api_key_prefix, hash_password_and_salt, ServerResponse};
-use axum::extract::{Path, State};
+use crate::utils::{
+    deserialize_some, generate_api_key, generate_api_key_prefix, ServerResponse,
+};
+use axum::extract::{Path, Query, State};
 use axum::response::IntoResponse;
+use chrono::Duration;
 use axum::Json;
-use chrono::Utc;
+use chrono::{NaiveDate, Utc};
 use sea_orm::*;
 use sea_orm::prelude::DateTime;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use uuid::Uuid;
+use crate::middleware::api_key_authorizer::{
+    normalize_allowed_ips, normalize_allowed_origins, ApiKeyScope,
+};
 use crate::middleware::authorization::{ApiKeyManager, AuthorizedOrganizationUser};
+use crate::middleware::invalidate_cached_key;
+
+const DEFAULT_ROTATION_GRACE_SECONDS: i64 = 24 * 60 * 60;
+const MAX_ROTATION_GRACE_SECONDS: i64 = 7 * 24 * 60 * 60;
+// Keys expiring within this window are flagged in the key list
+const EXPIRING_SOON_DAYS: i64 = 7;
+const DEFAULT_USAGE_DAYS: i64 = 30;
+const MAX_USAGE_DAYS: i64 = 366;
 
 #[derive(Debug, Serialize, FromQueryResult)]
 pub struct OrgUserResponse {
@@ -25,6 +41,26 @@ pub struct OrgUserResponse {
 pub struct CreateApiKeyRequest {
     name: String,
     key_type: ApiKeyType,
+    // Leave out to allow everything `key_type` allows
+    scopes: Option<Vec<ApiKeyScope>>,
+    // Leave out to allow every channel of the organization
+    channel_ids: Option<Vec<Uuid>>,
+    // Either an absolute expiry or a TTL, leave both out for the organization's maximum lifetime
+    expires_at: Option<chrono::DateTime<Utc>>,
+    expires_in_seconds: Option<i64>,
+    // CIDR ranges requests must come from, leave out to allow any address
+    allowed_ips: Option<Vec<String>>,
+    // Origins browser requests must come from, leave out to allow any origin
+    allowed_origins: Option<Vec<String>>,
+}
+
+// Fields left out stay as they are, `null` removes the restriction
+#[derive(Debug, Deserialize)]
+pub struct UpdateApiKeyRequest {
+    #[serde(default, deserialize_with = "deserialize_some")]
+    allowed_ips: Option<Option<Vec<String>>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    allowed_origins: Option<Option<Vec<String>>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -33,6 +69,15 @@ pub struct CreateApiKeyResponse {
     name: String,
     key: String,
     key_type: ApiKeyType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scopes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_ids: Option<Vec<Uuid>>,
+    expires_at: Option<chrono::DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_ips: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_origins: Option<Vec<String>>,
     created_at: chrono::DateTime<Utc>,
 }
 #[derive(Debug, Serialize)]
@@ -40,14 +85,140 @@ pub struct GetApiKeysResponse {
     id: Uuid,
     name: String,
     key_type: ApiKeyType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scopes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_ids: Option<Vec<Uuid>>,
+    last_used_at: Option<chrono::DateTime<Utc>>,
+    // Only set while the secret replaced by the last rotation still works
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_key_expires_at: Option<chrono::DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_key_last_used_at: Option<chrono::DateTime<Utc>>,
+    expires_at: Option<chrono::DateTime<Utc>>,
+    expiring_soon: bool,
+    // Set once the sweeper has revoked an expired key
+    revoked_at: Option<chrono::DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_ips: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_origins: Option<Vec<String>>,
     created_at: chrono::DateTime<Utc>,
 }
 
+impl GetApiKeysResponse {
+    fn new(key: api_keys::Model, now: DateTime) -> Self {
+        let expiring_soon_before = now + Duration::days(EXPIRING_SOON_DAYS);
+
+        Self {
+            id: key.id,
+            name: key.name,
+            key_type: key.key_type,
+            scopes: key.scopes,
+            channel_ids: key.channel_ids,
+            last_used_at: key.last_used_at.map(|at| at.and_utc()),
+            previous_key_expires_at: key
+                .previous_key_expires_at
+                .filter(|expires_at| *expires_at > now)
+                .map(|at| at.and_utc()),
+            previous_key_last_used_at: key.previous_key_last_used_at.map(|at| at.and_utc()),
+            expires_at: key.expires_at.map(|at| at.and_utc()),
+            expiring_soon: key.revoked_at.is_none()
+                && key
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at > now && expires_at <= expiring_soon_before),
+            revoked_at: key.revoked_at.map(|at| at.and_utc()),
+            allowed_ips: key.allowed_ips,
+            allowed_origins: key.allowed_origins,
+            created_at: key.created_at.and_utc(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RotateApiKeyRequest {
+    // How long the old secret keeps working, 0 revokes it right away
+    grace_period_seconds: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotateApiKeyResponse {
+    id: Uuid,
+    name: String,
+    key: String,
+    key_type: ApiKeyType,
+    // The key's own expiry, which applies to the new secret
+    expires_at: Option<chrono::DateTime<Utc>>,
+    previous_key_expires_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyUsageQuery {
+    // Both inclusive, defaults to the last 30 days
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyUsageDay {
+    date: NaiveDate,
+    request_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyUsageResponse {
+    id: Uuid,
+    name: String,
+    key_type: ApiKeyType,
+    total_requests: i64,
+    // One entry per day of the range, days without requests included
+    days: Vec<ApiKeyUsageDay>,
+}
+
+// An empty list would lock the key out completely
+fn validate_allowed_ips(allowed_ips: Option<Vec<String>>) -> Result<Option<Vec<String>>, String> {
+    match allowed_ips {
+        Some(allowed_ips) if allowed_ips.is_empty() => {
+            Err("allowed_ips must not be empty, leave it out to allow any address".into())
+        }
+        Some(allowed_ips) => normalize_allowed_ips(&allowed_ips).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn validate_allowed_origins(allowed_origins: Option<Vec<String>>) -> Result<Option<Vec<String>>, String> {
+    match allowed_origins {
+        Some(allowed_origins) if allowed_origins.is_empty() => {
+            Err("allowed_origins must not be empty, leave it out to allow any origin".into())
+        }
+        Some(allowed_origins) => normalize_allowed_origins(&allowed_origins).map(Some),
+        None => Ok(None),
+    }
+}
+
+// A new secret, with the values it is stored and looked up by
+struct ApiKeySecret {
+    key: String,
+    key_hmac: String,
+    key_prefix: String,
+}
+
+fn generate_api_key_secret(pepper: &ApiKeyPepper) -> Result<ApiKeySecret, Box<dyn std::error::Error>> {
+    let key = generate_api_key()?;
+
+    Ok(ApiKeySecret {
+        key_hmac: pepper.hash(&key)?,
+        key_prefix: generate_api_key_prefix(&key),
+        key,
+    })
+}
+
 pub async fn get_api_keys(
     State(state): State<AppState>,
     auth: AuthorizedOrganizationUser,
 ) -> impl IntoResponse {
     let db = &state.db.connection;
+    let now = Utc::now().naive_utc();
 
     match ApiKeys::find()
         .filter(api_keys::Column::OrganizationId.eq(auth.organization_id))
@@ -58,12 +229,7 @@ pub async fn get_api_keys(
         Ok(api_keys) => ServerResponse::ok({
             api_keys
                 .into_iter()
-                .map(|key| GetApiKeysResponse {
-                    id: key.id,
-                    name: key.name,
-                    key_type: key.key_type,
-                    created_at: key.created_at.and_utc(),
-                })
+                .map(|key| GetApiKeysResponse::new(key, now))
                 .collect::<Vec<GetApiKeysResponse>>()
         }),
         Err(err) => ServerResponse::server_error(err, "Failed to get keys"),
@@ -96,16 +262,94 @@ pub async fn create_api_key(
     let now = Utc::now().naive_utc();
     let auth_user = auth.0;
 
-    // Generate a unique API key
-    let api_key = format!("sk_{}", Uuid::new_v4());
+    // An empty list would create a key that can't be used for anything
+    let scopes = match payload.scopes {
+        Some(scopes) if scopes.is_empty() => {
+            return ServerResponse::bad_request("scopes must not be empty, leave it out for an unscoped key")
+        }
+        Some(mut scopes) => {
+            scopes.sort_by_key(|scope| scope.as_str());
+            scopes.dedup();
+            Some(scopes.iter().map(|scope| scope.as_str().to_string()).collect::<Vec<_>>())
+        }
+        None => None,
+    };
 
-    // Generate the key prefix
-    let key_prefix = generate_api_key_prefix(&api_key);
+    let channel_ids = match payload.channel_ids {
+        Some(channel_ids) if channel_ids.is_empty() => {
+            return ServerResponse::bad_request("channel_ids must not be empty, leave it out for every channel")
+        }
+        Some(mut channel_ids) => {
+            channel_ids.sort();
+            channel_ids.dedup();
 
-    // Hash the API key
-    let hashed_api_key = match hash_password_and_salt(&api_key) {
-        Ok(hashed) => hashed,
-        Err(err) => return ServerResponse::server_error(err, "Failed to hash API key"),
+            match Channels::find()
+                .filter(channels::Column::Id.is_in(channel_ids.clone()))
+                .filter(channels::Column::OrganizationId.eq(auth_user.organization_id))
+                .count(db)
+                .await
+            {
+                Ok(count) if count as usize == channel_ids.len() => Some(channel_ids),
+                Ok(_) => return ServerResponse::bad_request("Some channels were not found in this organization"),
+                Err(err) => return ServerResponse::server_error(err, "Failed to check channels"),
+            }
+        }
+        None => None,
+    };
+
+    let allowed_ips = match validate_allowed_ips(payload.allowed_ips) {
+        Ok(allowed_ips) => allowed_ips,
+        Err(msg) => return ServerResponse::bad_request(msg),
+    };
+    let allowed_origins = match validate_allowed_origins(payload.allowed_origins) {
+        Ok(allowed_origins) => allowed_origins,
+        Err(msg) => return ServerResponse::bad_request(msg),
+    };
+
+    let organization = match Organizations::find_by_id(auth_user.organization_id).one(db).await {
+        Ok(Some(organization)) => organization,
+        Ok(None) => return ServerResponse::not_found("Organization not found"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to find organization"),
+    };
+
+    let requested_expires_at = match (payload.expires_at, payload.expires_in_seconds) {
+        (Some(_), Some(_)) => {
+            return ServerResponse::bad_request("Set either expires_at or expires_in_seconds, not both")
+        }
+        (Some(expires_at), None) => Some(expires_at.naive_utc()),
+        (None, Some(seconds)) => match Duration::try_seconds(seconds) {
+            Some(ttl) if seconds > 0 => Some(now + ttl),
+            _ => return ServerResponse::bad_request("expires_in_seconds must be positive"),
+        },
+        (None, None) => None,
+    };
+
+    if requested_expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return ServerResponse::bad_request("expires_at must be in the future");
+    }
+
+    // Keys without an expiry get the longest lifetime the organization allows
+    let expires_at = match organization.max_api_key_lifetime_days {
+        Some(days) => {
+            let latest = now + Duration::days(days.into());
+            match requested_expires_at {
+                Some(expires_at) if expires_at > latest => {
+                    return ServerResponse::bad_request(format!(
+                        "API keys in this organization can't live longer than {} days",
+                        days
+                    ))
+                }
+                Some(expires_at) => Some(expires_at),
+                None => Some(latest),
+            }
+        }
+        None => requested_expires_at,
+    };
+
+    // Generate a unique API key
+    let secret = match generate_api_key_secret(&state.api_key_pepper) {
+        Ok(secret) => secret,
+        Err(err) => return ServerResponse::server_error(err, "Failed to generate API key"),
     };
 
     // Create new API key
@@ -113,14 +357,23 @@ pub async fn create_api_key(
         id: Set(Uuid::new_v4()),
         organization_id: Set(auth_user.organization_id),
         name: Set(payload.name),
-        key: Set(hashed_api_key),
-        key_prefix: Set(key_prefix), 
+        key: Set(None),
+        key_hmac: Set(Some(secret.key_hmac)),
+        key_prefix: Set(secret.key_prefix),
         key_type: Set(payload.key_type),
+        scopes: Set(scopes),
+        channel_ids: Set(channel_ids),
         created_by_user_id: Set(auth_user.user.user_id),
         last_used_at: Set(None),
-        expires_at: Set(None),
+        expires_at: Set(expires_at),
         created_at: Set(now),
         updated_at: Set(now),
+        previous_key_hmac: Set(None),
+        previous_key_expires_at: Set(None),
+        previous_key_last_used_at: Set(None),
+        revoked_at: Set(None),
+        allowed_ips: Set(allowed_ips),
+        allowed_origins: Set(allowed_origins),
     };
 
     match new_api_key.insert(db).await {
@@ -128,8 +381,13 @@ pub async fn create_api_key(
             let response = CreateApiKeyResponse {
                 id: api_key_model.id,
                 name: api_key_model.name,
-                key: api_key,  // Uppon creation we want to return the unhashed key to the user
+                key: secret.key,  // Uppon creation we want to return the unhashed key to the user
                 key_type: api_key_model.key_type,
+                scopes: api_key_model.scopes,
+                channel_ids: api_key_model.channel_ids,
+                expires_at: api_key_model.expires_at.map(|at| at.and_utc()),
+                allowed_ips: api_key_model.allowed_ips,
+                allowed_origins: api_key_model.allowed_origins,
                 created_at: api_key_model.created_at.and_utc(),
             };
             ServerResponse::created(response)
@@ -163,9 +421,272 @@ pub async fn delete_api_key(
     };
 
     // Delete the key
-    match ApiKeys::delete_by_id(key.id).exec(db).await {
-        Ok(_) => ServerResponse::ok(()),
-        Err(err) => ServerResponse::server_error(err, "Failed to delete API key"),
+    if let Err(err) = ApiKeys::delete_by_id(key.id).exec(db).await {
+        return ServerResponse::server_error(err, "Failed to delete API key");
+    }
+
+    invalidate_cached_key(&state, &key).await;
+
+    ServerResponse::ok(())
+}
+
+// Changes a key's allowlists, the key itself stays the same
+pub async fn update_api_key(
+    State(state): State<AppState>,
+    Path((organization_id, key_id)): Path<(Uuid, Uuid)>,
+    auth: ApiKeyManager,
+    Json(payload): Json<UpdateApiKeyRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+    let auth_user = auth.0;
+    let now = Utc::now().naive_utc();
+
+    if organization_id != auth_user.organization_id {
+        return ServerResponse::forbidden("Not authorized to access this organization");
+    }
+
+    let key = match ApiKeys::find_by_id(key_id)
+        .filter(api_keys::Column::OrganizationId.eq(organization_id))
+        .one(db)
+        .await
+    {
+        Ok(Some(key)) => key,
+        Ok(None) => return ServerResponse::not_found("API key not found"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to find API key"),
+    };
+
+    let mut updated = api_keys::ActiveModel {
+        id: Set(key.id),
+        updated_at: Set(now),
+        ..Default::default()
+    };
+
+    if let Some(allowed_ips) = payload.allowed_ips {
+        match validate_allowed_ips(allowed_ips) {
+            Ok(allowed_ips) => updated.allowed_ips = Set(allowed_ips),
+            Err(msg) => return ServerResponse::bad_request(msg),
+        }
+    }
+    if let Some(allowed_origins) = payload.allowed_origins {
+        match validate_allowed_origins(allowed_origins) {
+            Ok(allowed_origins) => updated.allowed_origins = Set(allowed_origins),
+            Err(msg) => return ServerResponse::bad_request(msg),
+        }
+    }
+
+    let updated = match updated.update(db).await {
+        Ok(updated) => updated,
+        Err(err) => return ServerResponse::server_error(err, "Failed to update API key"),
+    };
+
+    invalidate_cached_key(&state, &key).await;
+
+    ServerResponse::ok(GetApiKeysResponse::new(updated, now))
+}
+
+// Issues a new secret for the same key. The old one keeps working for the grace
+// period, so clients can switch over without downtime.
+pub async fn rotate_api_key(
+    State(state): State<AppState>,
+    Path((organization_id, key_id)): Path<(Uuid, Uuid)>,
+    auth: ApiKeyManager,
+    Json(payload): Json<RotateApiKeyRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+    let auth_user = auth.0;
+    let now = Utc::now().naive_utc();
+
+    if organization_id != auth_user.organization_id {
+        return ServerResponse::forbidden("Not authorized to access this organization");
+    }
+
+    let grace_period_seconds = payload
+        .grace_period_seconds
+        .unwrap_or(DEFAULT_ROTATION_GRACE_SECONDS);
+    if !(0..=MAX_ROTATION_GRACE_SECONDS).contains(&grace_period_seconds) {
+        return ServerResponse::bad_request("grace_period_seconds must be between 0 and 604800");
+    }
+
+    let key = match ApiKeys::find_by_id(key_id)
+        .filter(api_keys::Column::OrganizationId.eq(organization_id))
+        .one(db)
+        .await
+    {
+        Ok(Some(key)) => key,
+        Ok(None) => return ServerResponse::not_found("API key not found"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to find API key"),
+    };
+
+    if key.expires_at.is_some_and(|expires_at| expires_at < now) {
+        return ServerResponse::bad_request("Expired API keys can't be rotated");
+    }
+
+    let secret = match generate_api_key_secret(&state.api_key_pepper) {
+        Ok(secret) => secret,
+        Err(err) => return ServerResponse::server_error(err, "Failed to generate API key"),
+    };
+
+    let previous_key_expires_at = now + Duration::seconds(grace_period_seconds);
+    let keep_previous = grace_period_seconds > 0;
+
+    let mut rotated = api_keys::ActiveModel {
+        id: Set(key.id),
+        key_hmac: Set(Some(secret.key_hmac)),
+        key_prefix: Set(secret.key_prefix),
+        key: Set(None),
+        previous_key_hmac: Set(key.key_hmac.clone().filter(|_| keep_previous)),
+        previous_key_expires_at: Set(Some(previous_key_expires_at)),
+        previous_key_last_used_at: Set(key.last_used_at),
+        last_used_at: Set(None),
+        updated_at: Set(now),
+        ..Default::default()
+    };
+
+    // A legacy key that was never used has no HMAC yet. Its bcrypt hash becomes the
+    // previous secret instead, and its prefix has to stay so it can still be found.
+    if key.key_hmac.is_none() && keep_previous {
+        rotated.key = Set(key.key.clone());
+        rotated.key_prefix = Set(key.key_prefix.clone());
+    }
+
+    let rotated = match rotated.update(db).await {
+        Ok(rotated) => rotated,
+        Err(err) => return ServerResponse::server_error(err, "Failed to rotate API key"),
+    };
+
+    invalidate_cached_key(&state, &key).await;
+
+    ServerResponse::ok(RotateApiKeyResponse {
+        id: rotated.id,
+        name: rotated.name,
+        key: secret.key,
+        key_type: rotated.key_type,
+        expires_at: rotated.expires_at.map(|at| at.and_utc()),
+        previous_key_expires_at: previous_key_expires_at.and_utc(),
+    })
+}
+
+// Returns the inclusive range to report usage for
+fn usage_range(query: &ApiKeyUsageQuery) -> Result<(NaiveDate, NaiveDate), &'static str> {
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query
+        .from
+        .unwrap_or_else(|| to - Duration::days(DEFAULT_USAGE_DAYS - 1));
+
+    if from > to {
+        return Err("from must not be after to");
+    }
+    if (to - from).num_days() >= MAX_USAGE_DAYS {
+        return Err("Usage can be requested for at most 366 days at a time");
+    }
+
+    Ok((from, to))
+}
+
+// Builds a daily time series per key from the stored usage rows
+async fn get_usage_for_keys(
+    db: &DatabaseConnection,
+    keys: Vec<api_keys::Model>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<ApiKeyUsageResponse>, DbErr> {
+    let usage: HashMap<(Uuid, NaiveDate), i64> = ApiKeyUsage::find()
+        .filter(api_key_usage::Column::ApiKeyId.is_in(keys.iter().map(|key| key.id)))
+        .filter(api_key_usage::Column::Date.between(from, to))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|row| ((row.api_key_id, row.date), i64::from(row.message_count)))
+        .collect();
+
+    Ok(keys
+        .into_iter()
+        .map(|key| {
+            let days: Vec<ApiKeyUsageDay> = from
+                .iter_days()
+                .take_while(|date| *date <= to)
+                .map(|date| ApiKeyUsageDay {
+                    date,
+                    request_count: usage.get(&(key.id, date)).copied().unwrap_or(0),
+                })
+                .collect();
+
+            ApiKeyUsageResponse {
+                id: key.id,
+                name: key.name,
+                key_type: key.key_type,
+                total_requests: days.iter().map(|day| day.request_count).sum(),
+                days,
+            }
+        })
+        .collect())
+}
+
+// Usage of every key in the organization, busiest first
+pub async fn get_api_keys_usage(
+    State(state): State<AppState>,
+    auth: AuthorizedOrganizationUser,
+    Query(query): Query<ApiKeyUsageQuery>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    let (from, to) = match usage_range(&query) {
+        Ok(range) => range,
+        Err(msg) => return ServerResponse::bad_request(msg),
+    };
+
+    let keys = match ApiKeys::find()
+        .filter(api_keys::Column::OrganizationId.eq(auth.organization_id))
+        .order_by_asc(api_keys::Column::CreatedAt)
+        .all(db)
+        .await
+    {
+        Ok(keys) => keys,
+        Err(err) => return ServerResponse::server_error(err, "Failed to get keys"),
+    };
+
+    match get_usage_for_keys(db, keys, from, to).await {
+        Ok(mut usage) => {
+            usage.sort_by_key(|key| std::cmp::Reverse(key.total_requests));
+            ServerResponse::ok(usage)
+        }
+        Err(err) => ServerResponse::server_error(err, "Failed to get key usage"),
+    }
+}
+
+pub async fn get_api_key_usage(
+    State(state): State<AppState>,
+    Path((organization_id, key_id)): Path<(Uuid, Uuid)>,
+    auth: AuthorizedOrganizationUser,
+    Query(query): Query<ApiKeyUsageQuery>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    if organization_id != auth.organization_id {
+        return ServerResponse::forbidden("Not authorized to access this organization");
+    }
+
+    let (from, to) = match usage_range(&query) {
+        Ok(range) => range,
+        Err(msg) => return ServerResponse::bad_request(msg),
+    };
+
+    let key = match ApiKeys::find_by_id(key_id)
+        .filter(api_keys::Column::OrganizationId.eq(organization_id))
+        .one(db)
+        .await
+    {
+        Ok(Some(key)) => key,
+        Ok(None) => return ServerResponse::not_found("API key not found"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to find API key"),
+    };
+
+    match get_usage_for_keys(db, vec![key], from, to).await {
+        Ok(usage) => match usage.into_iter().next() {
+            Some(usage) => ServerResponse::ok(usage),
+            None => ServerResponse::not_found("API key not found"),
+        },
+        Err(err) => ServerResponse::server_error(err, "Failed to get key usage"),
     }
 }
 
@@ -0,0 +1,107 @@
+use super::participants::find_org_participant;
+use crate::entities::{channels, prelude::*};
+use crate::middleware::api_key_authorizer::{ApiKeyAuthorizer, ManageParticipants, ReadWriteAccess};
+use crate::state::AppState;
+use crate::utils::{encode_participant_token, ParticipantClaims, ServerResponse, PARTICIPANT_TOKEN_AUDIENCE};
+use axum::{extract::Path, extract::State, response::IntoResponse, Json};
+use chrono::{Duration, Utc};
+use sea_orm::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const DEFAULT_TOKEN_TTL_SECONDS: i64 = 60 * 60;
+const MIN_TOKEN_TTL_SECONDS: i64 = 60;
+const MAX_TOKEN_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateParticipantTokenRequest {
+    ttl_seconds: Option<i64>,
+    // Restricts the token to these channels, all channels of the organization when left out
+    channel_ids: Option<Vec<Uuid>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateParticipantTokenResponse {
+    token: String,
+    participant_id: Uuid,
+    expires_at: chrono::DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_ids: Option<Vec<Uuid>>,
+}
+
+// Minted by the customer's backend and handed to browser or mobile clients,
+// which use it instead of an API key for the socket and the message routes
+pub async fn create_participant_token(
+    State(state): State<AppState>,
+    auth: ApiKeyAuthorizer<ReadWriteAccess, ManageParticipants>,
+    Path((organization_id, participant_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<CreateParticipantTokenRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    let ttl_seconds = payload.ttl_seconds.unwrap_or(DEFAULT_TOKEN_TTL_SECONDS);
+    if !(MIN_TOKEN_TTL_SECONDS..=MAX_TOKEN_TTL_SECONDS).contains(&ttl_seconds) {
+        return ServerResponse::bad_request("ttl_seconds must be between 60 and 86400");
+    }
+
+    match find_org_participant(db, organization_id, participant_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return ServerResponse::not_found("Participant not found"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to fetch participant"),
+    }
+
+    // Tokens can't reach further than the key that issued them, so keys restricted
+    // to channels pass their restriction on
+    let mut channel_ids = match (payload.channel_ids, &auth.channel_ids) {
+        (Some(requested), Some(allowed)) if requested.iter().any(|id| !allowed.contains(id)) => {
+            return ServerResponse::forbidden("API key is not valid for some of these channels")
+        }
+        (None, Some(allowed)) => Some(allowed.clone()),
+        (requested, _) => requested,
+    };
+    if let Some(ids) = channel_ids.as_mut() {
+        ids.sort();
+        ids.dedup();
+
+        if ids.is_empty() {
+            return ServerResponse::bad_request("channel_ids must not be empty");
+        }
+
+        match Channels::find()
+            .filter(channels::Column::Id.is_in(ids.clone()))
+            .filter(channels::Column::OrganizationId.eq(organization_id))
+            .count(db)
+            .await
+        {
+            Ok(count) if count as usize == ids.len() => {}
+            Ok(_) => return ServerResponse::bad_request("Channel not found in this organization"),
+            Err(err) => return ServerResponse::server_error(err, "Failed to check channels"),
+        }
+    }
+
+    let secret = match std::env::var("JWT_SECRET") {
+        Ok(secret) => secret,
+        Err(err) => return ServerResponse::server_error(err, "Token signing is not configured"),
+    };
+
+    let now = Utc::now();
+    let expires_at = now + Duration::seconds(ttl_seconds);
+    let claims = ParticipantClaims {
+        sub: participant_id,
+        organization_id,
+        aud: PARTICIPANT_TOKEN_AUDIENCE.to_string(),
+        exp: expires_at.timestamp(),
+        iat: now.timestamp(),
+        channel_ids: channel_ids.clone(),
+    };
+
+    match encode_participant_token(&claims, &secret) {
+        Ok(token) => ServerResponse::created(CreateParticipantTokenResponse {
+            token,
+            participant_id,
+            expires_at,
+            channel_ids,
+        }),
+        Err(err) => ServerResponse::server_error(err, "Failed to issue token"),
+    }
+}
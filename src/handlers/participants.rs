@@ -1,56 +1,458 @@
-use crate::entities::{participant, prelude::*};
-use crate::utils::ServerResponse;
-use axum::{extract::State, response::IntoResponse, Json};
+use crate::entities::{channel_participant, participant, prelude::*};
+use crate::middleware::api_key_authorizer::{
+    AdminAccess, ApiKeyAuthorizer, ManageParticipants, ReadOnlyAccess, ReadParticipants,
+    ReadWriteAccess,
+};
+use crate::utils::{deserialize_some, ServerResponse};
+use axum::{extract::Path, extract::Query, extract::State, response::IntoResponse, Json};
+use chrono::Utc;
+use sea_orm::prelude::Expr;
+use sea_orm::sea_query::OnConflict;
 use sea_orm::*;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::state::AppState;
 
+const DEFAULT_PARTICIPANTS_PAGE_SIZE: u64 = 50;
+const MAX_PARTICIPANTS_PAGE_SIZE: u64 = 200;
+const MAX_METADATA_BYTES: usize = 16 * 1024;
+// Shown in place of deleted participants' names
+const DELETED_PARTICIPANT_NAME: &str = "Deleted participant";
+
 #[derive(Debug, Serialize)]
 pub struct CreateParticipantResponse {
     id: Uuid,
     name: String,
+    external_id: Option<String>,
+    display_name: Option<String>,
+    avatar_url: Option<String>,
+    metadata: Option<serde_json::Value>,
+    created_at: chrono::DateTime<Utc>,
+    updated_at: chrono::DateTime<Utc>,
+}
+
+impl From<participant::Model> for CreateParticipantResponse {
+    fn from(participant: participant::Model) -> Self {
+        Self {
+            id: participant.id,
+            name: participant.name,
+            external_id: participant.external_id,
+            display_name: participant.display_name,
+            avatar_url: participant.avatar_url,
+            metadata: participant.metadata,
+            created_at: participant.created_at.and_utc(),
+            updated_at: participant.updated_at.and_utc(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateParticipantRequest {
     name: String,
+    // The participant's id in the customer's own system, unique per organization
+    external_id: Option<String>,
+    display_name: Option<String>,
+    avatar_url: Option<String>,
+    metadata: Option<serde_json::Value>,
+}
+
+// Body of `PUT /participants/external/:external_id`, replaces the whole profile
+#[derive(Debug, Deserialize)]
+pub struct UpsertParticipantRequest {
+    name: String,
+    display_name: Option<String>,
+    avatar_url: Option<String>,
+    metadata: Option<serde_json::Value>,
+}
+
+// Fields that are left out stay as they are, `null` clears them
+#[derive(Debug, Deserialize)]
+pub struct UpdateParticipantRequest {
+    name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    external_id: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    avatar_url: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    metadata: Option<Option<serde_json::Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ParticipantsQuery {
+    // Id of the last participant of the previous page
+    after: Option<Uuid>,
+    limit: Option<u64>,
+}
+
+fn validate_profile(
+    avatar_url: Option<&String>,
+    metadata: Option<&serde_json::Value>,
+) -> Result<(), &'static str> {
+    if let Some(avatar_url) = avatar_url {
+        if !avatar_url.starts_with("https://") && !avatar_url.starts_with("http://") {
+            return Err("avatar_url must be an http(s) URL");
+        }
+    }
+
+    if let Some(metadata) = metadata {
+        if !metadata.is_object() {
+            return Err("metadata must be a JSON object");
+        }
+        if metadata.to_string().len() > MAX_METADATA_BYTES {
+            return Err("metadata must not be larger than 16 KB");
+        }
+    }
+
+    Ok(())
+}
+
+// Participant ids sent by API key callers have to be checked against the
+// organization, otherwise one customer could act as another one's participants
+pub(crate) async fn find_org_participant(
+    db: &DatabaseConnection,
+    organization_id: Uuid,
+    participant_id: Uuid,
+) -> Result<Option<participant::Model>, DbErr> {
+    Participant::find_by_id(participant_id)
+        .filter(participant::Column::OrganizationId.eq(organization_id))
+        .filter(participant::Column::DeletedAt.is_null())
+        .one(db)
+        .await
+}
+
+async fn find_by_external_id(
+    db: &DatabaseConnection,
+    organization_id: Uuid,
+    external_id: &str,
+) -> Result<Option<participant::Model>, DbErr> {
+    Participant::find()
+        .filter(participant::Column::OrganizationId.eq(organization_id))
+        .filter(participant::Column::ExternalId.eq(external_id))
+        .filter(participant::Column::DeletedAt.is_null())
+        .one(db)
+        .await
 }
 
 pub async fn create_participant(
     State(state): State<AppState>,
+    _: ApiKeyAuthorizer<ReadWriteAccess, ManageParticipants>,
+    Path(organization_id): Path<Uuid>,
     Json(payload): Json<CreateParticipantRequest>,
 ) -> impl IntoResponse {
     tracing::info!("executes: create_participant");
 
     let db = &state.db.connection;
 
-    let name = payload.name;
+    if let Err(msg) = validate_profile(payload.avatar_url.as_ref(), payload.metadata.as_ref()) {
+        return ServerResponse::bad_request(msg);
+    }
 
+    let now = Utc::now().naive_utc();
     let new_participant = participant::ActiveModel {
         id: Set(Uuid::new_v4()),
-        name: Set(name.clone()),
-        created_at: Set(chrono::Utc::now().naive_utc()),
-        updated_at: Set(chrono::Utc::now().naive_utc()),
+        name: Set(payload.name),
+        created_at: Set(now),
+        updated_at: Set(now),
+        organization_id: Set(organization_id),
+        external_id: Set(payload.external_id),
+        display_name: Set(payload.display_name),
+        avatar_url: Set(payload.avatar_url),
+        metadata: Set(payload.metadata),
+        deleted_at: Set(None),
     };
 
-    match Participant::insert(new_participant).exec(db).await {
-        Ok(participant) => {
-            let response = CreateParticipantResponse {
-                id: participant.last_insert_id,
-                name,
-            };
-            ServerResponse::created(response)
+    // The unique index decides, so concurrent creates with the same external_id
+    // can't both get past a lookup and fail on the insert
+    match Participant::insert(new_participant)
+        .on_conflict(
+            OnConflict::columns([
+                participant::Column::OrganizationId,
+                participant::Column::ExternalId,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .exec_with_returning(db)
+        .await
+    {
+        Ok(participant) => ServerResponse::created(CreateParticipantResponse::from(participant)),
+        // The insert returns no row when the conflict skipped it
+        Err(DbErr::RecordNotFound(_)) => {
+            ServerResponse::bad_request("Participant with this external_id already exists")
         }
         Err(err) => ServerResponse::server_error(err, "Failed to create participant"),
     }
 }
 
-pub async fn get_participants_count(State(state): State<AppState>) -> impl IntoResponse {
+// Creates the participant on first sight of an external id and keeps the profile
+// in sync afterwards, so callers never have to store our participant ids
+pub async fn upsert_participant_by_external_id(
+    State(state): State<AppState>,
+    _: ApiKeyAuthorizer<ReadWriteAccess, ManageParticipants>,
+    Path((organization_id, external_id)): Path<(Uuid, String)>,
+    Json(payload): Json<UpsertParticipantRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    if let Err(msg) = validate_profile(payload.avatar_url.as_ref(), payload.metadata.as_ref()) {
+        return ServerResponse::bad_request(msg);
+    }
+
+    let id = Uuid::new_v4();
+    let now = Utc::now().naive_utc();
+    let participant = participant::ActiveModel {
+        id: Set(id),
+        name: Set(payload.name),
+        created_at: Set(now),
+        updated_at: Set(now),
+        organization_id: Set(organization_id),
+        external_id: Set(Some(external_id)),
+        display_name: Set(payload.display_name),
+        avatar_url: Set(payload.avatar_url),
+        metadata: Set(payload.metadata),
+        deleted_at: Set(None),
+    };
+
+    match Participant::insert(participant)
+        .on_conflict(
+            OnConflict::columns([
+                participant::Column::OrganizationId,
+                participant::Column::ExternalId,
+            ])
+            .update_columns([
+                participant::Column::Name,
+                participant::Column::DisplayName,
+                participant::Column::AvatarUrl,
+                participant::Column::Metadata,
+                participant::Column::UpdatedAt,
+            ])
+            .to_owned(),
+        )
+        .exec_with_returning(db)
+        .await
+    {
+        // The id we generated is only kept when the row was actually inserted
+        Ok(participant) if participant.id == id => {
+            ServerResponse::created(CreateParticipantResponse::from(participant))
+        }
+        Ok(participant) => ServerResponse::ok(CreateParticipantResponse::from(participant)),
+        Err(err) => ServerResponse::server_error(err, "Failed to upsert participant"),
+    }
+}
+
+pub async fn get_participant_by_external_id(
+    State(state): State<AppState>,
+    _: ApiKeyAuthorizer<ReadOnlyAccess, ReadParticipants>,
+    Path((organization_id, external_id)): Path<(Uuid, String)>,
+) -> impl IntoResponse {
+    match find_by_external_id(&state.db.connection, organization_id, &external_id).await {
+        Ok(Some(participant)) => ServerResponse::ok(CreateParticipantResponse::from(participant)),
+        Ok(None) => ServerResponse::not_found("Participant not found"),
+        Err(err) => ServerResponse::server_error(err, "Failed to fetch participant"),
+    }
+}
+
+pub async fn get_participant(
+    State(state): State<AppState>,
+    _: ApiKeyAuthorizer<ReadOnlyAccess, ReadParticipants>,
+    Path((organization_id, participant_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    match find_org_participant(&state.db.connection, organization_id, participant_id).await {
+        Ok(Some(participant)) => ServerResponse::ok(CreateParticipantResponse::from(participant)),
+        Ok(None) => ServerResponse::not_found("Participant not found"),
+        Err(err) => ServerResponse::server_error(err, "Failed to fetch participant"),
+    }
+}
+
+pub async fn update_participant(
+    State(state): State<AppState>,
+    _: ApiKeyAuthorizer<ReadWriteAccess, ManageParticipants>,
+    Path((organization_id, participant_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<UpdateParticipantRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    if let Err(msg) = validate_profile(
+        payload.avatar_url.as_ref().and_then(Option::as_ref),
+        payload.metadata.as_ref().and_then(Option::as_ref),
+    ) {
+        return ServerResponse::bad_request(msg);
+    }
+
+    let participant = match find_org_participant(db, organization_id, participant_id).await {
+        Ok(Some(participant)) => participant,
+        Ok(None) => return ServerResponse::not_found("Participant not found"),
+        Err(err) => return ServerResponse::server_error(err, "Failed to fetch participant"),
+    };
+
+    if let Some(Some(external_id)) = &payload.external_id {
+        match find_by_external_id(db, organization_id, external_id).await {
+            Ok(Some(existing)) if existing.id != participant.id => {
+                return ServerResponse::bad_request("Participant with this external_id already exists")
+            }
+            Ok(_) => {}
+            Err(err) => return ServerResponse::server_error(err, "Failed to check external_id"),
+        }
+    }
+
+    let mut active: participant::ActiveModel = participant.into();
+    if let Some(name) = payload.name {
+        active.name = Set(name);
+    }
+    if let Some(external_id) = payload.external_id {
+        active.external_id = Set(external_id);
+    }
+    if let Some(display_name) = payload.display_name {
+        active.display_name = Set(display_name);
+    }
+    if let Some(avatar_url) = payload.avatar_url {
+        active.avatar_url = Set(avatar_url);
+    }
+    if let Some(metadata) = payload.metadata {
+        active.metadata = Set(metadata);
+    }
+    active.updated_at = Set(Utc::now().naive_utc());
+
+    match active.update(db).await {
+        Ok(participant) => ServerResponse::ok(CreateParticipantResponse::from(participant)),
+        Err(err) => ServerResponse::server_error(err, "Failed to update participant"),
+    }
+}
+
+// Anonymizes the participant and removes them from their channels. The row stays so
+// their messages remain as they were sent, and still count towards the month's usage.
+pub async fn delete_participant(
+    State(state): State<AppState>,
+    _: ApiKeyAuthorizer<AdminAccess, ManageParticipants>,
+    Path((organization_id, participant_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+    let now = Utc::now().naive_utc();
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(err) => return ServerResponse::server_error(err, "Failed to delete participant"),
+    };
+
+    // Freeing the external id lets the customer's user come back as a new participant
+    let deleted = Participant::update_many()
+        .col_expr(participant::Column::Name, Expr::value(DELETED_PARTICIPANT_NAME))
+        .col_expr(participant::Column::ExternalId, Expr::value(Option::<String>::None))
+        .col_expr(participant::Column::DisplayName, Expr::value(Option::<String>::None))
+        .col_expr(participant::Column::AvatarUrl, Expr::value(Option::<String>::None))
+        .col_expr(participant::Column::Metadata, Expr::value(Option::<serde_json::Value>::None))
+        .col_expr(participant::Column::DeletedAt, Expr::value(Some(now)))
+        .col_expr(participant::Column::UpdatedAt, Expr::value(now))
+        .filter(participant::Column::Id.eq(participant_id))
+        .filter(participant::Column::OrganizationId.eq(organization_id))
+        .filter(participant::Column::DeletedAt.is_null())
+        .exec(&txn)
+        .await;
+    match deleted {
+        Ok(result) if result.rows_affected == 0 => {
+            return ServerResponse::not_found("Participant not found")
+        }
+        Ok(_) => {}
+        Err(err) => return ServerResponse::server_error(err, "Failed to delete participant"),
+    }
+
+    if let Err(err) = ChannelParticipant::delete_many()
+        .filter(channel_participant::Column::ParticipantId.eq(participant_id))
+        .exec(&txn)
+        .await
+    {
+        return ServerResponse::server_error(err, "Failed to remove participant from channels");
+    }
+
+    match txn.commit().await {
+        Ok(()) => ServerResponse::ok(()),
+        Err(err) => ServerResponse::server_error(err, "Failed to delete participant"),
+    }
+}
+
+// Oldest first, paged with the id of the last participant as `after`
+pub async fn get_participants(
+    State(state): State<AppState>,
+    _: ApiKeyAuthorizer<ReadOnlyAccess, ReadParticipants>,
+    Path(organization_id): Path<Uuid>,
+    Query(params): Query<ParticipantsQuery>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PARTICIPANTS_PAGE_SIZE)
+        .clamp(1, MAX_PARTICIPANTS_PAGE_SIZE);
+
+    let mut query = Participant::find()
+        .filter(participant::Column::OrganizationId.eq(organization_id))
+        .filter(participant::Column::DeletedAt.is_null());
+
+    if let Some(after) = params.after {
+        // Deleted participants still hold their place in the order
+        let cursor = match Participant::find_by_id(after)
+            .filter(participant::Column::OrganizationId.eq(organization_id))
+            .one(db)
+            .await
+        {
+            Ok(Some(participant)) => participant,
+            Ok(None) => return ServerResponse::bad_request("Cursor participant not found"),
+            Err(err) => return ServerResponse::server_error(err, "Failed to resolve cursor"),
+        };
+
+        query = query.filter(
+            Condition::any()
+                .add(participant::Column::CreatedAt.gt(cursor.created_at))
+                .add(
+                    Condition::all()
+                        .add(participant::Column::CreatedAt.eq(cursor.created_at))
+                        .add(participant::Column::Id.gt(cursor.id)),
+                ),
+        );
+    }
+
+    match query
+        .order_by_asc(participant::Column::CreatedAt)
+        .order_by_asc(participant::Column::Id)
+        .limit(limit + 1)
+        .all(db)
+        .await
+    {
+        Ok(mut participants) => {
+            let has_more = participants.len() as u64 > limit;
+            participants.truncate(limit as usize);
+
+            let next_cursor = participants
+                .last()
+                .filter(|_| has_more)
+                .map(|participant| participant.id.to_string());
+
+            let participants = participants
+                .into_iter()
+                .map(CreateParticipantResponse::from)
+                .collect::<Vec<_>>();
+            ServerResponse::paginated(participants, next_cursor, None)
+        }
+        Err(err) => ServerResponse::server_error(err, "Failed to fetch participants"),
+    }
+}
+
+pub async fn get_participants_count(
+    State(state): State<AppState>,
+    _: ApiKeyAuthorizer<ReadOnlyAccess, ReadParticipants>,
+    Path(organization_id): Path<Uuid>,
+) -> impl IntoResponse {
     let db = &state.db.connection;
 
-    match Participant::find().count(db).await {
+    match Participant::find()
+        .filter(participant::Column::OrganizationId.eq(organization_id))
+        .filter(participant::Column::DeletedAt.is_null())
+        .count(db)
+        .await
+    {
         Ok(count) => ServerResponse::ok(count),
         Err(err) => ServerResponse::server_error(err, "Failed to get participants count"),
     }
@@ -0,0 +1,414 @@
+use super::channel_members::can_access_channel;
+use super::messages::find_live_message;
+use super::participants::find_org_participant;
+use crate::entities::{message_reactions, messages, prelude::*};
+use crate::middleware::api_key_authorizer::{ReadWriteAccess, WriteMessages};
+use crate::middleware::client_authorizer::{ClientAuthorizer, ClientCredential};
+use crate::realtime::{publish_event, ReactionPayload, ServerEvent};
+use crate::state::AppState;
+use crate::utils::ServerResponse;
+use axum::{extract::Path, extract::Query, extract::State, response::IntoResponse, response::Response, Json};
+use chrono::Utc;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::iter::Peekable;
+use unicode_properties::emoji::{self, EmojiStatus, UnicodeEmoji};
+use uuid::Uuid;
+
+// Long enough for ZWJ sequences and skin tones, short enough to keep
+// people from storing text in reactions
+const MAX_EMOJI_LENGTH: usize = 32;
+
+#[derive(Debug, Deserialize)]
+pub struct ReactionRequest {
+    emoji: String,
+    // Required for API keys, participant tokens always react as themselves
+    participant_id: Option<Uuid>,
+}
+
+// The emoji is part of the path when removing a reaction
+#[derive(Debug, Deserialize)]
+pub struct RemoveReactionQuery {
+    participant_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, FromQueryResult)]
+pub struct ReactionCount {
+    #[serde(skip)]
+    message_id: Uuid,
+    pub emoji: String,
+    pub count: i64,
+}
+
+// Aggregated reactions per message, emojis in the order they were first used
+pub(crate) async fn fetch_reaction_counts(
+    db: &DatabaseConnection,
+    message_ids: Vec<Uuid>,
+) -> Result<HashMap<Uuid, Vec<ReactionCount>>, DbErr> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let counts = MessageReactions::find()
+        .select_only()
+        .column(message_reactions::Column::MessageId)
+        .column(message_reactions::Column::Emoji)
+        .column_as(message_reactions::Column::ParticipantId.count(), "count")
+        .filter(message_reactions::Column::MessageId.is_in(message_ids))
+        .group_by(message_reactions::Column::MessageId)
+        .group_by(message_reactions::Column::Emoji)
+        .order_by(message_reactions::Column::CreatedAt.min(), Order::Asc)
+        .into_model::<ReactionCount>()
+        .all(db)
+        .await?;
+
+    let mut grouped: HashMap<Uuid, Vec<ReactionCount>> = HashMap::new();
+    for count in counts {
+        grouped.entry(count.message_id).or_default().push(count);
+    }
+
+    Ok(grouped)
+}
+
+// Works out who is reacting and whether they may react in the message's channel
+async fn resolve_reacting_participant(
+    db: &DatabaseConnection,
+    organization_id: Uuid,
+    credential: &ClientCredential,
+    requested: Option<Uuid>,
+    message: &messages::Model,
+) -> Result<Uuid, Response> {
+    let participant_id = match credential {
+        ClientCredential::Participant { participant_id, .. } => *participant_id,
+        ClientCredential::ApiKey { .. } => {
+            let Some(participant_id) = requested else {
+                return Err(ServerResponse::bad_request("participant_id is required"));
+            };
+
+            match find_org_participant(db, organization_id, participant_id).await {
+                Ok(Some(participant)) => participant.id,
+                Ok(None) => return Err(ServerResponse::bad_request("Participant not found in this organization")),
+                Err(err) => return Err(ServerResponse::server_error(err, "Failed to check participant")),
+            }
+        }
+    };
+
+    if !credential.allows_channel(&message.channel_id) {
+        return Err(ServerResponse::forbidden("Credentials are not valid for this channel"));
+    }
+
+    let channel = match Channels::find_by_id(message.channel_id).one(db).await {
+        Ok(Some(channel)) => channel,
+        Ok(None) => return Err(ServerResponse::not_found("Channel not found")),
+        Err(err) => return Err(ServerResponse::server_error(err, "Failed to fetch channel")),
+    };
+
+    match can_access_channel(db, &channel, participant_id).await {
+        Ok(true) => Ok(participant_id),
+        Ok(false) => Err(ServerResponse::forbidden("Participant is not a member of this channel")),
+        Err(err) => Err(ServerResponse::server_error(err, "Failed to check channel membership")),
+    }
+}
+
+const KEYCAP: char = '\u{20E3}';
+const CANCEL_TAG: char = '\u{E007F}';
+const EMOJI_PRESENTATION: char = '\u{FE0F}';
+
+// Accepts exactly one emoji as defined by Unicode (UTS #51): a flag, a keycap, or
+// emojis joined by ZWJ, each with an optional presentation selector, skin tone or
+// tag sequence. Anything else, text included, is rejected.
+//
+// Returns the fully qualified form, so "❤‍🔥" and "❤️‍🔥" are the same reaction.
+// A single character that is text by default, like "❤" or "©", is only an emoji
+// with U+FE0F and rejected without it.
+fn normalize_emoji(emoji: &str) -> Option<String> {
+    let emoji = emoji.trim();
+    if emoji.is_empty() || emoji.chars().count() > MAX_EMOJI_LENGTH {
+        return None;
+    }
+
+    let mut chars = emoji.chars().peekable();
+    if chars.peek().is_some_and(|c| emoji::is_regional_indicator(*c)) {
+        let is_flag = emoji.chars().count() == 2 && emoji.chars().all(emoji::is_regional_indicator);
+        return is_flag.then(|| emoji.to_string());
+    }
+
+    let mut elements = Vec::new();
+    loop {
+        elements.push(emoji_element(&mut chars)?);
+        match chars.next() {
+            None => break,
+            Some(c) if emoji::is_zwj(c) => continue,
+            Some(_) => return None,
+        }
+    }
+
+    if let [element] = elements.as_slice() {
+        if element.is_text {
+            return None;
+        }
+    }
+
+    let elements: Vec<String> = elements
+        .into_iter()
+        .map(|mut element| {
+            if element.is_text {
+                element.emoji.push(EMOJI_PRESENTATION);
+            }
+            element.emoji
+        })
+        .collect();
+    Some(elements.join("\u{200D}"))
+}
+
+// One element of a ZWJ sequence in its fully qualified form, except for text by
+// default characters that came without U+FE0F
+struct EmojiElement {
+    emoji: String,
+    is_text: bool,
+}
+
+fn emoji_element(chars: &mut Peekable<impl Iterator<Item = char>>) -> Option<EmojiElement> {
+    let base = chars.next()?;
+
+    // Digits, `#` and `*` count as emoji characters, but only as keycaps like 1️⃣
+    if base.is_ascii() {
+        if !matches!(base, '0'..='9' | '#' | '*') {
+            return None;
+        }
+        chars.next_if_eq(&EMOJI_PRESENTATION);
+        if chars.next() != Some(KEYCAP) {
+            return None;
+        }
+        return Some(EmojiElement {
+            emoji: [base, EMOJI_PRESENTATION, KEYCAP].iter().collect(),
+            is_text: false,
+        });
+    }
+
+    if !base.is_emoji_char() || emoji::is_regional_indicator(base) {
+        return None;
+    }
+
+    let mut emoji = String::from(base);
+    let has_selector = chars.next_if_eq(&EMOJI_PRESENTATION).is_some();
+    // A skin tone makes it an emoji already, U+FE0F isn't part of the qualified form then
+    let is_text = match chars.next_if(|c| is_skin_tone(*c)) {
+        Some(skin_tone) if is_modifier_base(base) => {
+            emoji.push(skin_tone);
+            false
+        }
+        Some(_) => return None,
+        None if has_emoji_presentation(base) => false,
+        None if has_selector => {
+            emoji.push(EMOJI_PRESENTATION);
+            false
+        }
+        None => true,
+    };
+
+    // Tag sequences, e.g. the flags of England or Scotland
+    if chars.peek().is_some_and(|c| emoji::is_tag_character(*c)) {
+        while let Some(tag) = chars.next_if(|c| emoji::is_tag_character(*c) && *c != CANCEL_TAG) {
+            emoji.push(tag);
+        }
+        if chars.next() != Some(CANCEL_TAG) {
+            return None;
+        }
+        emoji.push(CANCEL_TAG);
+    }
+
+    Some(EmojiElement { emoji, is_text })
+}
+
+fn is_skin_tone(c: char) -> bool {
+    matches!(c, '\u{1F3FB}'..='\u{1F3FF}')
+}
+
+fn has_emoji_presentation(c: char) -> bool {
+    matches!(
+        c.emoji_status(),
+        EmojiStatus::EmojiPresentation
+            | EmojiStatus::EmojiPresentationAndModifierBase
+            | EmojiStatus::EmojiPresentationAndEmojiComponent
+            | EmojiStatus::EmojiPresentationAndModifierAndEmojiComponent
+    )
+}
+
+fn is_modifier_base(c: char) -> bool {
+    matches!(
+        c.emoji_status(),
+        EmojiStatus::EmojiModifierBase | EmojiStatus::EmojiPresentationAndModifierBase
+    )
+}
+
+pub async fn add_reaction(
+    State(state): State<AppState>,
+    auth: ClientAuthorizer<ReadWriteAccess, WriteMessages>,
+    Path((_, message_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<ReactionRequest>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    let Some(emoji) = normalize_emoji(&payload.emoji) else {
+        return ServerResponse::bad_request("Invalid emoji");
+    };
+
+    let message = match find_live_message(db, auth.organization_id, message_id).await {
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+
+    let participant_id =
+        match resolve_reacting_participant(
+            db,
+            auth.organization_id,
+            &auth.credential,
+            payload.participant_id,
+            &message,
+        )
+        .await
+        {
+            Ok(id) => id,
+            Err(response) => return response,
+        };
+
+    let reaction = message_reactions::ActiveModel {
+        message_id: Set(message.id),
+        participant_id: Set(participant_id),
+        emoji: Set(emoji.clone()),
+        created_at: Set(Utc::now().naive_utc()),
+    };
+
+    // Adding a reaction twice is a no-op, only the first one is broadcast
+    let inserted = match MessageReactions::insert(reaction)
+        .on_conflict(
+            OnConflict::columns([
+                message_reactions::Column::MessageId,
+                message_reactions::Column::ParticipantId,
+                message_reactions::Column::Emoji,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await
+    {
+        Ok(rows) => rows > 0,
+        Err(err) => return ServerResponse::server_error(err, "Failed to add reaction"),
+    };
+
+    let payload = ReactionPayload {
+        message_id: message.id,
+        channel_id: message.channel_id,
+        participant_id,
+        emoji,
+    };
+
+    if !inserted {
+        return ServerResponse::ok(payload);
+    }
+
+    let event = ServerEvent::ReactionAdded(payload.clone());
+    if let Err(e) = publish_event(&state.redis, &message.channel_id, &event).await {
+        tracing::error!("Failed to publish reaction on message {}: {}", message.id, e);
+    }
+
+    ServerResponse::created(payload)
+}
+
+pub async fn remove_reaction(
+    State(state): State<AppState>,
+    auth: ClientAuthorizer<ReadWriteAccess, WriteMessages>,
+    Path((_, message_id, emoji)): Path<(Uuid, Uuid, String)>,
+    Query(query): Query<RemoveReactionQuery>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    let Some(emoji) = normalize_emoji(&emoji) else {
+        return ServerResponse::bad_request("Invalid emoji");
+    };
+
+    let message = match find_live_message(db, auth.organization_id, message_id).await {
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+
+    let participant_id =
+        match resolve_reacting_participant(
+            db,
+            auth.organization_id,
+            &auth.credential,
+            query.participant_id,
+            &message,
+        )
+        .await
+        {
+            Ok(id) => id,
+            Err(response) => return response,
+        };
+
+    match MessageReactions::delete_many()
+        .filter(message_reactions::Column::MessageId.eq(message.id))
+        .filter(message_reactions::Column::ParticipantId.eq(participant_id))
+        .filter(message_reactions::Column::Emoji.eq(&emoji))
+        .exec(db)
+        .await
+    {
+        Ok(result) if result.rows_affected == 0 => ServerResponse::not_found("Reaction not found"),
+        Ok(_) => {
+            let event = ServerEvent::ReactionRemoved(ReactionPayload {
+                message_id: message.id,
+                channel_id: message.channel_id,
+                participant_id,
+                emoji,
+            });
+            if let Err(e) = publish_event(&state.redis, &message.channel_id, &event).await {
+                tracing::error!("Failed to publish reaction removal on message {}: {}", message.id, e);
+            }
+
+            ServerResponse::ok(())
+        }
+        Err(err) => ServerResponse::server_error(err, "Failed to remove reaction"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_emojis_are_accepted_in_their_fully_qualified_form() {
+        let accepted = [
+            ("👍", "👍"),
+            (" 🎉 ", "🎉"),
+            ("👍\u{FE0F}", "👍"),
+            ("❤\u{FE0F}", "❤\u{FE0F}"),
+            ("©\u{FE0F}", "©\u{FE0F}"),
+            ("👍🏽", "👍🏽"),
+            ("☝🏽", "☝🏽"),
+            ("☝\u{FE0F}🏽", "☝🏽"),
+            ("👩‍👩‍👧‍👦", "👩‍👩‍👧‍👦"),
+            ("🧑🏿‍🚀", "🧑🏿‍🚀"),
+            ("❤\u{200D}🔥", "❤\u{FE0F}\u{200D}🔥"),
+            ("🏳\u{200D}🌈", "🏳\u{FE0F}\u{200D}🌈"),
+            ("🇩🇪", "🇩🇪"),
+            ("1\u{20E3}", "1\u{FE0F}\u{20E3}"),
+            ("#\u{FE0F}\u{20E3}", "#\u{FE0F}\u{20E3}"),
+            ("🏴󠁧󠁢󠁳󠁣󠁴󠁿", "🏴󠁧󠁢󠁳󠁣󠁴󠁿"),
+        ];
+        let rejected = [
+            "", "a", "1", "❤", "©", "❤\u{FE0E}", "❤🏽", "hi👍", "👍👍", "👍 👍", "👍\u{200D}", "🇩", "🇩🇪🇫",
+            "1a", "🏴󠁧󠁢", "+1", "<script>",
+        ];
+
+        for (emoji, expected) in accepted {
+            assert_eq!(normalize_emoji(emoji).as_deref(), Some(expected), "{:?}", emoji);
+        }
+        for emoji in rejected {
+            assert!(normalize_emoji(emoji).is_none(), "{:?} should be rejected", emoji);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::entities::{channels, messages, prelude::*};
+use crate::middleware::api_key_authorizer::{ApiKeyAuthorizer, ReadMessages, ReadOnlyAccess};
+use crate::middleware::usage_limiter::UsageLimiter;
+use crate::state::AppState;
+use crate::utils::ServerResponse;
+use axum::{extract::Path, extract::Query, extract::State, response::IntoResponse};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sea_orm::sea_query::{Expr, SimpleExpr};
+use sea_orm::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const DEFAULT_SEARCH_PAGE_SIZE: u64 = 20;
+const MAX_SEARCH_PAGE_SIZE: u64 = 100;
+
+// `search_vector` is a generated tsvector column (see the message_search migration).
+// It isn't part of the entity, so it's only referenced from these expressions.
+const TS_QUERY: &str = "websearch_to_tsquery('english', $1)";
+
+#[derive(Debug, Deserialize)]
+pub struct SearchMessagesQuery {
+    q: String,
+    channel_id: Option<Uuid>,
+    participant_id: Option<Uuid>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    cursor: Option<String>,
+    limit: Option<u64>,
+}
+
+#[derive(Debug, FromQueryResult)]
+struct SearchRow {
+    id: Uuid,
+    channel_id: Uuid,
+    participant_id: Uuid,
+    parent_message_id: Option<Uuid>,
+    content: String,
+    created_at: NaiveDateTime,
+    rank: f32,
+    snippet: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResultResponse {
+    id: Uuid,
+    channel_id: Uuid,
+    participant_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_message_id: Option<Uuid>,
+    content: String,
+    created_at: DateTime<Utc>,
+    rank: f32,
+    // HTML-escaped `content` with the matching terms wrapped in <mark></mark>
+    snippet: String,
+}
+
+impl From<SearchRow> for SearchResultResponse {
+    fn from(row: SearchRow) -> Self {
+        Self {
+            id: row.id,
+            channel_id: row.channel_id,
+            participant_id: row.participant_id,
+            parent_message_id: row.parent_message_id,
+            content: row.content,
+            created_at: row.created_at.and_utc(),
+            rank: row.rank,
+            snippet: row.snippet,
+        }
+    }
+}
+
+// Results are ordered by rank, so the cursor is the (rank, id) of the last result
+struct SearchCursor {
+    rank: f32,
+    id: Uuid,
+}
+
+impl SearchCursor {
+    fn parse(raw: &str) -> Option<Self> {
+        let (rank, id) = raw.split_once('_')?;
+        Some(Self {
+            rank: rank.parse().ok()?,
+            id: Uuid::parse_str(id).ok()?,
+        })
+    }
+
+    fn encode(result: &SearchRow) -> String {
+        format!("{}_{}", result.rank, result.id)
+    }
+}
+
+fn matches_expr(q: &str) -> SimpleExpr {
+    Expr::cust_with_values(format!("\"messages\".\"search_vector\" @@ {}", TS_QUERY), [q])
+}
+
+fn rank_expr(q: &str) -> SimpleExpr {
+    Expr::cust_with_values(
+        format!("ts_rank(\"messages\".\"search_vector\", {})", TS_QUERY),
+        [q],
+    )
+}
+
+// Messages are plain text, they're HTML-escaped before the matches are marked so the
+// snippet is safe to render as HTML. `&` goes first to not escape the other entities.
+const ESCAPED_CONTENT: &str =
+    "replace(replace(replace(\"messages\".\"content\", '&', '&amp;'), '<', '&lt;'), '>', '&gt;')";
+
+fn snippet_expr(q: &str) -> SimpleExpr {
+    Expr::cust_with_values(
+        format!(
+            "ts_headline('english', {}, {}, 'StartSel=<mark>, StopSel=</mark>, MaxFragments=2')",
+            ESCAPED_CONTENT, TS_QUERY
+        ),
+        [q],
+    )
+}
+
+pub async fn search_messages(
+    State(state): State<AppState>,
+    auth: ApiKeyAuthorizer<ReadOnlyAccess, ReadMessages>,
+    _: UsageLimiter,
+    Path(org_id): Path<Uuid>,
+    Query(params): Query<SearchMessagesQuery>,
+) -> impl IntoResponse {
+    let db = &state.db.connection;
+
+    let q = params.q.trim();
+    if q.is_empty() {
+        return ServerResponse::bad_request("Search query `q` must not be empty");
+    }
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_PAGE_SIZE)
+        .clamp(1, MAX_SEARCH_PAGE_SIZE);
+
+    // The channel join keeps results inside the organization of the API key,
+    // keys restricted to channels only search those
+    let mut query = Messages::find()
+        .select_only()
+        .columns([
+            messages::Column::Id,
+            messages::Column::ChannelId,
+            messages::Column::ParticipantId,
+            messages::Column::ParentMessageId,
+            messages::Column::Content,
+            messages::Column::CreatedAt,
+        ])
+        .column_as(rank_expr(q), "rank")
+        .column_as(snippet_expr(q), "snippet")
+        .join(JoinType::InnerJoin, messages::Relation::Channels.def())
+        .filter(channels::Column::OrganizationId.eq(org_id))
+        .filter(messages::Column::DeletedAt.is_null())
+        .filter(matches_expr(q));
+
+    if let Some(channel_ids) = auth.channel_ids {
+        query = query.filter(messages::Column::ChannelId.is_in(channel_ids));
+    }
+    if let Some(channel_id) = params.channel_id {
+        query = query.filter(messages::Column::ChannelId.eq(channel_id));
+    }
+    if let Some(participant_id) = params.participant_id {
+        query = query.filter(messages::Column::ParticipantId.eq(participant_id));
+    }
+    if let Some(from) = params.from {
+        query = query.filter(messages::Column::CreatedAt.gte(from.naive_utc()));
+    }
+    if let Some(to) = params.to {
+        query = query.filter(messages::Column::CreatedAt.lt(to.naive_utc()));
+    }
+
+    if let Some(raw_cursor) = &params.cursor {
+        let Some(cursor) = SearchCursor::parse(raw_cursor) else {
+            return ServerResponse::bad_request("Invalid cursor");
+        };
+
+        query = query.filter(
+            Condition::any()
+                .add(Expr::expr(rank_expr(q)).lt(cursor.rank))
+                .add(
+                    Condition::all()
+                        .add(Expr::expr(rank_expr(q)).eq(cursor.rank))
+                        .add(messages::Column::Id.lt(cursor.id)),
+                ),
+        );
+    }
+
+    // One extra row tells us whether there is another page
+    match query
+        .order_by(rank_expr(q), Order::Desc)
+        .order_by_desc(messages::Column::Id)
+        .limit(limit + 1)
+        .into_model::<SearchRow>()
+        .all(db)
+        .await
+    {
+        Ok(mut rows) => {
+            let has_more = rows.len() as u64 > limit;
+            rows.truncate(limit as usize);
+
+            let next_cursor = rows.last().filter(|_| has_more).map(SearchCursor::encode);
+            let results = rows
+                .into_iter()
+                .map(SearchResultResponse::from)
+                .collect::<Vec<_>>();
+
+            ServerResponse::paginated(results, next_cursor, None)
+        }
+        Err(err) => ServerResponse::server_error(err, "Failed to search messages"),
+    }
+}
@@ -1,59 +1,345 @@
+use super::channel_members::can_access_channel;
+use super::messages::{is_valid_thread_parent, persist_message};
+use super::participants::find_org_participant;
+use crate::entities::messages;
+use crate::entities::sea_orm_active_enums::ApiKeyType;
+use crate::middleware::api_key_authorizer::{ensure_key_type, ensure_scope, ApiKeyScope};
+use crate::middleware::error::MiddlewareError;
+use crate::middleware::client_authorizer::ClientCredential;
+use crate::middleware::revalidate_key;
+use crate::middleware::socket_authorizer::SocketAuthorizer;
+use crate::middleware::usage_limiter::enforce_usage_limit;
+use crate::middleware::usage_tracker::track_api_usage;
+use crate::realtime::{
+    publish_event, room_channel, AckPayload, ClientEvent, ErrorCode, ErrorPayload,
+    PresencePayload, PresenceStatus, ServerEvent, TypingPayload,
+};
 use crate::state::AppState;
-use axum::extract::ws::{Message, WebSocket};
+use crate::utils::ServerResponse;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
 use axum::{
-    extract::{Path, State, WebSocketUpgrade},
+    extract::{State, WebSocketUpgrade},
     response::IntoResponse,
 };
+use chrono::Utc;
 use futures::{SinkExt, StreamExt};
-use redis::AsyncCommands;
-use serde::{Deserialize, Serialize};
-use tracing::{error, info};
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ClientSideChatMessage {
-    participant_id: String,
-    channel_name: String,
-    content: String,
-}
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{interval_at, Instant};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+// Replies that are only meant for the current socket (ack, error, pong)
+// are queued here and written by the same task that forwards Redis events
+const DIRECT_REPLY_BUFFER: usize = 32;
 
+// How often open sockets check that their credentials and channel access still hold
+const ACCESS_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+// Credentials are checked by `SocketAuthorizer` before the upgrade happens,
+// so unauthorized clients get a regular HTTP error instead of a socket
 pub async fn chat_ws_handler(
-    ws: WebSocketUpgrade,
     State(state): State<AppState>,
-    Path(room_id): Path<String>,
+    auth: SocketAuthorizer,
+    ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_socket_connection(socket, state, room_id))
+    match &auth.credential {
+        ClientCredential::ApiKey { key_type, .. } => {
+            info!("Socket authorized with {:?} API key for organization {}", key_type, auth.organization_id)
+        }
+        ClientCredential::Participant { participant_id, .. } => {
+            info!("Socket authorized for participant {} in organization {}", participant_id, auth.organization_id)
+        }
+    }
+
+    if !auth.credential.allows_channel(&auth.channel.id) {
+        return ServerResponse::forbidden("Credentials are not valid for this channel");
+    }
+
+    // Participants can only listen to private channels they are a member of.
+    // API key connections name a participant per frame, which is checked in `ensure_member`.
+    if let Some(participant_id) = auth.credential.participant_id() {
+        match can_access_channel(&state.db.connection, &auth.channel, participant_id).await {
+            Ok(true) => {}
+            Ok(false) => return ServerResponse::forbidden("Participant is not a member of this channel"),
+            Err(err) => return ServerResponse::server_error(err, "Failed to check channel membership"),
+        }
+    }
+
+    ws.on_upgrade(|socket| handle_socket_connection(socket, state, auth))
 }
 
-#[derive(Debug, Serialize)]
-struct ActiveUsersUpdate {
-    type_: &'static str,
-    count: i64,
+// Participant tokens carry their own identity, API key connections have to name
+// the participant they are acting for in every frame
+fn resolve_participant(
+    auth: &SocketAuthorizer,
+    requested: Option<Uuid>,
+) -> Result<Uuid, ErrorPayload> {
+    auth.credential.participant_id().or(requested).ok_or_else(|| {
+        ErrorPayload::new(ErrorCode::MissingParticipant, "participant_id is required")
+    })
 }
 
-async fn broadcast_to_all_rooms(state: &AppState, message: &str) -> Result<(), redis::RedisError> {
-    let mut conn = state.redis.client.get_async_connection().await?;
-    conn.publish::<_, _, ()>("chat:broadcast", message).await?;
-    Ok(())
+// Membership is checked for every frame, so a removed member can't send from a socket
+// opened before. What they receive is cut off by `check_access`. API key connections
+// need a key that may write, and the participant they name has to belong to the key's organization.
+async fn ensure_member(
+    state: &AppState,
+    auth: &SocketAuthorizer,
+    participant_id: Uuid,
+) -> Result<(), ErrorPayload> {
+    if let ClientCredential::ApiKey { key_type, scopes, .. } = &auth.credential {
+        if ensure_key_type(key_type, &ApiKeyType::ReadWrite).is_err()
+            || ensure_scope(scopes, ApiKeyScope::MessagesWrite).is_err()
+        {
+            return Err(ErrorPayload::new(
+                ErrorCode::InsufficientPermissions,
+                "A read-write API key with the `messages:write` scope is required to send on this socket",
+            ));
+        }
+
+        match find_org_participant(&state.db.connection, auth.organization_id, participant_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return Err(ErrorPayload::new(
+                    ErrorCode::MissingParticipant,
+                    "Participant not found in this organization",
+                ))
+            }
+            Err(e) => {
+                error!("Failed to check participant: {}", e);
+                return Err(ErrorPayload::new(ErrorCode::MessageRejected, "Failed to check participant"));
+            }
+        }
+    }
+
+    match can_access_channel(&state.db.connection, &auth.channel, participant_id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(ErrorPayload::new(
+            ErrorCode::NotChannelMember,
+            "Participant is not a member of this channel",
+        )),
+        Err(e) => {
+            error!("Failed to check channel membership: {}", e);
+            Err(ErrorPayload::new(ErrorCode::MessageRejected, "Failed to check channel membership"))
+        }
+    }
 }
 
-async fn handle_socket_connection(socket: WebSocket, state: AppState, room_id: String) {
-    let (mut sender, mut receiver) = socket.split();
+// Sockets stay open much longer than a token may live or a key may stay unrevoked,
+// so they check again every `ACCESS_CHECK_INTERVAL`. Database errors keep the socket
+// open, they say nothing about the credentials.
+pub(crate) async fn check_access(state: &AppState, auth: &SocketAuthorizer) -> Result<(), ErrorPayload> {
+    let db = &state.db.connection;
+    let revoked = |message: &str| ErrorPayload::new(ErrorCode::AccessRevoked, message);
 
-    // Increment global active users count
-    {
-        let mut count = state.active_users.write().await;  // Now this will work
-        *count += 1;
+    match &auth.credential {
+        ClientCredential::ApiKey { key_id, key_hmac, .. } => {
+            let key = match revalidate_key(state, *key_id, key_hmac).await {
+                Ok(key) => key,
+                Err(MiddlewareError::DatabaseError(e)) => {
+                    error!("Failed to check API key {} of an open socket: {}", key_id, e);
+                    return Ok(());
+                }
+                Err(e) => return Err(revoked(&e.to_string())),
+            };
+
+            // Scopes and channels can be changed without rotating the key
+            let may_listen = ensure_scope(&key.scopes, ApiKeyScope::MessagesRead).is_ok()
+                && key
+                    .channel_ids
+                    .as_ref()
+                    .is_none_or(|channel_ids| channel_ids.contains(&auth.channel.id));
+            if !may_listen {
+                return Err(revoked("The API key may no longer read this channel"));
+            }
+            Ok(())
+        }
+        ClientCredential::Participant {
+            participant_id,
+            expires_at,
+            ..
+        } => {
+            if Utc::now().timestamp() >= *expires_at {
+                return Err(revoked("Participant token has expired"));
+            }
 
-        let update = ActiveUsersUpdate {
-            type_: "active_users",
-            count: *count,
-        };
-        if let Ok(json) = serde_json::to_string(&update) {
-            if let Err(e) = broadcast_to_all_rooms(&state, &json).await {
-                error!("Failed to broadcast active users update: {}", e);
+            match find_org_participant(db, auth.organization_id, *participant_id).await {
+                Ok(Some(_)) => {}
+                Ok(None) => return Err(revoked("Participant has been deleted")),
+                Err(e) => {
+                    error!("Failed to check participant {} of an open socket: {}", participant_id, e);
+                    return Ok(());
+                }
+            }
+
+            match can_access_channel(db, &auth.channel, *participant_id).await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(revoked("Participant is no longer a member of this channel")),
+                Err(e) => {
+                    error!("Failed to check channel membership of an open socket: {}", e);
+                    Ok(())
+                }
             }
         }
     }
+}
+
+// Presence is kept per room in Redis, so every instance reports the same count
+async fn publish_presence(
+    state: &AppState,
+    auth: &SocketAuthorizer,
+    socket_id: &Uuid,
+    status: PresenceStatus,
+) {
+    let active_users = match status {
+        PresenceStatus::Joined => state.redis.refresh_presence(&auth.channel.id, socket_id).await,
+        PresenceStatus::Left => state.redis.remove_presence(&auth.channel.id, socket_id).await,
+    };
+    let active_users = match active_users {
+        Ok(count) => count,
+        Err(e) => {
+            error!("Failed to update presence in room {}: {}", auth.channel.id, e);
+            return;
+        }
+    };
+
+    let event = ServerEvent::Presence(PresencePayload {
+        channel_id: auth.channel.id,
+        participant_id: auth.credential.participant_id(),
+        status,
+        active_users,
+    });
+
+    if let Err(e) = publish_event(&state.redis, &auth.channel.id, &event).await {
+        error!("Failed to publish presence update: {}", e);
+    }
+}
+
+// Stores the message and accounts for it exactly like `create_message` does,
+// `persist_message` then fans it out to the subscribers of the room
+async fn publish_client_message(
+    state: &AppState,
+    auth: &SocketAuthorizer,
+    participant_id: Uuid,
+    content: String,
+    parent_message_id: Option<Uuid>,
+) -> Result<messages::Model, ErrorPayload> {
+    enforce_usage_limit(state, &auth.organization_id)
+        .await
+        .map_err(|e| match e {
+            MiddlewareError::UsageLimitExceeded(msg) => {
+                ErrorPayload::new(ErrorCode::UsageLimitExceeded, msg)
+            }
+            other => ErrorPayload::new(ErrorCode::MessageRejected, other.to_string()),
+        })?;
+
+    if let Some(parent_message_id) = parent_message_id {
+        match is_valid_thread_parent(&state.db.connection, auth.channel.id, parent_message_id).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err(ErrorPayload::new(
+                    ErrorCode::MessageRejected,
+                    "Parent message not found in this channel",
+                ))
+            }
+            Err(e) => {
+                error!("Failed to check parent message: {}", e);
+                return Err(ErrorPayload::new(ErrorCode::MessageRejected, "Failed to store message"));
+            }
+        }
+    }
+
+    let message = persist_message(state, auth.channel.id, participant_id, content, parent_message_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to store socket message: {}", e);
+            ErrorPayload::new(ErrorCode::MessageRejected, "Failed to store message")
+        })?;
+
+    let tracking_state = state.clone();
+    let org_id = auth.organization_id;
+    tokio::spawn(async move {
+        if let Err(e) = track_api_usage(&tracking_state, &org_id).await {
+            error!("Background usage tracking failed: {}", e);
+        }
+    });
+
+    Ok(message)
+}
+
+// Handles one text frame and returns the reply meant for this socket only, if any
+async fn handle_client_frame(
+    state: &AppState,
+    auth: &SocketAuthorizer,
+    text: &str,
+) -> Option<ServerEvent> {
+    let event = match ClientEvent::parse(text) {
+        Ok(event) => event,
+        Err(e) => return Some(ServerEvent::Error(e)),
+    };
+
+    let result = match event {
+        ClientEvent::Ping => return Some(ServerEvent::Pong),
+        ClientEvent::MessageCreate(payload) => {
+            let participant_id = match resolve_participant(auth, payload.participant_id) {
+                Ok(id) => id,
+                Err(e) => return Some(ServerEvent::Error(e)),
+            };
+            if let Err(e) = ensure_member(state, auth, participant_id).await {
+                return Some(ServerEvent::Error(e));
+            }
+
+            publish_client_message(
+                state,
+                auth,
+                participant_id,
+                payload.content,
+                payload.parent_message_id,
+            )
+            .await
+            .map(|message| {
+                Some(ServerEvent::Ack(AckPayload {
+                    client_id: payload.client_id,
+                    message_id: message.id,
+                }))
+            })
+        }
+        ClientEvent::Typing(payload) => {
+            let participant_id = match resolve_participant(auth, payload.participant_id) {
+                Ok(id) => id,
+                Err(e) => return Some(ServerEvent::Error(e)),
+            };
+            if let Err(e) = ensure_member(state, auth, participant_id).await {
+                return Some(ServerEvent::Error(e));
+            }
+
+            // Typing indicators are ephemeral, so they skip the database entirely
+            let event = ServerEvent::Typing(TypingPayload {
+                channel_id: auth.channel.id,
+                participant_id,
+                is_typing: payload.is_typing,
+            });
+            if let Err(e) = publish_event(&state.redis, &auth.channel.id, &event).await {
+                error!("Failed to publish typing indicator: {}", e);
+            }
+
+            Ok(None)
+        }
+    };
+
+    match result {
+        Ok(reply) => reply,
+        Err(e) => {
+            warn!("Rejected frame in room {}: {}", auth.channel.id, e.message);
+            Some(ServerEvent::Error(e))
+        }
+    }
+}
+
+async fn handle_socket_connection(socket: WebSocket, state: AppState, auth: SocketAuthorizer) {
+    let room_id = auth.channel.id.to_string();
+    let (mut sender, mut receiver) = socket.split();
 
     // Get Redis connection
     let redis = state.redis.clone();
@@ -67,7 +353,7 @@ async fn handle_socket_connection(socket: WebSocket, state: AppState, room_id: S
 
     // Create Redis PubSub
     let mut pubsub = redis_conn.into_pubsub();
-    let channel = format!("chat:{}", room_id);
+    let channel = room_channel(&auth.channel.id);
 
     // Subscribe to room channel
     if let Err(e) = pubsub.subscribe(&channel).await {
@@ -75,52 +361,88 @@ async fn handle_socket_connection(socket: WebSocket, state: AppState, room_id: S
         return;
     }
 
+    // Increment global active users count
+    {
+        let mut count = state.active_users.write().await;
+        *count += 1;
+    }
+    let socket_id = Uuid::new_v4();
+    publish_presence(&state, &auth, &socket_id, PresenceStatus::Joined).await;
+
     info!("Client connected to room: {}", room_id);
 
+    let (reply_tx, mut reply_rx) = mpsc::channel::<ServerEvent>(DIRECT_REPLY_BUFFER);
+
     // Handle incoming WebSocket messages
-    let redis_publisher = redis.client.clone();
+    let recv_state = state.clone();
+    let recv_auth = auth.clone();
     let mut recv_task = tokio::spawn(async move {
         while let Some(result) = receiver.next().await {
-            match result {
-                Ok(Message::Text(text)) => {
-                    match serde_json::from_str::<ClientSideChatMessage>(&text) {
-                        Ok(msg) => {
-                            if let Ok(json) = serde_json::to_string(&msg) {
-                                println!("Publishing message: {}", json);
-                                let mut conn = redis_publisher
-                                    .get_async_connection()
-                                    .await
-                                    .expect("Failed to get Redis connection");
-
-                                match conn.publish::<_, _, ()>(&channel, json).await {
-                                    Ok(_) => println!("Successfully published to {}", channel),
-                                    Err(e) => error!("Failed to publish to Redis: {}", e),
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to parse message: {}", e);
-                        }
-                    }
-                }
+            let reply = match result {
+                Ok(Message::Text(text)) => handle_client_frame(&recv_state, &recv_auth, &text).await,
+                Ok(Message::Binary(_)) => Some(ServerEvent::Error(ErrorPayload::new(
+                    ErrorCode::InvalidFrame,
+                    "Binary frames are not supported",
+                ))),
                 Ok(Message::Close(_)) => break,
                 Err(e) => {
                     error!("WebSocket error: {}", e);
                     break;
                 }
-                _ => {}
+                _ => None,
+            };
+
+            if let Some(reply) = reply {
+                if reply_tx.send(reply).await.is_err() {
+                    break;
+                }
             }
         }
     });
 
-    // Handle messages from Redis and send them to WebSocket
+    // Forward events from Redis and direct replies to the WebSocket.
+    // Redis payloads are already serialized `ServerEvent`s, see `publish_event`.
+    let send_state = state.clone();
+    let send_auth = auth.clone();
     let mut send_task = tokio::spawn(async move {
         let mut pubsub_stream = pubsub.on_message();
+        let mut access_check = interval_at(Instant::now() + ACCESS_CHECK_INTERVAL, ACCESS_CHECK_INTERVAL);
 
-        while let Some(msg) = pubsub_stream.next().await {
-            let payload: String = msg.get_payload().unwrap_or_default();
+        loop {
+            let frame = tokio::select! {
+                _ = access_check.tick() => match check_access(&send_state, &send_auth).await {
+                    Ok(()) => {
+                        if let Err(e) = send_state.redis.refresh_presence(&send_auth.channel.id, &socket_id).await {
+                            warn!("Failed to refresh presence in room {}: {}", send_auth.channel.id, e);
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        info!("Closing socket in room {}: {}", send_auth.channel.id, e.message);
+                        if let Ok(json) = ServerEvent::Error(e).to_json() {
+                            let _ = sender.send(Message::Text(json)).await;
+                        }
+                        let _ = sender
+                            .send(Message::Close(Some(CloseFrame {
+                                code: close_code::POLICY,
+                                reason: "access revoked".into(),
+                            })))
+                            .await;
+                        break;
+                    }
+                },
+                Some(msg) = pubsub_stream.next() => msg.get_payload::<String>().unwrap_or_default(),
+                Some(reply) = reply_rx.recv() => match reply.to_json() {
+                    Ok(json) => json,
+                    Err(e) => {
+                        error!("Failed to serialize reply: {}", e);
+                        continue;
+                    }
+                },
+                else => break,
+            };
 
-            if let Err(e) = sender.send(Message::Text(payload)).await {
+            if let Err(e) = sender.send(Message::Text(frame)).await {
                 error!("Failed to send WebSocket message: {}", e);
                 break;
             }
@@ -135,20 +457,10 @@ async fn handle_socket_connection(socket: WebSocket, state: AppState, room_id: S
 
     // Decrement active users count on disconnect
     {
-        let mut count = state.active_users.write().await; // Added .await
+        let mut count = state.active_users.write().await;
         *count -= 1;
-
-        // Broadcast updated count
-        let update = ActiveUsersUpdate {
-            type_: "active_users",
-            count: *count,
-        };
-        if let Ok(json) = serde_json::to_string(&update) {
-            if let Err(e) = broadcast_to_all_rooms(&state, &json).await {
-                error!("Failed to broadcast active users update: {}", e);
-            }
-        }
     }
+    publish_presence(&state, &auth, &socket_id, PresenceStatus::Left).await;
 
     info!("Client disconnected from room: {}", room_id);
 }
@@ -0,0 +1,463 @@
+use crate::config::billing::ENDED_SUBSCRIPTION_STATUSES;
+use crate::config::plans::{plan_by_name, plan_for_tier, Plan};
+use crate::entities::prelude::{OrganizationTiers, Organizations, StripeWebhookEvents};
+use crate::entities::sea_orm_active_enums::OrganizationTier;
+use crate::entities::{organization_tiers, organizations, stripe_webhook_events};
+use crate::state::AppState;
+use crate::utils::{verify_stripe_signature, ServerResponse};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Stripe stopped collecting for these but the subscription still exists. The
+// organization only gets the free tier until a payment brings it back to `active`.
+// `past_due` keeps the tier while Stripe retries the payment.
+const LAPSED_SUBSCRIPTION_STATUSES: [&str; 2] = ["unpaid", "paused"];
+
+// Only the fields we use, so that changes to Stripe's API versions don't break parsing
+#[derive(Debug, Deserialize)]
+struct StripeEvent {
+    id: String,
+    #[serde(rename = "type")]
+    event_type: String,
+    // Unix time, the only way to tell which of two events is newer
+    created: i64,
+    data: StripeEventData,
+}
+
+impl StripeEvent {
+    fn created_at(&self) -> NaiveDateTime {
+        DateTime::from_timestamp(self.created, 0)
+            .unwrap_or_default()
+            .naive_utc()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeEventData {
+    object: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeSubscription {
+    id: String,
+    customer: String,
+    status: String,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    items: StripeList<StripeSubscriptionItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeList<T> {
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeSubscriptionItem {
+    id: String,
+    price: StripePrice,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripePrice {
+    lookup_key: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeInvoice {
+    customer: String,
+    // Moved to `parent.subscription_details` in newer API versions
+    subscription: Option<String>,
+    parent: Option<StripeInvoiceParent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeInvoiceParent {
+    subscription_details: Option<StripeInvoiceSubscriptionDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeInvoiceSubscriptionDetails {
+    subscription: Option<String>,
+}
+
+impl StripeInvoice {
+    fn subscription_id(&self) -> Option<&str> {
+        self.subscription.as_deref().or_else(|| {
+            self.parent
+                .as_ref()?
+                .subscription_details
+                .as_ref()?
+                .subscription
+                .as_deref()
+        })
+    }
+}
+
+enum WebhookEvent {
+    SubscriptionUpdated(StripeSubscription),
+    SubscriptionDeleted(StripeSubscription),
+    InvoicePaymentFailed(StripeInvoice),
+    InvoicePaid(StripeInvoice),
+    Other,
+}
+
+impl WebhookEvent {
+    fn parse(event: &StripeEvent) -> Result<Self, serde_json::Error> {
+        let object = event.data.object.clone();
+
+        Ok(match event.event_type.as_str() {
+            // Checkout creates a new subscription, otherwise they only get updated
+            "customer.subscription.created" | "customer.subscription.updated" => {
+                Self::SubscriptionUpdated(serde_json::from_value(object)?)
+            }
+            "customer.subscription.deleted" => Self::SubscriptionDeleted(serde_json::from_value(object)?),
+            "invoice.payment_failed" => Self::InvoicePaymentFailed(serde_json::from_value(object)?),
+            "invoice.paid" => Self::InvoicePaid(serde_json::from_value(object)?),
+            _ => Self::Other,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum WebhookOutcome {
+    Processed,
+    // Already handled, Stripe delivers events at least once
+    Duplicate,
+    // Not an event we handle, or not about a subscription we know
+    Ignored,
+    // Older than an event already applied to the organization
+    Outdated,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StripeWebhookResponse {
+    event_id: String,
+    outcome: WebhookOutcome,
+}
+
+// Keeps organizations and their tiers in sync with Stripe. Stripe retries on any
+// non-2xx response, so only failures worth retrying return one after the
+// signature has been verified.
+pub async fn stripe_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let secret = match std::env::var("STRIPE_WEBHOOK_SECRET") {
+        Ok(secret) => secret,
+        Err(err) => return ServerResponse::server_error(err, "Stripe webhooks are not configured"),
+    };
+
+    let Some(signature) = headers
+        .get("Stripe-Signature")
+        .and_then(|value| value.to_str().ok())
+    else {
+        return ServerResponse::bad_request("Missing Stripe-Signature header");
+    };
+
+    if let Err(err) = verify_stripe_signature(&body, signature, &secret, Utc::now().timestamp()) {
+        return ServerResponse::bad_request(err.to_string());
+    }
+
+    let event: StripeEvent = match serde_json::from_slice(&body) {
+        Ok(event) => event,
+        Err(err) => return ServerResponse::bad_request(format!("Invalid event: {}", err)),
+    };
+    let parsed = match WebhookEvent::parse(&event) {
+        Ok(parsed) => parsed,
+        Err(err) => return ServerResponse::bad_request(format!("Invalid {}: {}", event.event_type, err)),
+    };
+
+    match process_event(&state.db.connection, &event, parsed).await {
+        Ok(outcome) => {
+            tracing::info!("Stripe event {} ({}): {:?}", event.id, event.event_type, outcome);
+            ServerResponse::ok(StripeWebhookResponse {
+                event_id: event.id,
+                outcome,
+            })
+        }
+        Err(err) => ServerResponse::server_error(err, "Failed to process Stripe event"),
+    }
+}
+
+// Records the event and applies it in one transaction, so an event is either
+// applied exactly once or not at all and retried
+async fn process_event(
+    db: &DatabaseConnection,
+    event: &StripeEvent,
+    parsed: WebhookEvent,
+) -> Result<WebhookOutcome, DbErr> {
+    let txn = db.begin().await?;
+
+    let recorded = StripeWebhookEvents::insert(stripe_webhook_events::ActiveModel {
+        id: Set(event.id.clone()),
+        event_type: Set(event.event_type.clone()),
+        processed_at: Set(Utc::now().naive_utc()),
+    })
+    .on_conflict(
+        OnConflict::column(stripe_webhook_events::Column::Id)
+            .do_nothing()
+            .to_owned(),
+    )
+    .exec_without_returning(&txn)
+    .await?;
+
+    if recorded == 0 {
+        txn.rollback().await?;
+        return Ok(WebhookOutcome::Duplicate);
+    }
+
+    let created_at = event.created_at();
+    let outcome = match parsed {
+        WebhookEvent::SubscriptionUpdated(subscription) => {
+            subscription_updated(&txn, &subscription, created_at).await?
+        }
+        WebhookEvent::SubscriptionDeleted(subscription) => {
+            subscription_deleted(&txn, &subscription, created_at).await?
+        }
+        WebhookEvent::InvoicePaymentFailed(invoice) => {
+            invoice_settled(&txn, &invoice, "past_due", created_at).await?
+        }
+        WebhookEvent::InvoicePaid(invoice) => {
+            invoice_settled(&txn, &invoice, "active", created_at).await?
+        }
+        WebhookEvent::Other => WebhookOutcome::Ignored,
+    };
+
+    txn.commit().await?;
+    Ok(outcome)
+}
+
+// Locked, so that of two events for the same organization the older one sees
+// the newer one's timestamp
+async fn find_organization_by_customer(
+    txn: &DatabaseTransaction,
+    customer_id: &str,
+) -> Result<Option<organizations::Model>, DbErr> {
+    let organization = Organizations::find()
+        .filter(organizations::Column::StripeCustomerId.eq(customer_id))
+        .lock_exclusive()
+        .one(txn)
+        .await?;
+
+    if organization.is_none() {
+        tracing::warn!("No organization for Stripe customer {}", customer_id);
+    }
+    Ok(organization)
+}
+
+// Events from the same second can't be ordered and are applied as they come
+fn is_outdated(organization: &organizations::Model, created_at: NaiveDateTime) -> bool {
+    organization
+        .stripe_event_created_at
+        .is_some_and(|last| created_at < last)
+}
+
+// The tier is taken from the price the subscription is on, its `tier` metadata and
+// then its lookup key. Plan changes made outside the API, e.g. in the Billing Portal,
+// only swap the price, so the subscription's `tier` metadata is just a fallback.
+fn subscription_plan(subscription: &StripeSubscription) -> Option<&'static Plan> {
+    let price = subscription.items.data.first().map(|item| &item.price);
+
+    price
+        .and_then(|price| price.metadata.get("tier"))
+        .or_else(|| price?.lookup_key.as_ref())
+        .or_else(|| subscription.metadata.get("tier"))
+        .and_then(|name| plan_by_name(name))
+}
+
+// A `monthly_request_limit` override only belongs to the plan the subscription
+// metadata names, it's dropped once the price moved to another plan
+fn monthly_request_limit(subscription: &StripeSubscription, plan: &Plan) -> i64 {
+    let names_other_plan = subscription
+        .metadata
+        .get("tier")
+        .and_then(|name| plan_by_name(name))
+        .is_some_and(|named| named.tier != plan.tier);
+    if names_other_plan {
+        return plan.monthly_request_limit;
+    }
+
+    subscription
+        .metadata
+        .get("monthly_request_limit")
+        .and_then(|limit| limit.parse::<i64>().ok())
+        .unwrap_or(plan.monthly_request_limit)
+}
+
+async fn update_tier(
+    txn: &DatabaseTransaction,
+    organization_id: uuid::Uuid,
+    tier: OrganizationTier,
+    monthly_request_limit: i64,
+) -> Result<(), DbErr> {
+    let updated = OrganizationTiers::update_many()
+        .col_expr(organization_tiers::Column::Tier, tier.as_enum())
+        .col_expr(
+            organization_tiers::Column::MonthlyRequestLimit,
+            monthly_request_limit.into(),
+        )
+        .col_expr(
+            organization_tiers::Column::UpdatedAt,
+            Utc::now().naive_utc().into(),
+        )
+        .filter(organization_tiers::Column::OrganizationId.eq(organization_id))
+        .exec(txn)
+        .await?;
+
+    if updated.rows_affected == 0 {
+        tracing::error!("Organization {} has no tier to update", organization_id);
+    }
+    Ok(())
+}
+
+async fn subscription_updated(
+    txn: &DatabaseTransaction,
+    subscription: &StripeSubscription,
+    created_at: NaiveDateTime,
+) -> Result<WebhookOutcome, DbErr> {
+    let Some(organization) = find_organization_by_customer(txn, &subscription.customer).await? else {
+        return Ok(WebhookOutcome::Ignored);
+    };
+    if is_outdated(&organization, created_at) {
+        return Ok(WebhookOutcome::Outdated);
+    }
+
+    let status = subscription.status.as_str();
+    let is_current = organization.stripe_subscription_id.as_deref() == Some(subscription.id.as_str());
+    if ENDED_SUBSCRIPTION_STATUSES.contains(&status) {
+        // Events about ended subscriptions must not touch the current one
+        if !is_current {
+            return Ok(WebhookOutcome::Ignored);
+        }
+        return end_subscription(txn, organization.id, status, created_at).await;
+    }
+
+    organizations::ActiveModel {
+        id: Set(organization.id),
+        stripe_subscription_id: Set(Some(subscription.id.clone())),
+        stripe_subscription_item_id: Set(subscription
+            .items
+            .data
+            .first()
+            .map(|item| item.id.clone())
+            .or(organization.stripe_subscription_item_id)),
+        stripe_subscription_status: Set(Some(subscription.status.clone())),
+        stripe_event_created_at: Set(Some(created_at)),
+        updated_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .update(txn)
+    .await?;
+
+    if LAPSED_SUBSCRIPTION_STATUSES.contains(&status) {
+        let free = plan_for_tier(&OrganizationTier::Free);
+        update_tier(txn, organization.id, free.tier.clone(), free.monthly_request_limit).await?;
+        return Ok(WebhookOutcome::Processed);
+    }
+
+    match subscription_plan(subscription) {
+        Some(plan) => {
+            // Enterprise contracts can carry their own limit
+            let monthly_request_limit = monthly_request_limit(subscription, plan);
+            update_tier(txn, organization.id, plan.tier.clone(), monthly_request_limit).await?;
+        }
+        None => tracing::warn!(
+            "Subscription {} doesn't name a known tier, keeping the current one",
+            subscription.id
+        ),
+    }
+
+    Ok(WebhookOutcome::Processed)
+}
+
+async fn subscription_deleted(
+    txn: &DatabaseTransaction,
+    subscription: &StripeSubscription,
+    created_at: NaiveDateTime,
+) -> Result<WebhookOutcome, DbErr> {
+    let Some(organization) = find_organization_by_customer(txn, &subscription.customer).await? else {
+        return Ok(WebhookOutcome::Ignored);
+    };
+    if is_outdated(&organization, created_at) {
+        return Ok(WebhookOutcome::Outdated);
+    }
+
+    if organization.stripe_subscription_id.as_deref() != Some(subscription.id.as_str()) {
+        return Ok(WebhookOutcome::Ignored);
+    }
+
+    end_subscription(txn, organization.id, &subscription.status, created_at).await
+}
+
+// The organization forgets the subscription and falls back to the free tier
+async fn end_subscription(
+    txn: &DatabaseTransaction,
+    organization_id: uuid::Uuid,
+    status: &str,
+    created_at: NaiveDateTime,
+) -> Result<WebhookOutcome, DbErr> {
+    organizations::ActiveModel {
+        id: Set(organization_id),
+        stripe_subscription_id: Set(None),
+        stripe_subscription_item_id: Set(None),
+        stripe_subscription_status: Set(Some(status.to_string())),
+        stripe_event_created_at: Set(Some(created_at)),
+        updated_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .update(txn)
+    .await?;
+
+    let free = plan_for_tier(&OrganizationTier::Free);
+    update_tier(txn, organization_id, free.tier.clone(), free.monthly_request_limit).await?;
+
+    Ok(WebhookOutcome::Processed)
+}
+
+// Only the status changes. A failed payment keeps the tier while Stripe retries, once it
+// gives up the subscription moves to `unpaid` or `canceled` and its update drops the tier.
+async fn invoice_settled(
+    txn: &DatabaseTransaction,
+    invoice: &StripeInvoice,
+    status: &str,
+    created_at: NaiveDateTime,
+) -> Result<WebhookOutcome, DbErr> {
+    let Some(organization) = find_organization_by_customer(txn, &invoice.customer).await? else {
+        return Ok(WebhookOutcome::Ignored);
+    };
+    if is_outdated(&organization, created_at) {
+        return Ok(WebhookOutcome::Outdated);
+    }
+
+    // One-off invoices and invoices of earlier subscriptions don't change the status
+    let Some(subscription_id) = invoice.subscription_id() else {
+        return Ok(WebhookOutcome::Ignored);
+    };
+    if organization.stripe_subscription_id.as_deref() != Some(subscription_id) {
+        return Ok(WebhookOutcome::Ignored);
+    }
+
+    organizations::ActiveModel {
+        id: Set(organization.id),
+        stripe_subscription_status: Set(Some(status.to_string())),
+        stripe_event_created_at: Set(Some(created_at)),
+        updated_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .update(txn)
+    .await?;
+
+    Ok(WebhookOutcome::Processed)
+}
@@ -0,0 +1,37 @@
+use crate::jobs::stripe_usage_reporter::retry_failed_reports;
+use crate::jobs::usage_reconciler::reconcile_organization;
+use crate::middleware::authorization::OrganizationAdmin;
+use crate::state::AppState;
+use crate::utils::ServerResponse;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct RetryStripeReportsResponse {
+    retried: u64,
+}
+
+// Compares the usage counters with the messages without repairing anything,
+// the reconciler job does that on its own schedule
+pub async fn get_usage_reconciliation(
+    State(state): State<AppState>,
+    auth: OrganizationAdmin,
+) -> impl IntoResponse {
+    match reconcile_organization(&state, auth.0.organization_id, true).await {
+        Ok(Some(reconciliation)) => ServerResponse::ok(reconciliation),
+        Ok(None) => ServerResponse::not_found("Organization has no tier"),
+        Err(err) => ServerResponse::server_error(err, "Failed to reconcile usage"),
+    }
+}
+
+// Failed reports are never sent again on their own, this queues them for the reporter
+pub async fn retry_failed_stripe_reports(
+    State(state): State<AppState>,
+    auth: OrganizationAdmin,
+) -> impl IntoResponse {
+    match retry_failed_reports(&state.db.connection, auth.0.organization_id).await {
+        Ok(retried) => ServerResponse::ok(RetryStripeReportsResponse { retried }),
+        Err(err) => ServerResponse::server_error(err, "Failed to retry usage reports"),
+    }
+}
@@ -0,0 +1,87 @@
+use crate::entities::api_keys;
+use crate::entities::prelude::ApiKeys;
+use crate::middleware::invalidate_cached_key;
+use crate::state::AppState;
+use chrono::Utc;
+use sea_orm::prelude::Expr;
+use sea_orm::*;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Revokes expired keys and drops secrets whose rotation grace period is over.
+// Expired keys are rejected on use anyway, this makes it permanent and keeps
+// them out of the cache.
+pub fn spawn_api_key_sweeper(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match sweep_expired_api_keys(&state).await {
+                Ok(0) => {}
+                Ok(swept) => tracing::info!("Swept {} expired API keys", swept),
+                Err(err) => tracing::error!("Failed to sweep expired API keys: {}", err),
+            }
+        }
+    })
+}
+
+// Returns how many keys were changed
+pub async fn sweep_expired_api_keys(state: &AppState) -> Result<u64, DbErr> {
+    let db = &state.db.connection;
+    let now = Utc::now().naive_utc();
+
+    let expired = ApiKeys::find()
+        .filter(api_keys::Column::RevokedAt.is_null())
+        .filter(api_keys::Column::ExpiresAt.lte(now))
+        .all(db)
+        .await?;
+
+    if !expired.is_empty() {
+        ApiKeys::update_many()
+            .col_expr(api_keys::Column::RevokedAt, Expr::value(now))
+            .col_expr(api_keys::Column::UpdatedAt, Expr::value(now))
+            .filter(api_keys::Column::Id.is_in(expired.iter().map(|key| key.id)))
+            .filter(api_keys::Column::RevokedAt.is_null())
+            .exec(db)
+            .await?;
+    }
+
+    // A legacy key rotated before its first use keeps its bcrypt hash as the
+    // previous secret, next to the HMAC of the current one
+    let previous_expired = ApiKeys::find()
+        .filter(api_keys::Column::PreviousKeyExpiresAt.lte(now))
+        .filter(
+            Condition::any()
+                .add(api_keys::Column::PreviousKeyHmac.is_not_null())
+                .add(
+                    Condition::all()
+                        .add(api_keys::Column::Key.is_not_null())
+                        .add(api_keys::Column::KeyHmac.is_not_null()),
+                ),
+        )
+        .all(db)
+        .await?;
+
+    for key in &previous_expired {
+        let mut cleared = api_keys::ActiveModel {
+            id: Set(key.id),
+            previous_key_hmac: Set(None),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+        if key.key_hmac.is_some() {
+            cleared.key = Set(None);
+        }
+        cleared.update(db).await?;
+    }
+
+    for key in expired.iter().chain(&previous_expired) {
+        invalidate_cached_key(state, key).await;
+    }
+
+    Ok((expired.len() + previous_expired.len()) as u64)
+}
@@ -0,0 +1,166 @@
+use crate::entities::prelude::{ApiKeyAuditEvents, ApiKeyUsage, ApiKeys};
+use crate::entities::{api_key_audit_events, api_key_usage, api_keys};
+use crate::state::AppState;
+use chrono::Utc;
+use sea_orm::prelude::Expr;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::*;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+// Keeps each insert well below Postgres' limit of bind parameters
+const DENIAL_INSERT_BATCH_SIZE: usize = 1_000;
+
+// Writes the per-key request counts buffered by the authorizers to `api_key_usage`,
+// and the requests they rejected to `api_key_audit_events`
+pub fn spawn_key_usage_flusher(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = flush_key_usage(&state).await {
+                tracing::error!("Failed to flush API key usage: {}", err);
+            }
+            if let Err(err) = flush_key_denials(&state).await {
+                tracing::error!("Failed to flush API key audit events: {}", err);
+            }
+        }
+    })
+}
+
+// Adds everything buffered to the matching rows with a single upsert. On failure
+// the counts go back into the buffer for the next flush.
+pub async fn flush_key_usage(state: &AppState) -> Result<(), DbErr> {
+    let mut counts = state.key_usage.take();
+    if counts.is_empty() {
+        return Ok(());
+    }
+
+    let db = &state.db.connection;
+
+    let key_ids: HashSet<Uuid> = counts.keys().map(|(api_key_id, _)| *api_key_id).collect();
+    let existing = match existing_key_ids(db, key_ids).await {
+        Ok(existing) => existing,
+        Err(err) => {
+            state.key_usage.restore(counts);
+            return Err(err);
+        }
+    };
+    counts.retain(|(api_key_id, _), _| existing.contains(api_key_id));
+    if counts.is_empty() {
+        return Ok(());
+    }
+
+    let now = Utc::now().naive_utc();
+    // `message_count` predates per-request counting, it holds the number of requests
+    let rows = counts.iter().map(|((api_key_id, date), count)| api_key_usage::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        api_key_id: Set(*api_key_id),
+        date: Set(*date),
+        message_count: Set(*count),
+        created_at: Set(now),
+        updated_at: Set(now),
+    });
+
+    let result = ApiKeyUsage::insert_many(rows)
+        .on_conflict(
+            OnConflict::columns([api_key_usage::Column::ApiKeyId, api_key_usage::Column::Date])
+                .value(
+                    api_key_usage::Column::MessageCount,
+                    Expr::cust("api_key_usage.message_count + excluded.message_count"),
+                )
+                .update_column(api_key_usage::Column::UpdatedAt)
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await;
+
+    if let Err(err) = result {
+        state.key_usage.restore(counts);
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+// One row per key, reason and client with the number of rejections since the last
+// flush. On failure they go back into the buffer.
+pub async fn flush_key_denials(state: &AppState) -> Result<(), DbErr> {
+    let dropped = state.key_denials.take_dropped();
+    if dropped > 0 {
+        tracing::warn!("{} rejected API key requests weren't audited, the buffer was full", dropped);
+    }
+
+    let mut denials = state.key_denials.take();
+    if denials.is_empty() {
+        return Ok(());
+    }
+
+    let db = &state.db.connection;
+
+    let key_ids: HashSet<Uuid> = denials.keys().map(|denial| denial.api_key_id).collect();
+    let existing = match existing_key_ids(db, key_ids).await {
+        Ok(existing) => existing,
+        Err(err) => {
+            state.key_denials.restore(denials);
+            return Err(err);
+        }
+    };
+    denials.retain(|denial, _| existing.contains(&denial.api_key_id));
+
+    let rows: Vec<api_key_audit_events::ActiveModel> = denials
+        .iter()
+        .map(|(denial, count)| api_key_audit_events::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            api_key_id: Set(denial.api_key_id),
+            organization_id: Set(denial.organization_id),
+            event: Set(denial.event.to_string()),
+            client_ip: Set(denial.client_ip.clone()),
+            origin: Set(denial.origin.clone()),
+            occurrences: Set(count.occurrences),
+            created_at: Set(count.first_seen_at),
+        })
+        .collect();
+
+    let result = db
+        .transaction::<_, (), DbErr>(|txn| {
+            Box::pin(async move {
+                for batch in rows.chunks(DENIAL_INSERT_BATCH_SIZE) {
+                    ApiKeyAuditEvents::insert_many(batch.to_vec())
+                        .exec_without_returning(txn)
+                        .await?;
+                }
+                Ok(())
+            })
+        })
+        .await;
+
+    if let Err(err) = result {
+        state.key_denials.restore(denials);
+        return Err(match err {
+            TransactionError::Connection(err) | TransactionError::Transaction(err) => err,
+        });
+    }
+
+    Ok(())
+}
+
+// Keys deleted since their requests were seen would fail the whole insert
+async fn existing_key_ids(
+    db: &DatabaseConnection,
+    key_ids: HashSet<Uuid>,
+) -> Result<HashSet<Uuid>, DbErr> {
+    let existing = ApiKeys::find()
+        .select_only()
+        .column(api_keys::Column::Id)
+        .filter(api_keys::Column::Id.is_in(key_ids))
+        .into_tuple::<Uuid>()
+        .all(db)
+        .await?;
+    Ok(existing.into_iter().collect())
+}
@@ -0,0 +1,9 @@
+pub(crate) mod api_key_sweeper;
+pub(crate) mod key_usage_flusher;
+pub(crate) mod stripe_usage_reporter;
+pub(crate) mod usage_reconciler;
+
+pub use api_key_sweeper::spawn_api_key_sweeper;
+pub use key_usage_flusher::{flush_key_denials, flush_key_usage, spawn_key_usage_flusher};
+pub use stripe_usage_reporter::spawn_stripe_usage_reporter;
+pub use usage_reconciler::spawn_usage_reconciler;
@@ -0,0 +1,235 @@
+use crate::entities::prelude::{StripeUsageCounters, StripeUsageReports};
+use crate::entities::{stripe_usage_counters, stripe_usage_reports};
+use crate::state::AppState;
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use sea_orm::prelude::Expr;
+use sea_orm::*;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+const REPORT_BATCH_SIZE: u64 = 100;
+// Stripe forgets idempotency keys after 24 hours, retries stop long before that
+const MAX_ATTEMPTS: i32 = 8;
+const RETRY_BASE_SECONDS: i64 = 30;
+const RETRY_MAX_SECONDS: i64 = 3600;
+
+// Sends the usage counted by the usage tracker to Stripe
+pub fn spawn_stripe_usage_reporter(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REPORT_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = report_stripe_usage(&state).await {
+                tracing::error!("Failed to report usage to Stripe: {}", err);
+            }
+        }
+    })
+}
+
+pub async fn report_stripe_usage(state: &AppState) -> Result<(), DbErr> {
+    let db = &state.db.connection;
+
+    let claimed = claim_pending_usage(db).await?;
+    let (reported, failed) = send_due_reports(state).await?;
+
+    if claimed + reported + failed > 0 {
+        tracing::info!(
+            "Stripe usage: {} reports claimed, {} sent, {} failed",
+            claimed,
+            reported,
+            failed
+        );
+    }
+    Ok(())
+}
+
+// Moves the counted usage into reports in one transaction, so every request ends up
+// in exactly one report even if the process dies halfway
+pub async fn claim_pending_usage(db: &DatabaseConnection) -> Result<u64, DbErr> {
+    let txn = db.begin().await?;
+
+    // Locked so concurrent requests wait instead of adding to a count being claimed
+    let counters = StripeUsageCounters::find()
+        .filter(stripe_usage_counters::Column::PendingQuantity.gt(0))
+        .lock_exclusive()
+        .all(&txn)
+        .await?;
+    if counters.is_empty() {
+        txn.rollback().await?;
+        return Ok(0);
+    }
+
+    let now = Utc::now().naive_utc();
+    for counter in &counters {
+        StripeUsageCounters::update_many()
+            .col_expr(
+                stripe_usage_counters::Column::PendingQuantity,
+                Expr::col(stripe_usage_counters::Column::PendingQuantity)
+                    .sub(counter.pending_quantity),
+            )
+            .col_expr(stripe_usage_counters::Column::UpdatedAt, now.into())
+            .filter(
+                stripe_usage_counters::Column::SubscriptionItemId
+                    .eq(counter.subscription_item_id.as_str()),
+            )
+            .exec(&txn)
+            .await?;
+    }
+
+    let reports = counters.iter().map(|counter| stripe_usage_reports::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        organization_id: Set(counter.organization_id),
+        subscription_item_id: Set(counter.subscription_item_id.clone()),
+        quantity: Set(counter.pending_quantity),
+        attempts: Set(0),
+        last_error: Set(None),
+        next_attempt_at: Set(now),
+        created_at: Set(now),
+        reported_at: Set(None),
+        failed_at: Set(None),
+    });
+    StripeUsageReports::insert_many(reports)
+        .exec_without_returning(&txn)
+        .await?;
+
+    txn.commit().await?;
+    Ok(counters.len() as u64)
+}
+
+// Sends the reports that are due. A report is only marked as sent after Stripe
+// accepted it, a crash in between resends it with the same idempotency key.
+async fn send_due_reports(state: &AppState) -> Result<(u64, u64), DbErr> {
+    let db = &state.db.connection;
+    let now = Utc::now().naive_utc();
+
+    let due = StripeUsageReports::find()
+        .filter(stripe_usage_reports::Column::ReportedAt.is_null())
+        .filter(stripe_usage_reports::Column::FailedAt.is_null())
+        .filter(stripe_usage_reports::Column::NextAttemptAt.lte(now))
+        .order_by_asc(stripe_usage_reports::Column::CreatedAt)
+        .limit(REPORT_BATCH_SIZE)
+        .all(db)
+        .await?;
+
+    let (mut reported, mut failed) = (0, 0);
+    for report in due {
+        let result = state
+            .billing
+            .report_usage(
+                &report.subscription_item_id,
+                report.quantity,
+                report.created_at.and_utc().timestamp(),
+                &report.id.to_string(),
+            )
+            .await;
+
+        let attempts = report.attempts + 1;
+        let mut update = stripe_usage_reports::ActiveModel {
+            id: Set(report.id),
+            attempts: Set(attempts),
+            ..Default::default()
+        };
+
+        match result {
+            Ok(()) => {
+                update.reported_at = Set(Some(Utc::now().naive_utc()));
+                update.last_error = Set(None);
+                reported += 1;
+            }
+            Err(err) => {
+                update.last_error = Set(Some(err.to_string()));
+                if err.is_retryable() && attempts < MAX_ATTEMPTS {
+                    update.next_attempt_at = Set(next_attempt_at(now, attempts));
+                } else {
+                    tracing::error!(
+                        "Giving up on usage report {} ({} requests for {}): {}",
+                        report.id,
+                        report.quantity,
+                        report.subscription_item_id,
+                        err
+                    );
+                    update.failed_at = Set(Some(Utc::now().naive_utc()));
+                    failed += 1;
+                }
+            }
+        }
+
+        update.update(db).await?;
+    }
+
+    Ok((reported, failed))
+}
+
+// Gives the reports Stripe rejected or that ran out of attempts another round. Each one
+// gets a new id, its idempotency key, since Stripe would answer a reused key with the
+// failure it remembered. They are billed now, the period they were counted in may be closed.
+pub async fn retry_failed_reports(db: &DatabaseConnection, organization_id: Uuid) -> Result<u64, DbErr> {
+    let txn = db.begin().await?;
+
+    let failed = StripeUsageReports::find()
+        .filter(stripe_usage_reports::Column::OrganizationId.eq(organization_id))
+        .filter(stripe_usage_reports::Column::ReportedAt.is_null())
+        .filter(stripe_usage_reports::Column::FailedAt.is_not_null())
+        .lock_exclusive()
+        .all(&txn)
+        .await?;
+
+    let now = Utc::now().naive_utc();
+    for report in &failed {
+        StripeUsageReports::update_many()
+            .col_expr(stripe_usage_reports::Column::Id, Expr::value(Uuid::new_v4()))
+            .col_expr(stripe_usage_reports::Column::Attempts, Expr::value(0))
+            .col_expr(
+                stripe_usage_reports::Column::LastError,
+                Expr::value(Option::<String>::None),
+            )
+            .col_expr(stripe_usage_reports::Column::NextAttemptAt, now.into())
+            .col_expr(stripe_usage_reports::Column::CreatedAt, now.into())
+            .col_expr(
+                stripe_usage_reports::Column::FailedAt,
+                Option::<NaiveDateTime>::None.into(),
+            )
+            .filter(stripe_usage_reports::Column::Id.eq(report.id))
+            .exec(&txn)
+            .await?;
+    }
+
+    txn.commit().await?;
+
+    if !failed.is_empty() {
+        tracing::info!(
+            "Retrying {} failed usage reports of {}",
+            failed.len(),
+            organization_id
+        );
+    }
+    Ok(failed.len() as u64)
+}
+
+// Doubles the wait after every attempt, up to an hour
+fn next_attempt_at(now: NaiveDateTime, attempts: i32) -> NaiveDateTime {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    let seconds = RETRY_BASE_SECONDS
+        .saturating_mul(2_i64.pow(exponent))
+        .min(RETRY_MAX_SECONDS);
+    now + ChronoDuration::seconds(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_back_off_up_to_an_hour() {
+        let now = Utc::now().naive_utc();
+        let waits: Vec<i64> = (1..=MAX_ATTEMPTS)
+            .map(|attempts| (next_attempt_at(now, attempts) - now).num_seconds())
+            .collect();
+
+        assert_eq!(waits, vec![30, 60, 120, 240, 480, 960, 1920, 3600]);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::entities::prelude::{
+    Messages, OrganizationTiers, Organizations, StripeUsageCounters, StripeUsageReports,
+};
+use crate::entities::{
+    channels, messages, organization_tiers, stripe_usage_counters, stripe_usage_reports,
+};
+use crate::state::AppState;
+use chrono::{DateTime, Datelike, Days, NaiveDateTime, NaiveTime, Utc};
+use sea_orm::prelude::Expr;
+use sea_orm::sea_query::SimpleExpr;
+use sea_orm::*;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+// How far each counter is off from the messages, positive when it counts too many
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReconciliation {
+    pub organization_id: Uuid,
+    pub month: String,
+    // Messages created this month, the source of truth
+    pub messages: i64,
+    // None when Redis couldn't be read
+    pub redis_usage: Option<i64>,
+    pub redis_drift: Option<i64>,
+    pub database_usage: i64,
+    pub database_drift: i64,
+    // None without a subscription or when the billing provider couldn't be reached
+    pub stripe: Option<StripeUsageCheck>,
+    pub repaired: bool,
+}
+
+// Stripe can't take usage back, so it is only compared, never repaired
+#[derive(Debug, Clone, Serialize)]
+pub struct StripeUsageCheck {
+    pub period_start: NaiveDateTime,
+    // Messages since the billing period started
+    pub messages: i64,
+    pub billed: i64,
+    // Counted but not reported yet
+    pub pending: i64,
+    // Reports Stripe won't take without a retry, they stay in the drift until then
+    pub failed: i64,
+    pub drift: i64,
+}
+
+impl UsageReconciliation {
+    pub fn has_drifted(&self) -> bool {
+        self.redis_drift.unwrap_or(0) != 0
+            || self.database_drift != 0
+            || self.stripe.as_ref().is_some_and(|stripe| stripe.drift != 0)
+    }
+}
+
+// Brings the Redis and database usage counters back in line with the messages
+pub fn spawn_usage_reconciler(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RECONCILE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = reconcile_usage(&state, false).await {
+                tracing::error!("Failed to reconcile usage: {}", err);
+            }
+        }
+    })
+}
+
+// Reconciles every organization with a tier. With `dry_run` nothing is repaired.
+pub async fn reconcile_usage(
+    state: &AppState,
+    dry_run: bool,
+) -> Result<Vec<UsageReconciliation>, DbErr> {
+    let organization_ids: Vec<Uuid> = OrganizationTiers::find()
+        .select_only()
+        .column(organization_tiers::Column::OrganizationId)
+        .into_tuple()
+        .all(&state.db.connection)
+        .await?;
+
+    let mut results = Vec::with_capacity(organization_ids.len());
+    for organization_id in organization_ids {
+        if let Some(result) = reconcile_organization(state, organization_id, dry_run).await? {
+            results.push(result);
+        }
+    }
+
+    let drifted = results.iter().filter(|result| result.has_drifted()).count();
+    if drifted > 0 {
+        tracing::warn!(
+            "Usage reconciliation: {} of {} organizations drifted{}",
+            drifted,
+            results.len(),
+            if dry_run { " (dry run)" } else { "" }
+        );
+    }
+    Ok(results)
+}
+
+// None for organizations without a tier
+pub async fn reconcile_organization(
+    state: &AppState,
+    organization_id: Uuid,
+    dry_run: bool,
+) -> Result<Option<UsageReconciliation>, DbErr> {
+    let db = &state.db.connection;
+    let now = Utc::now();
+    let month_start = month_start(now);
+
+    let Some(tier) = OrganizationTiers::find_by_id(organization_id).one(db).await? else {
+        return Ok(None);
+    };
+    let message_count = count_messages_since(db, organization_id, month_start).await?;
+
+    // A missing key is a month nothing was counted in yet
+    let redis_usage = match state.redis.get_usage(&organization_id).await {
+        Ok(usage) => Some(usage.unwrap_or(0)),
+        Err(err) => {
+            tracing::warn!("Failed to read usage of {} from Redis: {}", organization_id, err);
+            None
+        }
+    };
+    let stripe = check_stripe_usage(state, organization_id, month_start).await?;
+
+    let mut result = UsageReconciliation {
+        organization_id,
+        month: now.format("%Y-%m").to_string(),
+        messages: message_count,
+        redis_usage,
+        redis_drift: redis_usage.map(|usage| usage - message_count),
+        database_usage: tier.current_month_usage,
+        database_drift: tier.current_month_usage - message_count,
+        stripe,
+        repaired: false,
+    };
+
+    if result.has_drifted() {
+        tracing::warn!(
+            "Usage of {} drifted from {} messages: redis {:?}, database {}, stripe {:?}",
+            organization_id,
+            message_count,
+            result.redis_drift,
+            result.database_drift,
+            result.stripe.as_ref().map(|stripe| stripe.drift)
+        );
+    }
+    if dry_run {
+        return Ok(Some(result));
+    }
+
+    // Repairs write the count rather than correct by the drift, so pods that run this
+    // at the same time end up with the same value instead of correcting twice
+    if result.redis_drift.is_some_and(|drift| drift != 0) {
+        match state.redis.set_usage(&organization_id, message_count).await {
+            Ok(_) => result.repaired = true,
+            Err(err) => {
+                tracing::error!("Failed to repair usage of {} in Redis: {}", organization_id, err)
+            }
+        }
+    }
+
+    // Counted in the same statement, which also resets counters that were never
+    // reset for the new month
+    if result.database_drift != 0 {
+        let count = messages_since(organization_id, month_start)
+            .select_only()
+            .expr(Expr::col((messages::Entity, messages::Column::Id)).count())
+            .into_query();
+
+        OrganizationTiers::update_many()
+            .col_expr(
+                organization_tiers::Column::CurrentMonthUsage,
+                SimpleExpr::SubQuery(None, Box::new(count.into_sub_query_statement())),
+            )
+            .col_expr(
+                organization_tiers::Column::LastResetAt,
+                month_start.and_utc().fixed_offset().into(),
+            )
+            .filter(organization_tiers::Column::OrganizationId.eq(organization_id))
+            .exec(db)
+            .await?;
+        result.repaired = true;
+    }
+
+    Ok(Some(result))
+}
+
+// What Stripe billed plus what is still on its way, against the messages of the
+// billing period. Periods usually start mid-month, so this isn't the monthly count.
+async fn check_stripe_usage(
+    state: &AppState,
+    organization_id: Uuid,
+    month_start: NaiveDateTime,
+) -> Result<Option<StripeUsageCheck>, DbErr> {
+    let db = &state.db.connection;
+
+    let Some(item_id) = Organizations::find_by_id(organization_id)
+        .one(db)
+        .await?
+        .and_then(|organization| organization.stripe_subscription_item_id)
+    else {
+        return Ok(None);
+    };
+
+    let billed = match state.billing.get_usage(&item_id).await {
+        Ok(billed) => billed,
+        Err(err) => {
+            tracing::warn!("Failed to fetch billed usage of {}: {}", organization_id, err);
+            return Ok(None);
+        }
+    };
+    let period_start = billed
+        .period_start
+        .and_then(|start| DateTime::from_timestamp(start, 0))
+        .map(|start| start.naive_utc())
+        .unwrap_or(month_start);
+
+    let counted: i64 = StripeUsageCounters::find()
+        .filter(stripe_usage_counters::Column::SubscriptionItemId.eq(item_id.as_str()))
+        .all(db)
+        .await?
+        .iter()
+        .map(|counter| counter.pending_quantity)
+        .sum();
+    let (failed, unsent): (Vec<_>, Vec<_>) = StripeUsageReports::find()
+        .filter(stripe_usage_reports::Column::SubscriptionItemId.eq(item_id.as_str()))
+        .filter(stripe_usage_reports::Column::ReportedAt.is_null())
+        .all(db)
+        .await?
+        .into_iter()
+        .partition(|report| report.failed_at.is_some());
+    let failed: i64 = failed.iter().map(|report| report.quantity).sum();
+    let unsent: i64 = unsent.iter().map(|report| report.quantity).sum();
+
+    let messages = count_messages_since(db, organization_id, period_start).await?;
+    let pending = counted + unsent;
+
+    Ok(Some(StripeUsageCheck {
+        period_start,
+        messages,
+        billed: billed.quantity,
+        pending,
+        failed,
+        drift: billed.quantity + pending - messages,
+    }))
+}
+
+// Deleted messages are counted too, they were billed when they were sent
+async fn count_messages_since(
+    db: &DatabaseConnection,
+    organization_id: Uuid,
+    since: NaiveDateTime,
+) -> Result<i64, DbErr> {
+    let count = messages_since(organization_id, since).count(db).await?;
+    Ok(count as i64)
+}
+
+fn messages_since(organization_id: Uuid, since: NaiveDateTime) -> Select<Messages> {
+    Messages::find()
+        .join(JoinType::InnerJoin, messages::Relation::Channels.def())
+        .filter(channels::Column::OrganizationId.eq(organization_id))
+        .filter(messages::Column::CreatedAt.gte(since))
+}
+
+// Midnight UTC on the first of the month, where the Redis keys start
+fn month_start(now: DateTime<Utc>) -> NaiveDateTime {
+    let today = now.date_naive();
+    (today - Days::new(today.day0() as u64)).and_time(NaiveTime::MIN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn months_start_at_midnight_on_the_first() {
+        let starts: Vec<Option<NaiveDateTime>> = ["2025-01-01T00:00:00Z", "2025-03-31T23:59:59Z"]
+            .iter()
+            .map(|now| now.parse::<DateTime<Utc>>().ok().map(month_start))
+            .collect();
+
+        let expected: Vec<Option<NaiveDateTime>> = [(2025, 1), (2025, 3)]
+            .iter()
+            .map(|(year, month)| {
+                NaiveDate::from_ymd_opt(*year, *month, 1).map(|day| day.and_time(NaiveTime::MIN))
+            })
+            .collect();
+        assert_eq!(starts, expected);
+    }
+}
@@ -6,15 +6,18 @@
 mod config;
 mod entities;
 mod handlers;
+mod jobs;
 mod middleware;
+mod realtime;
 mod router;
 mod state;
 mod utils;
 
-use crate::config::StripeClient;
-use config::{AppConfig, Database, RedisConfig, RedisStore};
+use crate::config::billing_provider_from_env;
+use config::{ApiKeyPepper, AppConfig, Database, RedisConfig, RedisStore};
 use router::api_router;
 use state::AppState;
+use std::net::SocketAddr;
 use std::str::FromStr;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 use crate::utils::setup_logging;
@@ -29,6 +32,15 @@ async fn main() {
 
     let app_config = AppConfig::new();
 
+    // Every API key is hashed with it, so refuse to start with a missing or weak one
+    let api_key_pepper = match ApiKeyPepper::from_env() {
+        Ok(pepper) => pepper,
+        Err(err) => {
+            tracing::error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
     let database = Database::new()
         .await
         .expect("Failed to connect to database");
@@ -41,14 +53,20 @@ async fn main() {
     // Verify Redis connection
     redis_store.ping().await.expect("Could not ping Redis");
 
-    // Initialize Stripe
-    let stripe = StripeClient::new().expect("Failed to create Stripe client.");
+    // Initialize billing, Stripe unless BILLING_PROVIDER says otherwise
+    let billing = billing_provider_from_env().expect("Failed to set up billing.");
 
     // Create app state
-    let state = AppState::new(database, redis_store, stripe);
+    let state = AppState::new(database, redis_store, billing, api_key_pepper);
+
+    // Start background jobs
+    jobs::spawn_api_key_sweeper(state.clone());
+    jobs::spawn_key_usage_flusher(state.clone());
+    jobs::spawn_stripe_usage_reporter(state.clone());
+    jobs::spawn_usage_reconciler(state.clone());
 
     // Build our application with routes
-    let app = api_router().with_state(state);
+    let app = api_router().with_state(state.clone());
 
     let addr = app_config.addr();
     println!("🚀 Server running on http://{}", addr);
@@ -57,5 +75,52 @@ async fn main() {
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
 
     // Start the server
-    axum::serve(listener, app).await.unwrap();
+    // Client addresses are needed for API key IP allowlists
+    if let Err(err) = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+    {
+        tracing::error!("Server error: {}", err);
+    }
+
+    // Write out what was buffered since the last flush, it would be lost otherwise
+    if let Err(err) = jobs::flush_key_usage(&state).await {
+        tracing::error!("Failed to flush API key usage: {}", err);
+    }
+    if let Err(err) = jobs::flush_key_denials(&state).await {
+        tracing::error!("Failed to flush API key audit events: {}", err);
+    }
+}
+
+// Resolves on Ctrl+C or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutting down, finishing open requests");
 }
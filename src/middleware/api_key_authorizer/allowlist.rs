@@ -0,0 +1,198 @@
+use crate::entities::api_keys;
+use crate::middleware::api_key_authorizer::denials::KeyDenial;
+use crate::middleware::error::MiddlewareError;
+use crate::state::AppState;
+use axum::extract::ConnectInfo;
+use http::header::{ORIGIN, REFERER};
+use http::request::Parts;
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use url::Url;
+
+const IP_NOT_ALLOWED: &str = "ip_not_allowed";
+const ORIGIN_NOT_ALLOWED: &str = "origin_not_allowed";
+
+// Parses CIDR ranges, a bare address allows just that address
+pub(crate) fn normalize_allowed_ips(allowed_ips: &[String]) -> Result<Vec<String>, String> {
+    let mut networks = allowed_ips
+        .iter()
+        .map(|entry| {
+            let entry = entry.trim();
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map(|network| network.trunc().to_string())
+                .map_err(|_| format!("`{}` is not an IP address or CIDR range", entry))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    networks.sort();
+    networks.dedup();
+    Ok(networks)
+}
+
+// Reduces each entry to its origin, e.g. `https://app.example.com`
+pub(crate) fn normalize_allowed_origins(allowed_origins: &[String]) -> Result<Vec<String>, String> {
+    let mut origins = allowed_origins
+        .iter()
+        .map(|entry| {
+            parse_origin(entry.trim())
+                .ok_or_else(|| format!("`{}` is not an http or https origin", entry))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    origins.sort();
+    origins.dedup();
+    Ok(origins)
+}
+
+fn parse_origin(value: &str) -> Option<String> {
+    let url = Url::parse(value).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+
+    let origin = url.origin();
+    origin.is_tuple().then(|| origin.ascii_serialization())
+}
+
+// With `TRUSTED_PROXY_HEADER` set the address is taken from that header, otherwise
+// from the connection. For a list like `X-Forwarded-For` the last entry is used,
+// which is the one the trusted proxy added.
+fn client_ip(parts: &Parts) -> Option<IpAddr> {
+    if let Ok(header) = std::env::var("TRUSTED_PROXY_HEADER") {
+        let value = parts.headers.get(header.as_str())?.to_str().ok()?;
+        let last = value.rsplit(',').next()?.trim();
+        return last
+            .parse::<IpAddr>()
+            .ok()
+            .or_else(|| last.parse::<SocketAddr>().ok().map(|addr| addr.ip()));
+    }
+
+    parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+// Browsers send `Origin` on cross-origin requests, `Referer` covers the rest
+fn request_origin(parts: &Parts) -> Option<String> {
+    [ORIGIN, REFERER]
+        .iter()
+        .filter_map(|header| parts.headers.get(header)?.to_str().ok())
+        .find_map(parse_origin)
+}
+
+// Rejects requests from outside the key's allowed IP ranges or origins and
+// records them in `api_key_audit_events`
+pub(crate) fn ensure_client_allowed(
+    parts: &Parts,
+    state: &AppState,
+    key: &api_keys::Model,
+) -> Result<(), MiddlewareError> {
+    let ip = client_ip(parts);
+    let origin = request_origin(parts);
+
+    if let Some(allowed_ips) = &key.allowed_ips {
+        let allowed = ip.is_some_and(|ip| {
+            allowed_ips
+                .iter()
+                .filter_map(|network| network.parse::<IpNet>().ok())
+                .any(|network| network.contains(&ip))
+        });
+
+        if !allowed {
+            record_denial(state, key, IP_NOT_ALLOWED, ip, origin);
+            return Err(MiddlewareError::ClientNotAllowed(
+                "API key is not allowed from this IP address".into(),
+            ));
+        }
+    }
+
+    if let Some(allowed_origins) = &key.allowed_origins {
+        let allowed = origin
+            .as_ref()
+            .is_some_and(|origin| allowed_origins.contains(origin));
+
+        if !allowed {
+            record_denial(state, key, ORIGIN_NOT_ALLOWED, ip, origin);
+            return Err(MiddlewareError::ClientNotAllowed(
+                "API key is not allowed from this origin".into(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn record_denial(
+    state: &AppState,
+    key: &api_keys::Model,
+    event: &'static str,
+    ip: Option<IpAddr>,
+    origin: Option<String>,
+) {
+    let denial = KeyDenial {
+        api_key_id: key.id,
+        organization_id: key.organization_id,
+        event,
+        client_ip: ip.map(|ip| ip.to_string()),
+        origin,
+    };
+
+    // Repeats are only counted, the key usage flusher writes them to `api_key_audit_events`
+    if state.key_denials.record(denial.clone()) {
+        tracing::warn!(
+            "Rejected API key {} ({}), ip: {:?}, origin: {:?}",
+            denial.api_key_id,
+            denial.event,
+            denial.client_ip,
+            denial.origin
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_ips_are_normalized_to_networks() {
+        let allowed = normalize_allowed_ips(&[
+            "10.1.2.3/8".into(),
+            "192.168.0.7".into(),
+            "2001:db8::1/32".into(),
+            "10.0.0.0/8".into(),
+        ]);
+
+        assert_eq!(
+            allowed,
+            Ok(vec![
+                "10.0.0.0/8".to_string(),
+                "192.168.0.7/32".to_string(),
+                "2001:db8::/32".to_string(),
+            ])
+        );
+        assert!(normalize_allowed_ips(&["10.0.0.0/33".into()]).is_err());
+        assert!(normalize_allowed_ips(&["example.com".into()]).is_err());
+    }
+
+    #[test]
+    fn allowed_origins_are_reduced_to_their_origin() {
+        let allowed = normalize_allowed_origins(&[
+            "https://app.example.com/some/page".into(),
+            "http://localhost:3000".into(),
+            "HTTPS://APP.EXAMPLE.COM".into(),
+        ]);
+
+        assert_eq!(
+            allowed,
+            Ok(vec![
+                "http://localhost:3000".to_string(),
+                "https://app.example.com".to_string(),
+            ])
+        );
+        assert!(normalize_allowed_origins(&["app.example.com".into()]).is_err());
+        assert!(normalize_allowed_origins(&["file:///etc/passwd".into()]).is_err());
+    }
+}
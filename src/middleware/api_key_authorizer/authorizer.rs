@@ -1,18 +1,35 @@
 use axum::async_trait;
 use axum::extract::FromRequestParts;
 use http::request::Parts;
+use std::marker::PhantomData;
+use uuid::Uuid;
 use crate::entities::sea_orm_active_enums::ApiKeyType;
+use crate::middleware::api_key_authorizer::allowlist::ensure_client_allowed;
+use crate::middleware::api_key_authorizer::permissions::{ensure_key_type, ensure_scope, KeyPermission, KeyScope};
 use crate::middleware::error::MiddlewareError;
 use crate::middleware::helpers::{extract_api_key, extract_organization_id, find_and_validate_key};
 use crate::state::AppState;
 
+// Rejects keys below `P` with `InsufficientPermissions` and scoped keys without
+// `S` with `MissingScope`, e.g. `_: ApiKeyAuthorizer<ReadWriteAccess, ManageChannels>`
 #[derive(Debug, Clone)]
-pub struct ApiKeyAuthorizer {
+pub struct ApiKeyAuthorizer<P, S> {
     pub key_type: ApiKeyType,
+    // Channels the key is restricted to, `None` for every channel of the organization
+    pub channel_ids: Option<Vec<Uuid>>,
+    _permission: PhantomData<(P, S)>,
+}
+
+impl<P, S> ApiKeyAuthorizer<P, S> {
+    pub fn allows_channel(&self, channel_id: &Uuid) -> bool {
+        self.channel_ids
+            .as_ref()
+            .is_none_or(|channel_ids| channel_ids.contains(channel_id))
+    }
 }
 
 #[async_trait]
-impl FromRequestParts<AppState> for ApiKeyAuthorizer {
+impl<P: KeyPermission, S: KeyScope> FromRequestParts<AppState> for ApiKeyAuthorizer<P, S> {
     type Rejection = MiddlewareError;
 
     async fn from_request_parts(
@@ -21,10 +38,16 @@ impl FromRequestParts<AppState> for ApiKeyAuthorizer {
     ) -> Result<Self, Self::Rejection> {
         let org_id = extract_organization_id(parts, state).await?;
         let api_key = extract_api_key(parts)?;
-        let key = find_and_validate_key(&api_key, &org_id, &state.db.connection).await?;
+        let key = find_and_validate_key(&api_key, &org_id, state).await?;
+        ensure_client_allowed(parts, state, &key)?;
+        ensure_key_type(&key.key_type, &P::REQUIRED)?;
+        ensure_scope(&key.scopes, S::SCOPE)?;
+        state.key_usage.record(key.id);
 
         Ok(Self {
             key_type: key.key_type,
+            channel_ids: key.channel_ids,
+            _permission: PhantomData,
         })
     }
 }
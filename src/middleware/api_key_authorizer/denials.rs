@@ -0,0 +1,137 @@
+use chrono::{NaiveDateTime, Utc};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use uuid::Uuid;
+
+// Bounds the memory a flood of rejected requests can take. Rejections from clients
+// that don't fit are counted as dropped until the next flush.
+const MAX_BUFFERED_DENIALS: usize = 10_000;
+
+// A key, the reason it was rejected and the client it was rejected for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyDenial {
+    pub api_key_id: Uuid,
+    pub organization_id: Uuid,
+    pub event: &'static str,
+    pub client_ip: Option<String>,
+    pub origin: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DenialCount {
+    pub first_seen_at: NaiveDateTime,
+    pub occurrences: i32,
+}
+
+// Rejections that haven't been written to `api_key_audit_events` yet
+pub type KeyDenials = HashMap<KeyDenial, DenialCount>;
+
+// Collects rejected requests in memory so that `api_key_audit_events` gets one row per
+// key, reason and client per flush instead of one write per request
+#[derive(Debug, Clone, Default)]
+pub struct KeyDenialBuffer {
+    denials: Arc<Mutex<KeyDenials>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl KeyDenialBuffer {
+    // Returns whether this is the first time the rejection was seen since the last flush
+    pub fn record(&self, denial: KeyDenial) -> bool {
+        let mut denials = self.lock();
+        if let Some(count) = denials.get_mut(&denial) {
+            count.occurrences = count.occurrences.saturating_add(1);
+            return false;
+        }
+        if denials.len() >= MAX_BUFFERED_DENIALS {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        denials.insert(
+            denial,
+            DenialCount {
+                first_seen_at: Utc::now().naive_utc(),
+                occurrences: 1,
+            },
+        );
+        true
+    }
+
+    // Takes everything collected so far, leaving the buffer empty
+    pub fn take(&self) -> KeyDenials {
+        std::mem::take(&mut *self.lock())
+    }
+
+    // Rejections that didn't fit into the buffer since the last call
+    pub fn take_dropped(&self) -> u64 {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+
+    // Puts back rejections that couldn't be written
+    pub fn restore(&self, denials: KeyDenials) {
+        let mut buffered = self.lock();
+        for (denial, count) in denials {
+            let is_full = buffered.len() >= MAX_BUFFERED_DENIALS;
+            match buffered.get_mut(&denial) {
+                Some(buffered_count) => {
+                    buffered_count.first_seen_at = buffered_count.first_seen_at.min(count.first_seen_at);
+                    buffered_count.occurrences = buffered_count.occurrences.saturating_add(count.occurrences);
+                }
+                None if is_full => {
+                    self.dropped.fetch_add(count.occurrences as u64, Ordering::Relaxed);
+                }
+                None => {
+                    buffered.insert(denial, count);
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, KeyDenials> {
+        // The counts stay consistent even if a holder panicked
+        self.denials.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn denial(api_key_id: Uuid, client_ip: &str) -> KeyDenial {
+        KeyDenial {
+            api_key_id,
+            organization_id: Uuid::nil(),
+            event: "ip_not_allowed",
+            client_ip: Some(client_ip.to_string()),
+            origin: None,
+        }
+    }
+
+    #[test]
+    fn repeated_denials_are_counted_and_the_buffer_is_bounded() {
+        let buffer = KeyDenialBuffer::default();
+        let key_id = Uuid::new_v4();
+
+        let first = buffer.record(denial(key_id, "198.51.100.1"));
+        let repeated = buffer.record(denial(key_id, "198.51.100.1"));
+        let taken = buffer.take();
+        buffer.record(denial(key_id, "198.51.100.1"));
+        buffer.restore(taken);
+
+        assert!(first && !repeated);
+        let denials = buffer.take();
+        assert_eq!(denials.len(), 1);
+        assert_eq!(
+            denials.get(&denial(key_id, "198.51.100.1")).map(|count| count.occurrences),
+            Some(3)
+        );
+
+        for i in 0..=MAX_BUFFERED_DENIALS {
+            buffer.record(denial(key_id, &i.to_string()));
+        }
+        assert_eq!(buffer.take().len(), MAX_BUFFERED_DENIALS);
+        assert_eq!(buffer.take_dropped(), 1);
+        assert_eq!(buffer.take_dropped(), 0);
+    }
+}
@@ -1,3 +1,16 @@
+mod allowlist;
 mod authorizer;
+mod denials;
+mod permissions;
 
-pub use authorizer::{ApiKeyAuthorizer};
\ No newline at end of file
+pub use authorizer::{ApiKeyAuthorizer};
+pub use denials::KeyDenialBuffer;
+pub use permissions::{
+    AdminAccess, ApiKeyScope, KeyPermission, KeyScope, ManageChannels, ManageParticipants,
+    ReadChannels, ReadMessages, ReadOnlyAccess, ReadParticipants, ReadUsage, ReadWriteAccess,
+    WriteMessages,
+};
+pub(crate) use allowlist::{ensure_client_allowed, normalize_allowed_ips, normalize_allowed_origins};
+pub(crate) use permissions::{ensure_key_type, ensure_scope};
+#[cfg(test)]
+pub(crate) use permissions::key_type_level;
@@ -0,0 +1,220 @@
+use crate::entities::sea_orm_active_enums::ApiKeyType;
+use crate::middleware::error::MiddlewareError;
+use serde::{Deserialize, Serialize};
+
+// Minimum key type a route accepts, chosen through the type parameter of
+// `ApiKeyAuthorizer` and `ClientAuthorizer`, e.g. `ApiKeyAuthorizer<ReadWriteAccess>`
+pub trait KeyPermission: Send + Sync + 'static {
+    const REQUIRED: ApiKeyType;
+}
+
+#[derive(Debug, Clone)]
+pub struct ReadOnlyAccess;
+
+#[derive(Debug, Clone)]
+pub struct ReadWriteAccess;
+
+#[derive(Debug, Clone)]
+pub struct AdminAccess;
+
+impl KeyPermission for ReadOnlyAccess {
+    const REQUIRED: ApiKeyType = ApiKeyType::ReadOnly;
+}
+
+impl KeyPermission for ReadWriteAccess {
+    const REQUIRED: ApiKeyType = ApiKeyType::ReadWrite;
+}
+
+impl KeyPermission for AdminAccess {
+    const REQUIRED: ApiKeyType = ApiKeyType::Admin;
+}
+
+// Narrows what a key may do on top of its `ApiKeyType`. Stored on `api_keys.scopes`
+// by their string form, keys without scopes may use every route their type allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApiKeyScope {
+    #[serde(rename = "messages:read")]
+    MessagesRead,
+    #[serde(rename = "messages:write")]
+    MessagesWrite,
+    #[serde(rename = "channels:read")]
+    ChannelsRead,
+    #[serde(rename = "channels:manage")]
+    ChannelsManage,
+    #[serde(rename = "participants:read")]
+    ParticipantsRead,
+    #[serde(rename = "participants:manage")]
+    ParticipantsManage,
+    #[serde(rename = "usage:read")]
+    UsageRead,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::MessagesRead => "messages:read",
+            ApiKeyScope::MessagesWrite => "messages:write",
+            ApiKeyScope::ChannelsRead => "channels:read",
+            ApiKeyScope::ChannelsManage => "channels:manage",
+            ApiKeyScope::ParticipantsRead => "participants:read",
+            ApiKeyScope::ParticipantsManage => "participants:manage",
+            ApiKeyScope::UsageRead => "usage:read",
+        }
+    }
+}
+
+// Scope a route requires, the second type parameter of the authorizers,
+// e.g. `ApiKeyAuthorizer<ReadWriteAccess, ManageChannels>`
+pub trait KeyScope: Send + Sync + 'static {
+    const SCOPE: ApiKeyScope;
+}
+
+#[derive(Debug, Clone)]
+pub struct ReadMessages;
+
+#[derive(Debug, Clone)]
+pub struct WriteMessages;
+
+#[derive(Debug, Clone)]
+pub struct ReadChannels;
+
+#[derive(Debug, Clone)]
+pub struct ManageChannels;
+
+#[derive(Debug, Clone)]
+pub struct ReadParticipants;
+
+#[derive(Debug, Clone)]
+pub struct ManageParticipants;
+
+#[derive(Debug, Clone)]
+pub struct ReadUsage;
+
+impl KeyScope for ReadMessages {
+    const SCOPE: ApiKeyScope = ApiKeyScope::MessagesRead;
+}
+
+impl KeyScope for WriteMessages {
+    const SCOPE: ApiKeyScope = ApiKeyScope::MessagesWrite;
+}
+
+impl KeyScope for ReadChannels {
+    const SCOPE: ApiKeyScope = ApiKeyScope::ChannelsRead;
+}
+
+impl KeyScope for ManageChannels {
+    const SCOPE: ApiKeyScope = ApiKeyScope::ChannelsManage;
+}
+
+impl KeyScope for ReadParticipants {
+    const SCOPE: ApiKeyScope = ApiKeyScope::ParticipantsRead;
+}
+
+impl KeyScope for ManageParticipants {
+    const SCOPE: ApiKeyScope = ApiKeyScope::ParticipantsManage;
+}
+
+impl KeyScope for ReadUsage {
+    const SCOPE: ApiKeyScope = ApiKeyScope::UsageRead;
+}
+
+// ReadOnly < ReadWrite < Admin
+pub(crate) fn key_type_level(key_type: &ApiKeyType) -> u8 {
+    match key_type {
+        ApiKeyType::ReadOnly => 0,
+        ApiKeyType::ReadWrite => 1,
+        ApiKeyType::Admin => 2,
+    }
+}
+
+pub(crate) fn ensure_key_type(
+    key_type: &ApiKeyType,
+    required: &ApiKeyType,
+) -> Result<(), MiddlewareError> {
+    if key_type_level(key_type) >= key_type_level(required) {
+        Ok(())
+    } else {
+        Err(MiddlewareError::InsufficientPermissions)
+    }
+}
+
+pub(crate) fn ensure_scope(
+    scopes: &Option<Vec<String>>,
+    required: ApiKeyScope,
+) -> Result<(), MiddlewareError> {
+    match scopes {
+        Some(scopes) if !scopes.iter().any(|scope| scope == required.as_str()) => {
+            Err(MiddlewareError::MissingScope(required.as_str()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_types_are_ordered() {
+        let cases = [
+            (ApiKeyType::ReadOnly, ApiKeyType::ReadOnly, true),
+            (ApiKeyType::ReadOnly, ApiKeyType::ReadWrite, false),
+            (ApiKeyType::ReadOnly, ApiKeyType::Admin, false),
+            (ApiKeyType::ReadWrite, ApiKeyType::ReadOnly, true),
+            (ApiKeyType::ReadWrite, ApiKeyType::ReadWrite, true),
+            (ApiKeyType::ReadWrite, ApiKeyType::Admin, false),
+            (ApiKeyType::Admin, ApiKeyType::ReadOnly, true),
+            (ApiKeyType::Admin, ApiKeyType::ReadWrite, true),
+            (ApiKeyType::Admin, ApiKeyType::Admin, true),
+        ];
+
+        for (key_type, required, allowed) in cases {
+            let result = ensure_key_type(&key_type, &required);
+            assert_eq!(
+                result.is_ok(),
+                allowed,
+                "{:?} key against a {:?} route",
+                key_type,
+                required
+            );
+            if let Err(err) = result {
+                assert!(matches!(err, MiddlewareError::InsufficientPermissions));
+            }
+        }
+    }
+
+    #[test]
+    fn unscoped_keys_pass_every_scope() {
+        assert!(ensure_scope(&None, ApiKeyScope::ChannelsManage).is_ok());
+    }
+
+    #[test]
+    fn scoped_keys_only_pass_their_scopes() {
+        let scopes = Some(vec!["messages:read".to_string(), "messages:write".to_string()]);
+
+        assert!(ensure_scope(&scopes, ApiKeyScope::MessagesRead).is_ok());
+        assert!(ensure_scope(&scopes, ApiKeyScope::MessagesWrite).is_ok());
+        assert!(matches!(
+            ensure_scope(&scopes, ApiKeyScope::ChannelsManage),
+            Err(MiddlewareError::MissingScope("channels:manage"))
+        ));
+        assert!(ensure_scope(&Some(vec![]), ApiKeyScope::MessagesRead).is_err());
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_string_form() {
+        for scope in [
+            ApiKeyScope::MessagesRead,
+            ApiKeyScope::MessagesWrite,
+            ApiKeyScope::ChannelsRead,
+            ApiKeyScope::ChannelsManage,
+            ApiKeyScope::ParticipantsRead,
+            ApiKeyScope::ParticipantsManage,
+            ApiKeyScope::UsageRead,
+        ] {
+            let json = serde_json::Value::String(scope.as_str().to_string());
+            let parsed: Result<ApiKeyScope, _> = serde_json::from_value(json);
+            assert!(matches!(parsed, Ok(parsed) if parsed == scope), "{}", scope.as_str());
+        }
+    }
+}
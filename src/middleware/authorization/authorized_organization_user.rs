@@ -21,6 +21,14 @@ impl AuthorizedOrganizationUser {
             OrganizationRole::Owner | OrganizationRole::Admin | OrganizationRole::Developer
         )
     }
+
+    pub fn is_owner(&self) -> bool {
+        matches!(self.role, OrganizationRole::Owner)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        matches!(self.role, OrganizationRole::Owner | OrganizationRole::Admin)
+    }
 }
 
 #[async_trait]
@@ -75,3 +83,43 @@ impl FromRequestParts<AppState> for ApiKeyManager {
         Ok(Self(auth_user))
     }
 }
+
+pub struct OrganizationAdmin(pub AuthorizedOrganizationUser);
+
+#[async_trait]
+impl FromRequestParts<AppState> for OrganizationAdmin {
+    type Rejection = MiddlewareError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let auth_user = AuthorizedOrganizationUser::from_request_parts(parts, state).await?;
+
+        if !auth_user.is_admin() {
+            return Err(MiddlewareError::InsufficientPermissions);
+        }
+
+        Ok(Self(auth_user))
+    }
+}
+
+pub struct OrganizationOwner(pub AuthorizedOrganizationUser);
+
+#[async_trait]
+impl FromRequestParts<AppState> for OrganizationOwner {
+    type Rejection = MiddlewareError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let auth_user = AuthorizedOrganizationUser::from_request_parts(parts, state).await?;
+
+        if !auth_user.is_owner() {
+            return Err(MiddlewareError::InsufficientPermissions);
+        }
+
+        Ok(Self(auth_user))
+    }
+}
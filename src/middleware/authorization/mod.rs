@@ -1,4 +1,6 @@
 mod auth;
 mod authorized_organization_user;
 
-pub use authorized_organization_user::{ApiKeyManager, AuthorizedOrganizationUser};
\ No newline at end of file
+pub use authorized_organization_user::{
+    ApiKeyManager, AuthorizedOrganizationUser, OrganizationAdmin, OrganizationOwner,
+};
\ No newline at end of file
@@ -0,0 +1,137 @@
+use crate::entities::sea_orm_active_enums::ApiKeyType;
+use crate::middleware::api_key_authorizer::{
+    ensure_client_allowed, ensure_key_type, ensure_scope, ApiKeyScope, KeyPermission, KeyScope,
+};
+use crate::middleware::error::MiddlewareError;
+use crate::middleware::helpers::{
+    api_key_hmac, extract_api_key, extract_organization_id, extract_participant_token,
+    find_and_validate_key,
+};
+use crate::state::AppState;
+use crate::utils::decode_participant_token;
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use jsonwebtoken::errors::ErrorKind;
+use std::marker::PhantomData;
+use uuid::Uuid;
+
+// Who is calling: a server side integration holding an API key, or an end-user
+// client holding a participant token
+#[derive(Debug, Clone)]
+pub enum ClientCredential {
+    ApiKey {
+        key_id: Uuid,
+        // Which of the key's secrets was used, so long lived connections can check it again
+        key_hmac: String,
+        key_type: ApiKeyType,
+        // `None` when the key isn't limited to particular scopes or channels
+        scopes: Option<Vec<String>>,
+        channel_ids: Option<Vec<Uuid>>,
+    },
+    Participant {
+        participant_id: Uuid,
+        // Channels the token was restricted to when it was minted
+        channel_ids: Option<Vec<Uuid>>,
+        // Unix time the token expires at
+        expires_at: i64,
+    },
+}
+
+impl ClientCredential {
+    pub fn participant_id(&self) -> Option<Uuid> {
+        match self {
+            ClientCredential::Participant { participant_id, .. } => Some(*participant_id),
+            ClientCredential::ApiKey { .. } => None,
+        }
+    }
+
+    // Unrestricted keys and tokens may use every channel of their organization
+    pub fn allows_channel(&self, channel_id: &Uuid) -> bool {
+        match self {
+            ClientCredential::ApiKey {
+                channel_ids: Some(channel_ids),
+                ..
+            }
+            | ClientCredential::Participant {
+                channel_ids: Some(channel_ids),
+                ..
+            } => channel_ids.contains(channel_id),
+            _ => true,
+        }
+    }
+}
+
+// Like `ApiKeyAuthorizer`, but also accepts participant tokens. `P` and `S` only
+// apply to API keys, what a participant may do is decided by the handler.
+#[derive(Debug, Clone)]
+pub struct ClientAuthorizer<P, S> {
+    pub organization_id: Uuid,
+    pub credential: ClientCredential,
+    _permission: PhantomData<(P, S)>,
+}
+
+#[async_trait]
+impl<P: KeyPermission, S: KeyScope> FromRequestParts<AppState> for ClientAuthorizer<P, S> {
+    type Rejection = MiddlewareError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let organization_id = extract_organization_id(parts, state).await?;
+        let credential = authorize_client(parts, state, &organization_id, &P::REQUIRED, S::SCOPE).await?;
+
+        Ok(Self {
+            organization_id,
+            credential,
+            _permission: PhantomData,
+        })
+    }
+}
+
+// An `X-API-Key` header takes precedence, otherwise a participant token is
+// required. Either way the credential must belong to `organization_id`, and an
+// API key must be at least `required_key_type` and have `required_scope`.
+pub(crate) async fn authorize_client(
+    parts: &mut Parts,
+    state: &AppState,
+    organization_id: &Uuid,
+    required_key_type: &ApiKeyType,
+    required_scope: ApiKeyScope,
+) -> Result<ClientCredential, MiddlewareError> {
+    if parts.headers.contains_key("X-API-Key") {
+        let api_key = extract_api_key(parts)?;
+        let key = find_and_validate_key(&api_key, organization_id, state).await?;
+        ensure_client_allowed(parts, state, &key)?;
+        ensure_key_type(&key.key_type, required_key_type)?;
+        ensure_scope(&key.scopes, required_scope)?;
+        state.key_usage.record(key.id);
+
+        return Ok(ClientCredential::ApiKey {
+            key_id: key.id,
+            key_hmac: api_key_hmac(state, &api_key)?,
+            key_type: key.key_type,
+            scopes: key.scopes,
+            channel_ids: key.channel_ids,
+        });
+    }
+
+    let token = extract_participant_token(parts, state).await?;
+    let secret = std::env::var("JWT_SECRET")
+        .map_err(|_| MiddlewareError::ConfigError("JWT_SECRET must be set".into()))?;
+    let claims = decode_participant_token(&token, &secret).map_err(|e| match e.kind() {
+        ErrorKind::ExpiredSignature => MiddlewareError::ExpiredToken,
+        _ => MiddlewareError::InvalidToken(e.to_string()),
+    })?;
+
+    if claims.organization_id != *organization_id {
+        return Err(MiddlewareError::InvalidToken(
+            "Token was issued for another organization".into(),
+        ));
+    }
+
+    Ok(ClientCredential::Participant {
+        participant_id: claims.sub,
+        channel_ids: claims.channel_ids,
+        expires_at: claims.exp,
+    })
+}
@@ -0,0 +1,4 @@
+mod authorizer;
+
+pub use authorizer::{ClientAuthorizer, ClientCredential};
+pub(crate) use authorizer::authorize_client;
@@ -16,13 +16,17 @@ pub enum MiddlewareError {
     ExpiredToken,
     #[error("Insufficient permissions")]
     InsufficientPermissions,
+    #[error("Missing scope: {0}")]
+    MissingScope(&'static str),
+    #[error("Client not allowed: {0}")]
+    ClientNotAllowed(String),
     #[error("Database error: {0}")]
     DatabaseError(String),
     #[error("Redis error: {0}")]
     CacheError(String),
     #[error("Stripe error: {0}")]
     StripeError(String),
-    #[error("Stripe Metadata config error")]
+    #[error("Configuration error: {0}")]
     ConfigError(String),
     #[error("Usage limit exceeded: {0}")]
     UsageLimitExceeded(String),
@@ -37,10 +41,14 @@ impl IntoResponse for MiddlewareError {
             MiddlewareError::MissingToken => ServerResponse::unauthorized("Authorization token is missing"),
             MiddlewareError::ExpiredToken => ServerResponse::unauthorized("Authorization token has expired"),
             MiddlewareError::InsufficientPermissions => ServerResponse::forbidden("Insufficient permissions to access this resource"),
+            MiddlewareError::MissingScope(scope) => {
+                ServerResponse::forbidden(format!("API key is missing the `{}` scope", scope))
+            }
+            MiddlewareError::ClientNotAllowed(msg) => ServerResponse::client_not_allowed(msg),
             MiddlewareError::DatabaseError(msg) => ServerResponse::server_error(msg, "Database error occurred"),
             MiddlewareError::CacheError(msg) => ServerResponse::server_error(msg, "Cache error occurred"),
             MiddlewareError::StripeError(msg) => ServerResponse::server_error(msg, "Stripe error occurred"),
-            MiddlewareError::ConfigError(msg) => ServerResponse::server_error(msg, "Stripe metadata parsing error occurred"),
+            MiddlewareError::ConfigError(msg) => ServerResponse::server_error(msg, "Configuration error occurred"),
             MiddlewareError::UsageLimitExceeded(msg) => ServerResponse::forbidden(msg),
         }
     }
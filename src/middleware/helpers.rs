@@ -5,55 +5,81 @@ use crate::{
     middleware::error::MiddlewareError,
     state::AppState,
 };
-use axum::extract::{FromRequestParts, Path};
+use axum::extract::{FromRequestParts, Path, Query};
+use axum::http::header::AUTHORIZATION;
 use axum::http::request::Parts;
 use bcrypt::verify;
 use chrono::Utc;
 use sea_orm::prelude::Expr;
 use sea_orm::*;
+use serde::Deserialize;
 use tokio::spawn;
 use uuid::Uuid;
 
 pub(crate) async fn find_and_validate_key(
     api_key: &str,
     organization_id: &Uuid,
-    db: &DatabaseConnection,
+    state: &AppState,
 ) -> Result<api_keys::Model, MiddlewareError> {
-    let key_prefix = generate_api_key_prefix(api_key);
+    let db = &state.db.connection;
+    let key_hmac = api_key_hmac(state, api_key)?;
 
-    let potential_keys = api_keys::Entity::find()
-        .filter(api_keys::Column::KeyPrefix.eq(key_prefix))
-        .filter(api_keys::Column::OrganizationId.eq(*organization_id))
-        .all(db)
-        .await
-        .map_err(|e| MiddlewareError::DatabaseError(e.to_string()))?;
+    let cached = match state.redis.get_cached_api_key(&key_hmac).await {
+        Ok(cached) => cached,
+        Err(e) => {
+            tracing::warn!("Failed to read cached API key: {}", e);
+            None
+        }
+    };
 
-    let key = potential_keys
-        .into_iter()
-        .find(|k| verify(api_key, &k.key).unwrap_or(false))
-        .ok_or_else(|| MiddlewareError::InvalidToken("Invalid API key".into()))?;
+    let key = match cached {
+        Some(key) => key,
+        None => {
+            let key = match find_key_by_hmac(db, &key_hmac).await? {
+                Some(key) => key,
+                None => find_legacy_key(db, api_key, organization_id, &key_hmac)
+                    .await?
+                    .ok_or_else(|| MiddlewareError::InvalidToken("Invalid API key".into()))?,
+            };
 
-    // Checks expiration first
-    if let Some(expires_at) = key.expires_at {
-        if expires_at < Utc::now().naive_utc() {
-            return Err(MiddlewareError::ExpiredToken);
+            // Legacy keys whose upgrade failed have no HMAC to invalidate them by
+            if key.key_hmac.as_deref() == Some(&key_hmac)
+                || key.previous_key_hmac.as_deref() == Some(&key_hmac)
+            {
+                if let Err(e) = state.redis.cache_api_key(&key_hmac, &key).await {
+                    tracing::warn!("Failed to cache API key: {}", e);
+                }
+            }
+            key
         }
+    };
+
+    // The HMAC lookup and the cache aren't scoped to an organization
+    if key.organization_id != *organization_id {
+        return Err(MiddlewareError::InvalidToken("Invalid API key".into()));
     }
 
+    let is_previous_secret = check_key_validity(&key, &key_hmac)?;
+
     // We clone what we need for the background task
     let db = db.clone();
     let key_id = key.id;
 
-    // Update last_used_at in the background so that we don't block the response
+    // Update last_used_at in the background so that we don't block the response.
+    // Each secret has its own timestamp, which shows whether clients still use the old one.
     spawn(async move {
         let now = Utc::now().naive_utc();
 
-        let key_active: api_keys::ActiveModel = api_keys::ActiveModel {
+        let mut key_active: api_keys::ActiveModel = api_keys::ActiveModel {
             id: Set(key_id),
-            last_used_at: Set(Some(now)),
             updated_at: Set(now),
             ..Default::default()
         };
+        if is_previous_secret {
+            key_active.previous_key_last_used_at = Set(Some(now));
+        } else {
+            key_active.last_used_at = Set(Some(now));
+        }
 
         if let Err(e) = key_active.update(&db).await {
             tracing::error!("Failed to update key last_used_at: {}", e);
@@ -63,6 +89,127 @@ pub(crate) async fn find_and_validate_key(
     Ok(key)
 }
 
+// What keys are looked up by, the raw key is never stored
+pub(crate) fn api_key_hmac(state: &AppState, api_key: &str) -> Result<String, MiddlewareError> {
+    state.api_key_pepper.hash(api_key).map_err(MiddlewareError::ConfigError)
+}
+
+// Returns whether `key_hmac` is the secret replaced by the last rotation
+fn check_key_validity(key: &api_keys::Model, key_hmac: &str) -> Result<bool, MiddlewareError> {
+    let now = Utc::now().naive_utc();
+
+    // Checks expiration first
+    if let Some(expires_at) = key.expires_at {
+        if expires_at < now {
+            return Err(MiddlewareError::ExpiredToken);
+        }
+    }
+
+    if key.revoked_at.is_some() {
+        return Err(MiddlewareError::InvalidToken("API key has been revoked".into()));
+    }
+
+    // Anything that isn't the current secret is the one replaced by the last
+    // rotation, which only works during the grace period. Legacy keys that were
+    // never rotated have no current HMAC until their upgrade is stored.
+    let is_previous_secret = key.key_hmac.as_deref().is_some_and(|current| current != key_hmac);
+    if is_previous_secret && key.previous_key_expires_at.is_none_or(|expires_at| expires_at < now) {
+        return Err(MiddlewareError::ExpiredToken);
+    }
+
+    Ok(is_previous_secret)
+}
+
+// For connections that outlive a request, e.g. sockets, which check their key again
+// from time to time. Reads the database, a cached key could be a revocation behind.
+pub(crate) async fn revalidate_key(
+    state: &AppState,
+    key_id: Uuid,
+    key_hmac: &str,
+) -> Result<api_keys::Model, MiddlewareError> {
+    let key = ApiKeys::find_by_id(key_id)
+        .one(&state.db.connection)
+        .await
+        .map_err(|e| MiddlewareError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| MiddlewareError::InvalidToken("API key has been deleted".into()))?;
+
+    check_key_validity(&key, key_hmac)?;
+    Ok(key)
+}
+
+// Cached keys would otherwise keep working until their cache entry expires
+pub(crate) async fn invalidate_cached_key(state: &AppState, key: &api_keys::Model) {
+    for key_hmac in [&key.key_hmac, &key.previous_key_hmac].into_iter().flatten() {
+        if let Err(err) = state.redis.invalidate_api_key(key_hmac).await {
+            tracing::error!("Failed to invalidate cached API key {}: {}", key.id, err);
+        }
+    }
+}
+
+async fn find_key_by_hmac(
+    db: &DatabaseConnection,
+    key_hmac: &str,
+) -> Result<Option<api_keys::Model>, MiddlewareError> {
+    api_keys::Entity::find()
+        .filter(
+            Condition::any()
+                .add(api_keys::Column::KeyHmac.eq(key_hmac))
+                .add(api_keys::Column::PreviousKeyHmac.eq(key_hmac)),
+        )
+        .one(db)
+        .await
+        .map_err(|e| MiddlewareError::DatabaseError(e.to_string()))
+}
+
+// Keys created before the switch to HMACs only have a bcrypt hash. Once one of
+// them is verified it gets its HMAC, so this only runs on a key's first use.
+// A legacy key that was rotated before its first use still has its bcrypt hash,
+// which then becomes the previous secret.
+async fn find_legacy_key(
+    db: &DatabaseConnection,
+    api_key: &str,
+    organization_id: &Uuid,
+    key_hmac: &str,
+) -> Result<Option<api_keys::Model>, MiddlewareError> {
+    let key_prefix = generate_api_key_prefix(api_key);
+
+    let potential_keys = api_keys::Entity::find()
+        .filter(api_keys::Column::KeyPrefix.eq(key_prefix))
+        .filter(api_keys::Column::OrganizationId.eq(*organization_id))
+        .filter(api_keys::Column::Key.is_not_null())
+        .all(db)
+        .await
+        .map_err(|e| MiddlewareError::DatabaseError(e.to_string()))?;
+
+    let Some(key) = potential_keys.into_iter().find(|k| {
+        k.key
+            .as_ref()
+            .is_some_and(|hash| verify(api_key, hash).unwrap_or(false))
+    }) else {
+        return Ok(None);
+    };
+
+    let mut upgrade = api_keys::ActiveModel {
+        id: Set(key.id),
+        key: Set(None),
+        updated_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    };
+    if key.key_hmac.is_none() {
+        upgrade.key_hmac = Set(Some(key_hmac.to_string()));
+    } else {
+        upgrade.previous_key_hmac = Set(Some(key_hmac.to_string()));
+    }
+
+    match upgrade.update(db).await {
+        Ok(upgraded) => Ok(Some(upgraded)),
+        Err(e) => {
+            tracing::error!("Failed to upgrade API key {} to an HMAC: {}", key.id, e);
+            Ok(Some(key))
+        }
+    }
+}
+
 // Helper function to extract API key from headers
 pub(crate) fn extract_api_key(parts: &Parts) -> Result<String, MiddlewareError> {
     parts
@@ -90,3 +237,61 @@ pub(crate) async fn extract_organization_id(
 
     Err(MiddlewareError::OrgNotFound)
 }
+
+#[derive(Debug, Deserialize)]
+struct ParticipantTokenQuery {
+    token: Option<String>,
+}
+
+// Participant tokens come as a bearer token, or as a `token` query parameter for
+// clients that can't set headers (e.g. browsers opening a WebSocket)
+pub(crate) async fn extract_participant_token(
+    parts: &mut Parts,
+    state: &AppState,
+) -> Result<String, MiddlewareError> {
+    if let Some(header) = parts.headers.get(AUTHORIZATION) {
+        let header = header
+            .to_str()
+            .map_err(|_| MiddlewareError::InvalidToken("Invalid header value".into()))?;
+
+        return header
+            .strip_prefix("Bearer ")
+            .map(String::from)
+            .ok_or(MiddlewareError::InvalidToken("Invalid token format".into()));
+    }
+
+    Query::<ParticipantTokenQuery>::from_request_parts(parts, state)
+        .await
+        .ok()
+        .and_then(|Query(query)| query.token)
+        .ok_or(MiddlewareError::MissingToken)
+}
+
+// The organization's usage as the billing provider sees it. Only a fallback for when
+// Redis has no count, so failures are logged and count as no usage.
+pub(crate) async fn billed_usage(state: &AppState, org_id: &Uuid) -> i64 {
+    let org = match Organizations::find_by_id(*org_id).one(&state.db.connection).await {
+        Ok(Some(org)) => org,
+        Ok(None) => {
+            tracing::error!("Organization not found: {}", org_id);
+            return 0;
+        }
+        Err(e) => {
+            tracing::error!("Database error: {}", e);
+            return 0;
+        }
+    };
+
+    let Some(subscription_item_id) = org.stripe_subscription_item_id else {
+        tracing::error!("No subscription item ID found for org: {}", org_id);
+        return 0;
+    };
+
+    match state.billing.get_usage(&subscription_item_id).await {
+        Ok(usage) => usage.quantity,
+        Err(e) => {
+            tracing::error!("Failed to fetch usage for org {}: {}", org_id, e);
+            0
+        }
+    }
+}
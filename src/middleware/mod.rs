@@ -2,7 +2,11 @@ pub(crate) mod authorization;
 pub(crate) mod usage_tracker;
 pub(crate) mod api_key_authorizer;
 pub(crate) mod usage_limiter;
+pub(crate) mod socket_authorizer;
+pub(crate) mod client_authorizer;
 
 pub mod error;
 mod helpers;
 
+
+pub(crate) use helpers::{invalidate_cached_key, revalidate_key};
@@ -0,0 +1,59 @@
+use crate::entities::sea_orm_active_enums::ApiKeyType;
+use crate::entities::{channels, prelude::Channels};
+use crate::middleware::api_key_authorizer::ApiKeyScope;
+use crate::middleware::client_authorizer::{authorize_client, ClientCredential};
+use crate::middleware::error::MiddlewareError;
+use crate::state::AppState;
+use axum::extract::Path;
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use sea_orm::*;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct SocketAuthorizer {
+    pub organization_id: Uuid,
+    pub channel: channels::Model,
+    pub credential: ClientCredential,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for SocketAuthorizer {
+    type Rejection = MiddlewareError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // The room id is the channel id, and the channel decides which organization
+        // the credentials have to belong to
+        let Path(room_id) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| MiddlewareError::NotFound("Channel not found".into()))?;
+        let channel_id = Uuid::parse_str(&room_id)
+            .map_err(|_| MiddlewareError::NotFound("Channel not found".into()))?;
+
+        let channel = Channels::find_by_id(channel_id)
+            .one(&state.db.connection)
+            .await
+            .map_err(|e| MiddlewareError::DatabaseError(e.to_string()))?
+            .ok_or_else(|| MiddlewareError::NotFound("Channel not found".into()))?;
+
+        // Browsers can't set headers on a WebSocket handshake, so participant tokens
+        // are also accepted as a `token` query parameter. Any key that may read messages
+        // may listen, sending is checked per frame.
+        let credential = authorize_client(
+            parts,
+            state,
+            &channel.organization_id,
+            &ApiKeyType::ReadOnly,
+            ApiKeyScope::MessagesRead,
+        )
+        .await?;
+
+        Ok(Self {
+            organization_id: channel.organization_id,
+            channel,
+            credential,
+        })
+    }
+}
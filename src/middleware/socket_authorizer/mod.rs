@@ -0,0 +1,3 @@
+mod authorizer;
+
+pub use authorizer::{SocketAuthorizer};
\ No newline at end of file
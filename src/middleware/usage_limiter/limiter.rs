@@ -1,6 +1,6 @@
 use crate::entities::prelude::{OrganizationTiers, Organizations};
 use crate::middleware::error::MiddlewareError;
-use crate::middleware::helpers::extract_organization_id;
+use crate::middleware::helpers::{billed_usage, extract_organization_id};
 use crate::state::AppState;
 use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
 use sea_orm::*;
@@ -26,60 +26,71 @@ impl FromRequestParts<AppState> for UsageLimiter {
             }
         };
 
-        // Check usage, log error to devs but return generic success to user
-        let usage = match check_usage(&state, &org_id).await {
-            Ok(u) => u,
-            Err(e) => {
-                tracing::error!("Failed to check usage for org {}: {}", org_id, e);
-                return Ok(Self); // Let the request through if we can't check usage
-            }
-        };
-
-        // Get tier info, log error to devs but return generic success to user
-        let org_tier = match OrganizationTiers::find_by_id(org_id)
-            .one(&state.db.connection)
-            .await
-        {
-            Ok(Some(tier)) => tier,
-            Ok(None) => {
-                tracing::error!("Organization tier not found for org: {}", org_id);
-                return Ok(Self); // Let the request through if we can't find tier
-            }
-            Err(e) => {
-                tracing::error!("Database error getting org tier: {}", e);
-                return Ok(Self); // Let the request through on DB errors
-            }
-        };
+        enforce_usage_limit(state, &org_id).await?;
 
-        let tier_limit = org_tier.monthly_request_limit;
+        Ok(Self)
+    }
+}
 
-        // This is the only error we want to show to users
-        if usage >= tier_limit {
-            let error_message = format!(
-                "Usage limit exceeded. Current usage: {}, Tier limit: {}. Please upgrade your subscription.",
-                usage,
-                tier_limit
-            );
-            return Err(MiddlewareError::UsageLimitExceeded(error_message));
+// Shared by the extractor and the WebSocket handler, which can't run extractors
+// for every frame it receives. Only an exceeded limit is reported as an error.
+pub(crate) async fn enforce_usage_limit(
+    state: &AppState,
+    org_id: &Uuid,
+) -> Result<(), MiddlewareError> {
+    // Check usage, log error to devs but return generic success to user
+    let usage = match check_usage(state, org_id).await {
+        Ok(u) => u,
+        Err(e) => {
+            tracing::error!("Failed to check usage for org {}: {}", org_id, e);
+            return Ok(()); // Let the request through if we can't check usage
         }
+    };
 
-        // Threshold notification in background
-        let threshold_percentage = 0.8;
-        let threshold = (tier_limit as f64 * threshold_percentage) as i64;
-
-        if usage > threshold {
-            let state = state.clone();
-            let org_id = org_id.clone();
-            tokio::spawn(async move {
-                if let Err(e) = notify_usage_threshold(&state, &org_id, usage, tier_limit).await {
-                    tracing::error!("Failed to send usage notification: {}", e);
-                    // Error is only logged, not returned to user
-                }
-            });
+    // Get tier info, log error to devs but return generic success to user
+    let org_tier = match OrganizationTiers::find_by_id(*org_id)
+        .one(&state.db.connection)
+        .await
+    {
+        Ok(Some(tier)) => tier,
+        Ok(None) => {
+            tracing::error!("Organization tier not found for org: {}", org_id);
+            return Ok(()); // Let the request through if we can't find tier
         }
+        Err(e) => {
+            tracing::error!("Database error getting org tier: {}", e);
+            return Ok(()); // Let the request through on DB errors
+        }
+    };
 
-        Ok(Self)
+    let tier_limit = org_tier.monthly_request_limit;
+
+    // This is the only error we want to show to users
+    if usage >= tier_limit {
+        let error_message = format!(
+            "Usage limit exceeded. Current usage: {}, Tier limit: {}. Please upgrade your subscription.",
+            usage,
+            tier_limit
+        );
+        return Err(MiddlewareError::UsageLimitExceeded(error_message));
     }
+
+    // Threshold notification in background
+    let threshold_percentage = 0.8;
+    let threshold = (tier_limit as f64 * threshold_percentage) as i64;
+
+    if usage > threshold {
+        let state = state.clone();
+        let org_id = *org_id;
+        tokio::spawn(async move {
+            if let Err(e) = notify_usage_threshold(&state, &org_id, usage, tier_limit).await {
+                tracing::error!("Failed to send usage notification: {}", e);
+                // Error is only logged, not returned to user
+            }
+        });
+    }
+
+    Ok(())
 }
 
 async fn check_usage(state: &AppState, org_id: &Uuid) -> Result<i64, MiddlewareError> {
@@ -96,11 +107,8 @@ async fn check_usage(state: &AppState, org_id: &Uuid) -> Result<i64, MiddlewareE
         }
     }
 
-    // Fallback to Stripe
-    let usage = state
-        .stripe
-        .get_subscription_usage(&state.db.connection, org_id)
-        .await;
+    // Fallback to the billing provider
+    let usage = billed_usage(state, org_id).await;
 
     // Try to cache the result, but only log errors
     if let Err(e) = state.redis.set_usage(org_id, usage).await {
@@ -1,3 +1,4 @@
 mod limiter;
 
 pub use limiter::{UsageLimiter};
+pub(crate) use limiter::enforce_usage_limit;
@@ -0,0 +1,61 @@
+use chrono::{NaiveDate, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use uuid::Uuid;
+
+// Requests per API key and day that haven't been written to `api_key_usage` yet
+pub type KeyUsageCounts = HashMap<(Uuid, NaiveDate), i32>;
+
+// Counts authorized requests in memory so that `api_key_usage` is written once per
+// flush instead of once per request. Counts that weren't flushed yet are lost if
+// the process dies.
+#[derive(Debug, Clone, Default)]
+pub struct KeyUsageBuffer {
+    counts: Arc<Mutex<KeyUsageCounts>>,
+}
+
+impl KeyUsageBuffer {
+    pub fn record(&self, api_key_id: Uuid) {
+        let today = Utc::now().date_naive();
+        *self.lock().entry((api_key_id, today)).or_insert(0) += 1;
+    }
+
+    // Takes everything counted so far, leaving the buffer empty
+    pub fn take(&self) -> KeyUsageCounts {
+        std::mem::take(&mut *self.lock())
+    }
+
+    // Puts back counts that couldn't be written
+    pub fn restore(&self, counts: KeyUsageCounts) {
+        let mut buffered = self.lock();
+        for (key, count) in counts {
+            *buffered.entry(key).or_insert(0) += count;
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, KeyUsageCounts> {
+        // The counts stay consistent even if a holder panicked
+        self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restored_counts_add_up_with_new_ones() {
+        let buffer = KeyUsageBuffer::default();
+        let key_id = Uuid::new_v4();
+
+        buffer.record(key_id);
+        buffer.record(key_id);
+        let taken = buffer.take();
+        buffer.record(key_id);
+        buffer.restore(taken);
+
+        let today = Utc::now().date_naive();
+        assert_eq!(buffer.take().get(&(key_id, today)), Some(&3));
+        assert!(buffer.take().is_empty());
+    }
+}
@@ -1,3 +1,8 @@
+mod key_usage;
+mod stripe_usage;
 mod tracker;
 
+pub use key_usage::KeyUsageBuffer;
+pub(crate) use stripe_usage::record_stripe_usage;
 pub use tracker::{UsageTracker};
+pub(crate) use tracker::track_api_usage;
@@ -0,0 +1,35 @@
+use crate::entities::prelude::StripeUsageCounters;
+use crate::entities::stripe_usage_counters;
+use chrono::Utc;
+use sea_orm::prelude::Expr;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::*;
+use uuid::Uuid;
+
+// Counts one request towards the subscription item's next usage report. The
+// reporter job claims the count and sends it to Stripe.
+pub async fn record_stripe_usage(
+    db: &DatabaseConnection,
+    organization_id: Uuid,
+    subscription_item_id: &str,
+) -> Result<(), DbErr> {
+    StripeUsageCounters::insert(stripe_usage_counters::ActiveModel {
+        subscription_item_id: Set(subscription_item_id.to_string()),
+        organization_id: Set(organization_id),
+        pending_quantity: Set(1),
+        updated_at: Set(Utc::now().naive_utc()),
+    })
+    .on_conflict(
+        OnConflict::column(stripe_usage_counters::Column::SubscriptionItemId)
+            .value(
+                stripe_usage_counters::Column::PendingQuantity,
+                Expr::cust("stripe_usage_counters.pending_quantity + excluded.pending_quantity"),
+            )
+            .update_column(stripe_usage_counters::Column::UpdatedAt)
+            .to_owned(),
+    )
+    .exec_without_returning(db)
+    .await?;
+
+    Ok(())
+}
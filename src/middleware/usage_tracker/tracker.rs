@@ -1,6 +1,7 @@
 use crate::entities::organization_tiers;
 use crate::entities::prelude::{OrganizationTiers, Organizations};
-use crate::middleware::helpers::extract_organization_id;
+use crate::middleware::helpers::{billed_usage, extract_organization_id};
+use crate::middleware::usage_tracker::record_stripe_usage;
 use crate::{middleware::error::MiddlewareError, state::AppState};
 use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
 use sea_orm::prelude::Expr;
@@ -83,12 +84,11 @@ pub(crate) async fn track_api_usage(
             return;
         };
 
-        // Spawn Stripe update
-        let stripe_client = state.stripe.clone();
-        let stripe_sub_id = stripe_subscription_item_id.clone();
+        // Counted here and sent to Stripe in batches by the usage reporter
+        let db = state.db.connection.clone();
         spawn(async move {
-            if let Err(e) = stripe_client.report_api_usage(&stripe_sub_id).await {
-                tracing::error!("Failed to report usage to Stripe: {}", e);
+            if let Err(e) = record_stripe_usage(&db, org_id, &stripe_subscription_item_id).await {
+                tracing::error!("Failed to record usage for Stripe: {}", e);
             }
         });
 
@@ -110,12 +110,9 @@ pub(crate) async fn track_api_usage(
             }
         });
 
-        // If Redis increment failed earlier, try to get current usage from Stripe and set it
+        // If Redis increment failed earlier, try to get current usage from billing and set it
         if state.redis.get_usage(&org_id).await.is_err() {
-            let current_usage = state
-                .stripe
-                .get_subscription_usage(&state.db.connection, &org_id)
-                .await;
+            let current_usage = billed_usage(&state, &org_id).await;
 
             if let Err(e) = state.redis.set_usage(&org_id, current_usage).await {
                 tracing::error!(
@@ -0,0 +1,372 @@
+use crate::entities::messages;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Bumped whenever a breaking change is made to the frames below.
+// Every server frame carries it, clients may send it and are rejected on mismatch.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+// Events sent to clients, either fanned out through `chat:{channel_id}` or
+// replied directly to the socket that caused them (ack, error, pong).
+// See README.md in this module for the wire format.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum ServerEvent {
+    #[serde(rename = "message.created")]
+    MessageCreated(MessagePayload),
+    #[serde(rename = "message.updated")]
+    MessageUpdated(MessagePayload),
+    #[serde(rename = "message.deleted")]
+    MessageDeleted(MessageDeletedPayload),
+    #[serde(rename = "reaction.added")]
+    ReactionAdded(ReactionPayload),
+    #[serde(rename = "reaction.removed")]
+    ReactionRemoved(ReactionPayload),
+    #[serde(rename = "typing")]
+    Typing(TypingPayload),
+    #[serde(rename = "presence")]
+    Presence(PresencePayload),
+    #[serde(rename = "ack")]
+    Ack(AckPayload),
+    #[serde(rename = "error")]
+    Error(ErrorPayload),
+    #[serde(rename = "pong")]
+    Pong,
+}
+
+#[derive(Serialize)]
+struct ServerEnvelope<'a> {
+    version: u8,
+    #[serde(flatten)]
+    event: &'a ServerEvent,
+}
+
+impl ServerEvent {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&ServerEnvelope {
+            version: PROTOCOL_VERSION,
+            event: self,
+        })
+    }
+}
+
+// Frames accepted from clients
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum ClientEvent {
+    #[serde(rename = "message.create")]
+    MessageCreate(MessageCreatePayload),
+    #[serde(rename = "typing")]
+    Typing(ClientTypingPayload),
+    #[serde(rename = "ping")]
+    Ping,
+}
+
+#[derive(Deserialize)]
+struct ClientEnvelope {
+    version: Option<u8>,
+    #[serde(flatten)]
+    event: ClientEvent,
+}
+
+impl ClientEvent {
+    pub fn parse(text: &str) -> Result<Self, ErrorPayload> {
+        let envelope = serde_json::from_str::<ClientEnvelope>(text)
+            .map_err(|e| ErrorPayload::new(ErrorCode::InvalidFrame, e.to_string()))?;
+
+        match envelope.version {
+            Some(version) if version != PROTOCOL_VERSION => Err(ErrorPayload::new(
+                ErrorCode::UnsupportedVersion,
+                format!("Unsupported protocol version {}, expected {}", version, PROTOCOL_VERSION),
+            )),
+            _ => Ok(envelope.event),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MessagePayload {
+    pub message_id: Uuid,
+    pub channel_id: Uuid,
+    pub participant_id: Uuid,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    // Set once the message has been edited
+    pub edited_at: Option<DateTime<Utc>>,
+    // Set for thread replies, clients nest them under this message
+    pub parent_message_id: Option<Uuid>,
+}
+
+impl From<messages::Model> for MessagePayload {
+    fn from(message: messages::Model) -> Self {
+        Self {
+            message_id: message.id,
+            channel_id: message.channel_id,
+            participant_id: message.participant_id,
+            content: message.content,
+            created_at: message.created_at.and_utc(),
+            edited_at: message.edited_at.map(|edited_at| edited_at.and_utc()),
+            parent_message_id: message.parent_message_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageDeletedPayload {
+    pub message_id: Uuid,
+    pub channel_id: Uuid,
+    pub deleted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReactionPayload {
+    pub message_id: Uuid,
+    pub channel_id: Uuid,
+    pub participant_id: Uuid,
+    pub emoji: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TypingPayload {
+    pub channel_id: Uuid,
+    pub participant_id: Uuid,
+    pub is_typing: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Joined,
+    Left,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PresencePayload {
+    pub channel_id: Uuid,
+    // Only known for sockets authorized with a participant token
+    pub participant_id: Option<Uuid>,
+    pub status: PresenceStatus,
+    // Sockets connected to the room, counted across instances
+    pub active_users: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AckPayload {
+    // Echoed back so clients can match the ack to the frame they sent
+    pub client_id: Option<String>,
+    pub message_id: Uuid,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidFrame,
+    UnsupportedVersion,
+    MissingParticipant,
+    NotChannelMember,
+    InsufficientPermissions,
+    UsageLimitExceeded,
+    MessageRejected,
+    // Sent right before the server closes a socket whose credentials or access ended
+    AccessRevoked,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorPayload {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorPayload {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessageCreatePayload {
+    pub client_id: Option<String>,
+    // Required for API key connections, participant tokens carry their own identity
+    pub participant_id: Option<Uuid>,
+    pub content: String,
+    // Posts the message as a reply in the thread of this message
+    pub parent_message_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientTypingPayload {
+    pub participant_id: Option<Uuid>,
+    pub is_typing: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn to_value(event: &ServerEvent) -> Result<Value, serde_json::Error> {
+        serde_json::from_str(&event.to_json()?)
+    }
+
+    #[test]
+    fn server_events_are_framed_with_version_and_type() -> Result<(), serde_json::Error> {
+        let message_id = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+        let participant_id = Uuid::new_v4();
+        let created_at = DateTime::parse_from_rfc3339("2024-12-30T10:15:00Z")
+            .map(|created_at| created_at.with_timezone(&Utc))
+            .unwrap_or_default();
+
+        let message = MessagePayload {
+            message_id,
+            channel_id,
+            participant_id,
+            content: "Hello world".into(),
+            created_at,
+            edited_at: None,
+            parent_message_id: None,
+        };
+        let reaction = ReactionPayload {
+            message_id,
+            channel_id,
+            participant_id,
+            emoji: "👍".into(),
+        };
+
+        assert_eq!(
+            to_value(&ServerEvent::MessageCreated(message.clone()))?,
+            json!({
+                "version": PROTOCOL_VERSION,
+                "type": "message.created",
+                "message_id": message_id,
+                "channel_id": channel_id,
+                "participant_id": participant_id,
+                "content": "Hello world",
+                "created_at": "2024-12-30T10:15:00Z",
+                "edited_at": null,
+                "parent_message_id": null,
+            })
+        );
+        assert_eq!(
+            to_value(&ServerEvent::Presence(PresencePayload {
+                channel_id,
+                participant_id: None,
+                status: PresenceStatus::Left,
+                active_users: 3,
+            }))?,
+            json!({
+                "version": PROTOCOL_VERSION,
+                "type": "presence",
+                "channel_id": channel_id,
+                "participant_id": null,
+                "status": "left",
+                "active_users": 3,
+            })
+        );
+        assert_eq!(
+            to_value(&ServerEvent::Error(ErrorPayload::new(ErrorCode::AccessRevoked, "gone")))?,
+            json!({
+                "version": PROTOCOL_VERSION,
+                "type": "error",
+                "code": "access_revoked",
+                "message": "gone",
+            })
+        );
+        assert_eq!(
+            to_value(&ServerEvent::Pong)?,
+            json!({ "version": PROTOCOL_VERSION, "type": "pong" })
+        );
+
+        let types = [
+            (ServerEvent::MessageUpdated(message), "message.updated"),
+            (
+                ServerEvent::MessageDeleted(MessageDeletedPayload {
+                    message_id,
+                    channel_id,
+                    deleted_at: created_at,
+                }),
+                "message.deleted",
+            ),
+            (ServerEvent::ReactionAdded(reaction.clone()), "reaction.added"),
+            (ServerEvent::ReactionRemoved(reaction), "reaction.removed"),
+            (
+                ServerEvent::Typing(TypingPayload {
+                    channel_id,
+                    participant_id,
+                    is_typing: true,
+                }),
+                "typing",
+            ),
+            (
+                ServerEvent::Ack(AckPayload {
+                    client_id: Some("local-42".into()),
+                    message_id,
+                }),
+                "ack",
+            ),
+        ];
+        for (event, expected) in types {
+            let value = to_value(&event)?;
+            assert_eq!(value["type"], expected);
+            assert_eq!(value["version"], PROTOCOL_VERSION);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn client_frames_parse_with_or_without_version() {
+        let participant_id = Uuid::new_v4();
+        let create = json!({
+            "version": PROTOCOL_VERSION,
+            "type": "message.create",
+            "client_id": "local-42",
+            "participant_id": participant_id,
+            "content": "Hello world",
+        });
+        let typing = json!({ "type": "typing", "is_typing": true });
+
+        let create = ClientEvent::parse(&create.to_string());
+        let typing = ClientEvent::parse(&typing.to_string());
+        let ping = ClientEvent::parse(r#"{ "type": "ping" }"#);
+
+        assert!(matches!(
+            create,
+            Ok(ClientEvent::MessageCreate(MessageCreatePayload {
+                client_id: Some(ref client_id),
+                participant_id: Some(id),
+                ref content,
+                parent_message_id: None,
+            })) if client_id == "local-42" && id == participant_id && content == "Hello world"
+        ));
+        assert!(matches!(
+            typing,
+            Ok(ClientEvent::Typing(ClientTypingPayload {
+                participant_id: None,
+                is_typing: true,
+            }))
+        ));
+        assert!(matches!(ping, Ok(ClientEvent::Ping)));
+    }
+
+    #[test]
+    fn unknown_frames_and_versions_are_rejected() {
+        let code = |text: &str| ClientEvent::parse(text).err().map(|error| error.code);
+
+        assert!(matches!(code(r#"{ "type": "message.delete" }"#), Some(ErrorCode::InvalidFrame)));
+        assert!(matches!(code(r#"{ "version": 1 }"#), Some(ErrorCode::InvalidFrame)));
+        assert!(matches!(code(r#"{ "type": "message.create" }"#), Some(ErrorCode::InvalidFrame)));
+        assert!(matches!(code("not json"), Some(ErrorCode::InvalidFrame)));
+        assert!(matches!(
+            code(r#"{ "version": 2, "type": "ping" }"#),
+            Some(ErrorCode::UnsupportedVersion)
+        ));
+        assert!(matches!(
+            code(r#"{ "version": "1", "type": "ping" }"#),
+            Some(ErrorCode::InvalidFrame)
+        ));
+    }
+}
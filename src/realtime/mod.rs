@@ -0,0 +1,8 @@
+mod events;
+mod publisher;
+
+pub use events::{
+    AckPayload, ClientEvent, ErrorCode, ErrorPayload, MessageDeletedPayload, MessagePayload,
+    PresencePayload, PresenceStatus, ReactionPayload, ServerEvent, TypingPayload,
+};
+pub use publisher::{publish_event, room_channel};
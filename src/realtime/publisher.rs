@@ -0,0 +1,30 @@
+use crate::config::RedisStore;
+use crate::realtime::events::ServerEvent;
+use redis::{AsyncCommands, RedisError};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Debug, Error)]
+pub enum RealtimeError {
+    #[error("Redis error: {0}")]
+    Redis(#[from] RedisError),
+    #[error("JSON serialization error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Redis pub/sub channel that sockets connected to `/ws/chat/:room_id` subscribe to
+pub fn room_channel(channel_id: &Uuid) -> String {
+    format!("chat:{}", channel_id)
+}
+
+pub async fn publish_event(
+    redis: &RedisStore,
+    channel_id: &Uuid,
+    event: &ServerEvent,
+) -> Result<(), RealtimeError> {
+    let payload = event.to_json()?;
+    let mut conn = redis.client.get_multiplexed_async_connection().await?;
+    conn.publish::<_, _, ()>(room_channel(channel_id), payload).await?;
+
+    Ok(())
+}
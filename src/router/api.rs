@@ -1,7 +1,7 @@
 use crate::{handlers, state::AppState};
 use axum::routing::delete;
 use axum::{
-    routing::{get, post},
+    routing::{get, post, put},
     Router,
 };
 
@@ -9,21 +9,58 @@ pub fn api_router() -> Router<AppState> {
     Router::new()
         .route("/health", get(handlers::health_check))
         .route("/ws/chat/:room_id", get(handlers::chat_ws_handler))
+        .route("/webhooks/stripe", post(handlers::stripe_webhook))
         .nest(
             "/api",
             Router::new()
                 .nest(
                     "/organizations/:organization_id",
                     Router::new()
-                        .route("/participants", post(handlers::create_participant))
+                        .route(
+                            "/participants",
+                            get(handlers::get_participants).post(handlers::create_participant),
+                        )
                         .route("/participants/count", get(handlers::get_participants_count))
+                        .route(
+                            "/participants/external/:external_id",
+                            get(handlers::get_participant_by_external_id)
+                                .put(handlers::upsert_participant_by_external_id),
+                        )
+                        .route(
+                            "/participants/:participant_id",
+                            get(handlers::get_participant)
+                                .patch(handlers::update_participant)
+                                .delete(handlers::delete_participant),
+                        )
+                        .route(
+                            "/participants/:participant_id/tokens",
+                            post(handlers::create_participant_token),
+                        )
 
                         .route("/channels", post(handlers::create_channel))
                         .route("/channels", get(handlers::get_channels))
                         .route("/channels/:channel_id", get(handlers::get_channel_by_id))
+                        .route(
+                            "/channels/:channel_id/members",
+                            get(handlers::get_channel_members).post(handlers::add_channel_member),
+                        )
+                        .route(
+                            "/channels/:channel_id/members/:participant_id",
+                            delete(handlers::remove_channel_member),
+                        )
 
                         .route("/messages/count", get(handlers::get_messages_count_for_current_month))
-                        .route("/messages/:channel_id", get(handlers::get_messages_by_channel_id))
+                        .route("/messages/search", get(handlers::search_messages))
+                        // GET takes a channel id, PATCH and DELETE a message id
+                        .route(
+                            "/messages/:id",
+                            get(handlers::get_messages_by_channel_id)
+                                .patch(handlers::update_message)
+                                .delete(handlers::delete_message),
+                        )
+                        .route("/messages/:id/replies", get(handlers::get_message_replies))
+                        .route("/messages/:id/reactions", post(handlers::add_reaction))
+                        .route("/messages/:id/reactions/:emoji", delete(handlers::remove_reaction))
                         .route("/messages", post(handlers::create_message))
 
                         // admin routes they all need
@@ -31,9 +68,31 @@ pub fn api_router() -> Router<AppState> {
                         .route("/users/active", get(handlers::get_active_users))
                         .route("/users/count", get(handlers::get_users_in_org_count))
                         .route("/keys", get(handlers::get_api_keys))
-                        .route("/keys/:key_id", delete(handlers::delete_api_key))
+                        .route(
+                            "/keys/:key_id",
+                            delete(handlers::delete_api_key).patch(handlers::update_api_key),
+                        )
+                        .route("/keys/:key_id/rotate", post(handlers::rotate_api_key))
                         .route("/keys/count", get(handlers::get_api_key_count))
-                        .route("/keys", post(handlers::create_api_key)),
+                        .route("/keys/usage", get(handlers::get_api_keys_usage))
+                        .route("/keys/:key_id/usage", get(handlers::get_api_key_usage))
+                        .route("/keys", post(handlers::create_api_key))
+                        .route(
+                            "/settings",
+                            get(handlers::get_organization_settings)
+                                .put(handlers::update_organization_settings),
+                        )
+                        .route("/billing/plans", get(handlers::get_billing_plans))
+                        .route("/billing/preview", post(handlers::preview_tier_change))
+                        .route("/billing/tier", put(handlers::change_tier))
+                        .route("/billing/checkout", post(handlers::create_checkout_session))
+                        .route("/billing/portal", post(handlers::create_billing_portal_session))
+                        .route("/usage", get(handlers::get_organization_usage))
+                        .route("/usage/reconciliation", get(handlers::get_usage_reconciliation))
+                        .route(
+                            "/usage/stripe-reports/retry",
+                            post(handlers::retry_failed_stripe_reports),
+                        ),
                 )
                 .route(
                     "/organization_accounts/create",
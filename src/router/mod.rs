@@ -1,2 +1,6 @@
 mod api;
+
+#[cfg(test)]
+mod tests;
+
 pub use api::api_router;
\ No newline at end of file
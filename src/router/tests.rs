@@ -0,0 +1,2299 @@
+// Runs every API key route with a key of each type against a real database.
+// The tests are ignored unless asked for and need TEST_DATABASE_URL to point at a
+// migrated database, e.g.
+// TEST_DATABASE_URL=postgres://postgres@localhost:5432/chat_test cargo test -- --include-ignored
+
+use super::api_router;
+use crate::config::{ApiKeyPepper, BillingProvider, Database, InMemoryBilling, RedisConfig, RedisStore};
+use crate::entities::sea_orm_active_enums::{ApiKeyType, OrganizationRole, OrganizationTier};
+use crate::entities::{
+    api_key_audit_events, api_keys, channel_participant, channels, message_reactions, messages, organization_members,
+    organization_tiers, organizations, participant, stripe_usage_counters, stripe_usage_reports, users,
+};
+use crate::handlers::check_access;
+use crate::jobs::key_usage_flusher::flush_key_denials;
+use crate::jobs::stripe_usage_reporter::{claim_pending_usage, report_stripe_usage, retry_failed_reports};
+use crate::jobs::usage_reconciler::reconcile_organization;
+use crate::middleware::api_key_authorizer::key_type_level;
+use crate::middleware::client_authorizer::ClientCredential;
+use crate::middleware::socket_authorizer::SocketAuthorizer;
+use crate::middleware::usage_tracker::record_stripe_usage;
+use crate::realtime::{ErrorCode, ErrorPayload};
+use crate::state::AppState;
+use crate::utils::{
+    encode_participant_token, generate_api_key, generate_api_key_prefix, hash_api_key, sign_stripe_payload,
+    ParticipantClaims, PARTICIPANT_TOKEN_AUDIENCE,
+};
+use axum::body::{to_bytes, Body};
+use axum::Router;
+use chrono::Utc;
+use http::{Method, Request, StatusCode};
+use sea_orm::prelude::Expr;
+use sea_orm::*;
+use serde_json::json;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Once};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+type TestResult = Result<(), Box<dyn Error>>;
+
+const TEST_API_KEY_PEPPER: &str = "test-pepper-at-least-thirty-two-bytes";
+const TEST_STRIPE_WEBHOOK_SECRET: &str = "whsec_test";
+
+const KEY_TYPES: [ApiKeyType; 3] = [ApiKeyType::ReadOnly, ApiKeyType::ReadWrite, ApiKeyType::Admin];
+
+struct Fixture {
+    user_id: Uuid,
+    organization_id: Uuid,
+    channel_id: Uuid,
+    participant_id: Uuid,
+    message_id: Uuid,
+    keys: Vec<(ApiKeyType, String)>,
+}
+
+struct RouteCase {
+    method: Method,
+    path: String,
+    body: Option<serde_json::Value>,
+    required: ApiKeyType,
+}
+
+static TEST_ENV: Once = Once::new();
+
+// Fails instead of skipping, so a missing or unreachable database can't pass as green
+async fn connect() -> Result<DatabaseConnection, Box<dyn Error>> {
+    let url = std::env::var("TEST_DATABASE_URL")
+        .map_err(|_| "TEST_DATABASE_URL must point at a migrated database to run these tests")?;
+    set_test_env();
+    Ok(sea_orm::Database::connect(url).await?)
+}
+
+// The handlers read their configuration from the environment. It is set once, before
+// the first test gets to read it, and never changed by the tests themselves.
+fn set_test_env() {
+    TEST_ENV.call_once(|| {
+        if std::env::var("JWT_SECRET").is_err() {
+            std::env::set_var("JWT_SECRET", "test-jwt-secret");
+        }
+        // `oneshot` requests have no connection, so client addresses come from this header
+        std::env::set_var("TRUSTED_PROXY_HEADER", "X-Forwarded-For");
+        std::env::set_var("STRIPE_WEBHOOK_SECRET", TEST_STRIPE_WEBHOOK_SECRET);
+        // Only tiers with a Stripe price are available, enterprise is left without one
+        std::env::set_var("STRIPE_PRICE_ID_BASIC", "price_test_basic");
+        std::env::set_var("STRIPE_PRICE_ID_PRO", "price_test_pro");
+    });
+}
+
+fn test_state(connection: DatabaseConnection) -> Result<AppState, Box<dyn Error>> {
+    test_state_with_billing(connection, &InMemoryBilling::default())
+}
+
+fn test_state_with_billing(
+    connection: DatabaseConnection,
+    billing: &InMemoryBilling,
+) -> Result<AppState, Box<dyn Error>> {
+    // Redis is only used for usage counters, which fail open when it isn't running
+    Ok(AppState::new(
+        Database { connection },
+        RedisStore::new(RedisConfig::new())?,
+        Arc::new(billing.clone()),
+        ApiKeyPepper::new(TEST_API_KEY_PEPPER)?,
+    ))
+}
+
+async fn seed(db: &DatabaseConnection) -> Result<Fixture, Box<dyn Error>> {
+    let now = Utc::now().naive_utc();
+    let user_id = Uuid::new_v4();
+    let organization_id = Uuid::new_v4();
+    let channel_id = Uuid::new_v4();
+    let participant_id = Uuid::new_v4();
+    let message_id = Uuid::new_v4();
+
+    users::ActiveModel {
+        id: Set(user_id),
+        email: Set(format!("permissions-{}@example.com", user_id)),
+        password_hash: Set(String::new()),
+        created_at: Set(now),
+        updated_at: Set(now),
+    }
+    .insert(db)
+    .await?;
+
+    organizations::ActiveModel {
+        id: Set(organization_id),
+        name: Set("Permissions test".into()),
+        created_at: Set(now),
+        updated_at: Set(now),
+        stripe_customer_id: Set(None),
+        stripe_subscription_id: Set(None),
+        stripe_subscription_item_id: Set(None),
+        max_api_key_lifetime_days: Set(None),
+        stripe_subscription_status: Set(None),
+        stripe_event_created_at: Set(None),
+    }
+    .insert(db)
+    .await?;
+
+    let mut keys = Vec::new();
+    for key_type in KEY_TYPES {
+        let key = insert_key(db, organization_id, user_id, key_type.clone(), None, None).await?;
+        keys.push((key_type, key));
+    }
+
+    channels::ActiveModel {
+        id: Set(channel_id),
+        name: Set("general".into()),
+        created_at: Set(now),
+        updated_at: Set(now),
+        organization_id: Set(organization_id),
+        is_private: Set(false),
+    }
+    .insert(db)
+    .await?;
+
+    participant::ActiveModel {
+        id: Set(participant_id),
+        name: Set("Ada".into()),
+        created_at: Set(now),
+        updated_at: Set(now),
+        organization_id: Set(organization_id),
+        external_id: Set(Some("ext-1".into())),
+        display_name: Set(None),
+        avatar_url: Set(None),
+        metadata: Set(None),
+        deleted_at: Set(None),
+    }
+    .insert(db)
+    .await?;
+
+    messages::ActiveModel {
+        id: Set(message_id),
+        content: Set("hello world".into()),
+        channel_id: Set(channel_id),
+        participant_id: Set(participant_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        edited_at: Set(None),
+        deleted_at: Set(None),
+        parent_message_id: Set(None),
+    }
+    .insert(db)
+    .await?;
+
+    Ok(Fixture {
+        user_id,
+        organization_id,
+        channel_id,
+        participant_id,
+        message_id,
+        keys,
+    })
+}
+
+async fn insert_key(
+    db: &DatabaseConnection,
+    organization_id: Uuid,
+    user_id: Uuid,
+    key_type: ApiKeyType,
+    scopes: Option<Vec<String>>,
+    channel_ids: Option<Vec<Uuid>>,
+) -> Result<String, Box<dyn Error>> {
+    let now = Utc::now().naive_utc();
+    let key = generate_api_key()?;
+
+    api_keys::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        organization_id: Set(organization_id),
+        name: Set(format!("{:?}", key_type)),
+        key: Set(None),
+        key_hmac: Set(Some(hash_api_key(&key, TEST_API_KEY_PEPPER)?)),
+        key_type: Set(key_type),
+        created_by_user_id: Set(user_id),
+        last_used_at: Set(None),
+        expires_at: Set(None),
+        created_at: Set(now),
+        updated_at: Set(now),
+        key_prefix: Set(generate_api_key_prefix(&key)),
+        scopes: Set(scopes),
+        channel_ids: Set(channel_ids),
+        previous_key_hmac: Set(None),
+        previous_key_expires_at: Set(None),
+        previous_key_last_used_at: Set(None),
+        revoked_at: Set(None),
+        allowed_ips: Set(None),
+        allowed_origins: Set(None),
+    }
+    .insert(db)
+    .await?;
+
+    Ok(key)
+}
+
+async fn cleanup(db: &DatabaseConnection, fixture: &Fixture) -> TestResult {
+    // Keys, channels, participants and messages cascade with the organization
+    organizations::Entity::delete_by_id(fixture.organization_id)
+        .exec(db)
+        .await?;
+    users::Entity::delete_by_id(fixture.user_id).exec(db).await?;
+    Ok(())
+}
+
+// Destructive routes come last, so earlier cases still find the seeded rows
+fn route_cases(fixture: &Fixture) -> Vec<RouteCase> {
+    let org = format!("/api/organizations/{}", fixture.organization_id);
+    let channel = fixture.channel_id;
+    let participant = fixture.participant_id;
+    let message = fixture.message_id;
+
+    let case = |method: Method, path: String, body: Option<serde_json::Value>, required: ApiKeyType| {
+        RouteCase {
+            method,
+            path: format!("{}{}", org, path),
+            body,
+            required,
+        }
+    };
+
+    vec![
+        case(Method::GET, "/participants".into(), None, ApiKeyType::ReadOnly),
+        case(Method::GET, "/participants/count".into(), None, ApiKeyType::ReadOnly),
+        case(Method::GET, "/participants/external/ext-1".into(), None, ApiKeyType::ReadOnly),
+        case(Method::GET, format!("/participants/{}", participant), None, ApiKeyType::ReadOnly),
+        case(Method::GET, "/channels".into(), None, ApiKeyType::ReadOnly),
+        case(Method::GET, format!("/channels/{}", channel), None, ApiKeyType::ReadOnly),
+        case(Method::GET, format!("/channels/{}/members", channel), None, ApiKeyType::ReadOnly),
+        case(Method::GET, format!("/messages/{}", channel), None, ApiKeyType::ReadOnly),
+        case(Method::GET, format!("/messages/{}/replies", message), None, ApiKeyType::ReadOnly),
+        case(Method::GET, "/messages/search?q=hello".into(), None, ApiKeyType::ReadOnly),
+        case(Method::GET, "/usage".into(), None, ApiKeyType::ReadOnly),
+        case(
+            Method::POST,
+            "/participants".into(),
+            Some(json!({ "name": "Grace" })),
+            ApiKeyType::ReadWrite,
+        ),
+        case(
+            Method::PUT,
+            "/participants/external/ext-2".into(),
+            Some(json!({ "name": "Linus" })),
+            ApiKeyType::ReadWrite,
+        ),
+        case(
+            Method::PATCH,
+            format!("/participants/{}", participant),
+            Some(json!({ "display_name": "Ada L." })),
+            ApiKeyType::ReadWrite,
+        ),
+        case(
+            Method::POST,
+            format!("/participants/{}/tokens", participant),
+            Some(json!({})),
+            ApiKeyType::ReadWrite,
+        ),
+        case(
+            Method::POST,
+            "/channels".into(),
+            Some(json!({ "name": "random" })),
+            ApiKeyType::ReadWrite,
+        ),
+        case(
+            Method::POST,
+            format!("/channels/{}/members", channel),
+            Some(json!({ "participant_id": participant })),
+            ApiKeyType::ReadWrite,
+        ),
+        case(
+            Method::DELETE,
+            format!("/channels/{}/members/{}", channel, participant),
+            None,
+            ApiKeyType::ReadWrite,
+        ),
+        case(
+            Method::POST,
+            "/messages".into(),
+            Some(json!({
+                "content": "hi",
+                "participant_id": participant,
+                "channel_name": "general",
+            })),
+            ApiKeyType::ReadWrite,
+        ),
+        case(
+            Method::POST,
+            format!("/messages/{}/reactions", message),
+            Some(json!({ "emoji": "👍", "participant_id": participant })),
+            ApiKeyType::ReadWrite,
+        ),
+        case(
+            Method::DELETE,
+            // 👍, percent-encoded
+            format!("/messages/{}/reactions/%F0%9F%91%8D?participant_id={}", message, participant),
+            None,
+            ApiKeyType::ReadWrite,
+        ),
+        case(
+            Method::PATCH,
+            format!("/messages/{}", message),
+            Some(json!({ "content": "edited" })),
+            ApiKeyType::ReadWrite,
+        ),
+        case(Method::DELETE, format!("/messages/{}", message), None, ApiKeyType::ReadWrite),
+        case(Method::DELETE, format!("/participants/{}", participant), None, ApiKeyType::Admin),
+    ]
+}
+
+async fn send(
+    app: &Router,
+    route: &RouteCase,
+    key: &str,
+) -> Result<(StatusCode, String), Box<dyn Error>> {
+    let mut request = Request::builder()
+        .method(route.method.clone())
+        .uri(&route.path)
+        .header("X-API-Key", key);
+
+    let body = match &route.body {
+        Some(body) => {
+            request = request.header("Content-Type", "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+
+    let response = app.clone().oneshot(request.body(body)?).await?;
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+
+    Ok((status, String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn routes_require_their_minimum_key_type() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+
+    let mut failures = Vec::new();
+    for route in route_cases(&fixture) {
+        // Weaker keys first, so a destructive route only succeeds on its last run
+        for (key_type, key) in &fixture.keys {
+            let (status, body) = send(&app, &route, key).await?;
+            let denied = status == StatusCode::FORBIDDEN && body.contains("Insufficient permissions");
+            let should_deny = key_type_level(key_type) < key_type_level(&route.required);
+
+            if denied != should_deny {
+                failures.push(format!(
+                    "{} {} with {:?} key: expected {}, got {} {}",
+                    route.method,
+                    route.path,
+                    key_type,
+                    if should_deny { "403" } else { "access" },
+                    status,
+                    body
+                ));
+            }
+        }
+    }
+
+    cleanup(&db, &fixture).await?;
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn scoped_keys_only_reach_their_scopes_and_channels() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+
+    let now = Utc::now().naive_utc();
+    let other_channel_id = Uuid::new_v4();
+    channels::ActiveModel {
+        id: Set(other_channel_id),
+        name: Set("elsewhere".into()),
+        created_at: Set(now),
+        updated_at: Set(now),
+        organization_id: Set(fixture.organization_id),
+        is_private: Set(false),
+    }
+    .insert(&db)
+    .await?;
+
+    let key = insert_key(
+        &db,
+        fixture.organization_id,
+        fixture.user_id,
+        ApiKeyType::ReadWrite,
+        Some(vec!["messages:read".into(), "channels:read".into()]),
+        Some(vec![fixture.channel_id]),
+    )
+    .await?;
+
+    let org = format!("/api/organizations/{}", fixture.organization_id);
+    let get = |path: String| RouteCase {
+        method: Method::GET,
+        path: format!("{}{}", org, path),
+        body: None,
+        required: ApiKeyType::ReadOnly,
+    };
+
+    let (allowed_channel_status, _) =
+        send(&app, &get(format!("/messages/{}", fixture.channel_id)), &key).await?;
+    let (other_channel_status, _) =
+        send(&app, &get(format!("/messages/{}", other_channel_id)), &key).await?;
+    let (channels_status, channels_body) = send(&app, &get("/channels".into()), &key).await?;
+
+    let (participants_status, participants_body) = send(&app, &get("/participants".into()), &key).await?;
+    let (usage_status, usage_body) = send(&app, &get("/usage".into()), &key).await?;
+
+    let create_message = RouteCase {
+        method: Method::POST,
+        path: format!("{}/messages", org),
+        body: Some(json!({
+            "content": "hi",
+            "participant_id": fixture.participant_id,
+            "channel_name": "general",
+        })),
+        required: ApiKeyType::ReadWrite,
+    };
+    let (create_status, create_body) = send(&app, &create_message, &key).await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(allowed_channel_status, StatusCode::OK);
+    assert_eq!(other_channel_status, StatusCode::FORBIDDEN);
+    assert_eq!(channels_status, StatusCode::OK);
+    assert!(channels_body.contains(&fixture.channel_id.to_string()));
+    assert!(!channels_body.contains(&other_channel_id.to_string()));
+    assert_eq!(participants_status, StatusCode::FORBIDDEN);
+    assert!(participants_body.contains("participants:read"), "{}", participants_body);
+    assert_eq!(usage_status, StatusCode::FORBIDDEN);
+    assert!(usage_body.contains("usage:read"), "{}", usage_body);
+    assert_eq!(create_status, StatusCode::FORBIDDEN);
+    assert!(create_body.contains("messages:write"), "{}", create_body);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn search_snippets_escape_the_message_content() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+
+    let now = Utc::now().naive_utc();
+    messages::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        content: Set("<img src=x onerror=alert(1)> needle & <script>steal()</script>".into()),
+        channel_id: Set(fixture.channel_id),
+        participant_id: Set(fixture.participant_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        edited_at: Set(None),
+        deleted_at: Set(None),
+        parent_message_id: Set(None),
+    }
+    .insert(&db)
+    .await?;
+
+    let search = RouteCase {
+        method: Method::GET,
+        path: format!("/api/organizations/{}/messages/search?q=needle", fixture.organization_id),
+        body: None,
+        required: ApiKeyType::ReadOnly,
+    };
+    let (status, body) = send(&app, &search, &fixture.keys[0].1).await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let body: serde_json::Value = serde_json::from_str(&body)?;
+    let snippet = body["data"][0]["snippet"].as_str().unwrap_or_default();
+    assert!(snippet.contains("<mark>needle</mark>"), "{}", snippet);
+    assert!(snippet.contains("&lt;script&gt;"), "{}", snippet);
+    assert!(snippet.contains(" &amp; "), "{}", snippet);
+    assert!(!snippet.contains("<img") && !snippet.contains("<script"), "{}", snippet);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn bcrypt_keys_are_upgraded_on_first_use() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+
+    // Keys created before the switch to HMACs looked like this
+    let now = Utc::now().naive_utc();
+    let key_id = Uuid::new_v4();
+    let key = format!("sk_{}", Uuid::new_v4());
+    api_keys::ActiveModel {
+        id: Set(key_id),
+        organization_id: Set(fixture.organization_id),
+        name: Set("Legacy".into()),
+        // The lowest cost keeps verification fast in debug builds
+        key: Set(Some(bcrypt::hash(&key, 4)?)),
+        key_hmac: Set(None),
+        key_type: Set(ApiKeyType::ReadOnly),
+        created_by_user_id: Set(fixture.user_id),
+        last_used_at: Set(None),
+        expires_at: Set(None),
+        created_at: Set(now),
+        updated_at: Set(now),
+        key_prefix: Set(generate_api_key_prefix(&key)),
+        scopes: Set(None),
+        channel_ids: Set(None),
+        previous_key_hmac: Set(None),
+        previous_key_expires_at: Set(None),
+        previous_key_last_used_at: Set(None),
+        revoked_at: Set(None),
+        allowed_ips: Set(None),
+        allowed_origins: Set(None),
+    }
+    .insert(&db)
+    .await?;
+
+    let route = RouteCase {
+        method: Method::GET,
+        path: format!("/api/organizations/{}/channels", fixture.organization_id),
+        body: None,
+        required: ApiKeyType::ReadOnly,
+    };
+    let (first_status, _) = send(&app, &route, &key).await?;
+    let upgraded = api_keys::Entity::find_by_id(key_id).one(&db).await?;
+    let (second_status, _) = send(&app, &route, &key).await?;
+    let (wrong_key_status, _) = send(&app, &route, &format!("sk_{}", Uuid::new_v4())).await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(first_status, StatusCode::OK);
+    assert_eq!(second_status, StatusCode::OK);
+    assert_eq!(wrong_key_status, StatusCode::UNAUTHORIZED);
+
+    let upgraded = upgraded.ok_or("legacy key disappeared")?;
+    assert_eq!(upgraded.key, None);
+    assert_eq!(upgraded.key_hmac, Some(hash_api_key(&key, TEST_API_KEY_PEPPER)?));
+    Ok(())
+}
+
+// Signs in `user_id` to the dashboard as an owner of the fixture's organization
+async fn dashboard_token(db: &DatabaseConnection, fixture: &Fixture) -> Result<String, Box<dyn Error>> {
+    let now = Utc::now().naive_utc();
+    organization_members::ActiveModel {
+        user_id: Set(fixture.user_id),
+        organization_id: Set(fixture.organization_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        role: Set(OrganizationRole::Owner),
+    }
+    .insert(db)
+    .await?;
+
+    let claims = json!({
+        "user_id": fixture.user_id,
+        "email": format!("permissions-{}@example.com", fixture.user_id),
+        "exp": (Utc::now() + chrono::Duration::hours(1)).timestamp(),
+    });
+
+    Ok(jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(std::env::var("JWT_SECRET")?.as_bytes()),
+    )?)
+}
+
+// Sends a dashboard request and returns the status with the response's `data`
+async fn send_dashboard(
+    app: &Router,
+    method: Method,
+    path: String,
+    token: &str,
+    body: Option<serde_json::Value>,
+) -> Result<(StatusCode, serde_json::Value), Box<dyn Error>> {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header("Authorization", format!("Bearer {}", token));
+    let body = match body {
+        Some(body) => {
+            request = request.header("Content-Type", "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+
+    let response = app.clone().oneshot(request.body(body)?).await?;
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+    let body: serde_json::Value = serde_json::from_slice(&bytes)?;
+
+    Ok((status, body["data"].clone()))
+}
+
+async fn rotate(
+    app: &Router,
+    fixture: &Fixture,
+    key_id: Uuid,
+    token: &str,
+    grace_period_seconds: i64,
+) -> Result<serde_json::Value, Box<dyn Error>> {
+    let (_, data) = send_dashboard(
+        app,
+        Method::POST,
+        format!("/api/organizations/{}/keys/{}/rotate", fixture.organization_id, key_id),
+        token,
+        Some(json!({ "grace_period_seconds": grace_period_seconds })),
+    )
+    .await?;
+
+    Ok(data)
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rotated_keys_keep_the_old_secret_for_the_grace_period() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+    let token = dashboard_token(&db, &fixture).await?;
+
+    let (_, original) = &fixture.keys[0];
+    let key_id = api_keys::Entity::find()
+        .filter(api_keys::Column::KeyHmac.eq(hash_api_key(original, TEST_API_KEY_PEPPER)?))
+        .one(&db)
+        .await?
+        .ok_or("seeded key not found")?
+        .id;
+
+    let route = RouteCase {
+        method: Method::GET,
+        path: format!("/api/organizations/{}/channels", fixture.organization_id),
+        body: None,
+        required: ApiKeyType::ReadOnly,
+    };
+
+    let first = rotate(&app, &fixture, key_id, &token, 3600).await?;
+    let rotated = first["key"].as_str().unwrap_or_default().to_string();
+    let (original_in_grace, _) = send(&app, &route, original).await?;
+    let (rotated_after_first, _) = send(&app, &route, &rotated).await?;
+
+    let second = rotate(&app, &fixture, key_id, &token, 0).await?;
+    let latest = second["key"].as_str().unwrap_or_default().to_string();
+    let (original_after_second, _) = send(&app, &route, original).await?;
+    let (rotated_after_second, _) = send(&app, &route, &rotated).await?;
+    let (latest_after_second, _) = send(&app, &route, &latest).await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert!(first["previous_key_expires_at"].is_string(), "{}", first);
+    assert_ne!(rotated, *original);
+    assert_eq!(original_in_grace, StatusCode::OK);
+    assert_eq!(rotated_after_first, StatusCode::OK);
+
+    // A zero grace period revokes the replaced secret right away
+    assert_eq!(original_after_second, StatusCode::UNAUTHORIZED);
+    assert_eq!(rotated_after_second, StatusCode::UNAUTHORIZED);
+    assert_eq!(latest_after_second, StatusCode::OK);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn keys_expire_within_the_organization_lifetime_and_get_revoked() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let state = test_state(db.clone())?;
+    let app = api_router().with_state(state.clone());
+    let token = dashboard_token(&db, &fixture).await?;
+    let keys_path = format!("/api/organizations/{}/keys", fixture.organization_id);
+
+    let (settings_status, _) = send_dashboard(
+        &app,
+        Method::PUT,
+        format!("/api/organizations/{}/settings", fixture.organization_id),
+        &token,
+        Some(json!({ "max_api_key_lifetime_days": 30 })),
+    )
+    .await?;
+
+    let (default_status, default_key) = send_dashboard(
+        &app,
+        Method::POST,
+        keys_path.clone(),
+        &token,
+        Some(json!({ "name": "Default", "key_type": "ReadOnly" })),
+    )
+    .await?;
+    let (ttl_status, ttl_key) = send_dashboard(
+        &app,
+        Method::POST,
+        keys_path.clone(),
+        &token,
+        Some(json!({ "name": "Short", "key_type": "ReadOnly", "expires_in_seconds": 3600 })),
+    )
+    .await?;
+    let (_, soon_key) = send_dashboard(
+        &app,
+        Method::POST,
+        keys_path.clone(),
+        &token,
+        Some(json!({ "name": "Soon", "key_type": "ReadOnly", "expires_in_seconds": 86400 })),
+    )
+    .await?;
+    let too_late = Utc::now() + chrono::Duration::days(31);
+    let (too_late_status, _) = send_dashboard(
+        &app,
+        Method::POST,
+        keys_path.clone(),
+        &token,
+        Some(json!({ "name": "Too late", "key_type": "ReadOnly", "expires_at": too_late })),
+    )
+    .await?;
+    let (both_status, _) = send_dashboard(
+        &app,
+        Method::POST,
+        keys_path.clone(),
+        &token,
+        Some(json!({
+            "name": "Both",
+            "key_type": "ReadOnly",
+            "expires_at": Utc::now() + chrono::Duration::days(1),
+            "expires_in_seconds": 3600,
+        })),
+    )
+    .await?;
+
+    // Let the short lived key expire without waiting for it
+    let ttl_key_id = ttl_key["id"].as_str().unwrap_or_default().parse::<Uuid>()?;
+    api_keys::ActiveModel {
+        id: Set(ttl_key_id),
+        expires_at: Set(Some(Utc::now().naive_utc() - chrono::Duration::minutes(1))),
+        ..Default::default()
+    }
+    .update(&db)
+    .await?;
+    let (listed_before_sweep_status, listed_before_sweep) =
+        send_dashboard(&app, Method::GET, keys_path.clone(), &token, None).await?;
+
+    crate::jobs::api_key_sweeper::sweep_expired_api_keys(&state).await?;
+
+    let revoked = api_keys::Entity::find_by_id(ttl_key_id).one(&db).await?;
+    let route = RouteCase {
+        method: Method::GET,
+        path: format!("/api/organizations/{}/channels", fixture.organization_id),
+        body: None,
+        required: ApiKeyType::ReadOnly,
+    };
+    let (revoked_use, _) = send(&app, &route, ttl_key["key"].as_str().unwrap_or_default()).await?;
+    let (default_use, _) = send(&app, &route, default_key["key"].as_str().unwrap_or_default()).await?;
+    let (_, listed) = send_dashboard(&app, Method::GET, keys_path, &token, None).await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(settings_status, StatusCode::OK);
+    assert_eq!(default_status, StatusCode::CREATED);
+    assert_eq!(ttl_status, StatusCode::CREATED);
+    assert_eq!(too_late_status, StatusCode::BAD_REQUEST);
+    assert_eq!(both_status, StatusCode::BAD_REQUEST);
+
+    // Keys without an expiry get the organization's maximum lifetime
+    let default_expires_at: chrono::DateTime<Utc> =
+        serde_json::from_value(default_key["expires_at"].clone())?;
+    let expected = Utc::now() + chrono::Duration::days(30);
+    assert!((expected - default_expires_at).num_minutes().abs() < 5, "{}", default_key);
+
+    let find = |keys: &serde_json::Value, id: &serde_json::Value| {
+        keys.as_array()
+            .and_then(|keys| keys.iter().find(|key| key["id"] == *id).cloned())
+            .unwrap_or_default()
+    };
+    assert_eq!(listed_before_sweep_status, StatusCode::OK);
+    assert_eq!(find(&listed_before_sweep, &default_key["id"])["expiring_soon"], false);
+    assert_eq!(find(&listed_before_sweep, &soon_key["id"])["expiring_soon"], true);
+    assert!(find(&listed_before_sweep, &ttl_key["id"])["revoked_at"].is_null());
+
+    assert!(revoked.is_some_and(|key| key.revoked_at.is_some()));
+    assert_eq!(revoked_use, StatusCode::UNAUTHORIZED);
+    assert_eq!(default_use, StatusCode::OK);
+    assert!(find(&listed, &ttl_key["id"])["revoked_at"].is_string(), "{}", listed);
+    assert_eq!(find(&listed, &ttl_key["id"])["expiring_soon"], false);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn authorized_requests_are_counted_per_key_and_day() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let state = test_state(db.clone())?;
+    let app = api_router().with_state(state.clone());
+    let token = dashboard_token(&db, &fixture).await?;
+    let flush = || crate::jobs::key_usage_flusher::flush_key_usage(&state);
+
+    let (_, read_only) = &fixture.keys[0];
+    let (_, admin) = &fixture.keys[2];
+    let route = RouteCase {
+        method: Method::GET,
+        path: format!("/api/organizations/{}/channels", fixture.organization_id),
+        body: None,
+        required: ApiKeyType::ReadOnly,
+    };
+    let forbidden = RouteCase {
+        method: Method::DELETE,
+        path: format!(
+            "/api/organizations/{}/participants/{}",
+            fixture.organization_id, fixture.participant_id
+        ),
+        body: None,
+        required: ApiKeyType::Admin,
+    };
+
+    // Two flushes, so the second one has to add to the existing row
+    send(&app, &route, read_only).await?;
+    send(&app, &route, read_only).await?;
+    flush().await?;
+    send(&app, &route, read_only).await?;
+    send(&app, &route, admin).await?;
+    // Rejected requests aren't counted
+    send(&app, &forbidden, read_only).await?;
+    send(&app, &route, "sk_not_a_key").await?;
+    flush().await?;
+
+    let (status, usage) = send_dashboard(
+        &app,
+        Method::GET,
+        format!("/api/organizations/{}/keys/usage", fixture.organization_id),
+        &token,
+        None,
+    )
+    .await?;
+    let (bad_range, _) = send_dashboard(
+        &app,
+        Method::GET,
+        format!(
+            "/api/organizations/{}/keys/usage?from=2025-02-01&to=2025-01-01",
+            fixture.organization_id
+        ),
+        &token,
+        None,
+    )
+    .await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(bad_range, StatusCode::BAD_REQUEST);
+
+    let keys = usage.as_array().cloned().unwrap_or_default();
+    let totals: Vec<(String, i64)> = keys
+        .iter()
+        .map(|key| {
+            (
+                key["name"].as_str().unwrap_or_default().to_string(),
+                key["total_requests"].as_i64().unwrap_or_default(),
+            )
+        })
+        .collect();
+    // Busiest first
+    assert_eq!(
+        totals,
+        vec![
+            ("ReadOnly".to_string(), 3),
+            ("Admin".to_string(), 1),
+            ("ReadWrite".to_string(), 0),
+        ]
+    );
+
+    let days = keys[0]["days"].as_array().cloned().unwrap_or_default();
+    assert_eq!(days.len(), 30);
+    assert_eq!(days[29]["date"], Utc::now().date_naive().to_string());
+    assert_eq!(days[29]["request_count"], 3);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn keys_are_only_accepted_from_their_allowed_ips_and_origins() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let state = test_state(db.clone())?;
+    let app = api_router().with_state(state.clone());
+    let token = dashboard_token(&db, &fixture).await?;
+
+    let (_, key) = &fixture.keys[0];
+    let key_id = api_keys::Entity::find()
+        .filter(api_keys::Column::KeyHmac.eq(hash_api_key(key, TEST_API_KEY_PEPPER)?))
+        .one(&db)
+        .await?
+        .ok_or("seeded key not found")?
+        .id;
+    let key_path = format!("/api/organizations/{}/keys/{}", fixture.organization_id, key_id);
+
+    let (restricted_status, restricted) = send_dashboard(
+        &app,
+        Method::PATCH,
+        key_path.clone(),
+        &token,
+        Some(json!({
+            "allowed_ips": ["203.0.113.0/24", "2001:db8::1"],
+            "allowed_origins": ["https://app.example.com/"],
+        })),
+    )
+    .await?;
+    let (invalid_status, _) = send_dashboard(
+        &app,
+        Method::PATCH,
+        key_path.clone(),
+        &token,
+        Some(json!({ "allowed_ips": ["not-an-ip"] })),
+    )
+    .await?;
+
+    let channels = format!("/api/organizations/{}/channels", fixture.organization_id);
+    let request = |headers: &[(&str, &str)]| {
+        let mut request = Request::builder()
+            .method(Method::GET)
+            .uri(&channels)
+            .header("X-API-Key", key.as_str());
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(Body::empty())
+    };
+    let status_and_code = |request: Request<Body>| {
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await?;
+            let status = response.status();
+            let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+            let body: serde_json::Value = serde_json::from_slice(&bytes)?;
+            Ok::<_, Box<dyn Error>>((status, body["error"]["code"].clone()))
+        }
+    };
+
+    // The proxy appends the address it saw, anything before it comes from the client
+    let allowed = status_and_code(request(&[
+        ("X-Forwarded-For", "198.51.100.1, 203.0.113.5"),
+        ("Origin", "https://app.example.com"),
+    ])?)
+    .await?;
+    let from_referer = status_and_code(request(&[
+        ("X-Forwarded-For", "2001:db8::1"),
+        ("Referer", "https://app.example.com/dashboard?tab=1"),
+    ])?)
+    .await?;
+    let spoofed_ip = status_and_code(request(&[
+        ("X-Forwarded-For", "203.0.113.5, 198.51.100.1"),
+        ("Origin", "https://app.example.com"),
+    ])?)
+    .await?;
+    let wrong_origin = status_and_code(request(&[
+        ("X-Forwarded-For", "203.0.113.5"),
+        ("Origin", "https://evil.example.com"),
+    ])?)
+    .await?;
+    // Retries from the same client are audited once, with how often they happened
+    let wrong_origin_again = status_and_code(request(&[
+        ("X-Forwarded-For", "203.0.113.5"),
+        ("Origin", "https://evil.example.com"),
+    ])?)
+    .await?;
+    let no_headers = status_and_code(request(&[])?).await?;
+
+    let (cleared_status, _) = send_dashboard(
+        &app,
+        Method::PATCH,
+        key_path,
+        &token,
+        Some(json!({ "allowed_ips": null, "allowed_origins": null })),
+    )
+    .await?;
+    let after_clearing = status_and_code(request(&[])?).await?;
+
+    // Denials are buffered until the key usage flusher writes them
+    let before_flush = api_key_audit_events::Entity::find()
+        .filter(api_key_audit_events::Column::ApiKeyId.eq(key_id))
+        .count(&db)
+        .await?;
+    flush_key_denials(&state).await?;
+    let audit_events = api_key_audit_events::Entity::find()
+        .filter(api_key_audit_events::Column::ApiKeyId.eq(key_id))
+        .all(&db)
+        .await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(restricted_status, StatusCode::OK);
+    assert_eq!(
+        restricted["allowed_ips"],
+        json!(["2001:db8::1/128", "203.0.113.0/24"])
+    );
+    assert_eq!(restricted["allowed_origins"], json!(["https://app.example.com"]));
+    assert_eq!(invalid_status, StatusCode::BAD_REQUEST);
+
+    assert_eq!(allowed.0, StatusCode::OK);
+    assert_eq!(from_referer.0, StatusCode::OK);
+    for denied in [&spoofed_ip, &wrong_origin, &wrong_origin_again, &no_headers] {
+        assert_eq!(denied.0, StatusCode::FORBIDDEN);
+        assert_eq!(denied.1, "ClientNotAllowed");
+    }
+
+    assert_eq!(cleared_status, StatusCode::OK);
+    assert_eq!(after_clearing.0, StatusCode::OK);
+
+    assert_eq!(before_flush, 0);
+    let mut events: Vec<(String, Option<String>, i32)> = audit_events
+        .into_iter()
+        .map(|event| (event.event, event.client_ip, event.occurrences))
+        .collect();
+    events.sort();
+    assert_eq!(
+        events,
+        vec![
+            ("ip_not_allowed".to_string(), None, 1),
+            ("ip_not_allowed".to_string(), Some("198.51.100.1".to_string()), 1),
+            ("origin_not_allowed".to_string(), Some("203.0.113.5".to_string()), 2),
+        ]
+    );
+    Ok(())
+}
+
+// Replays a recorded Stripe event for `customer_id` and `subscription_id`. Event ids
+// get `run` appended, so that replays from earlier test runs aren't duplicates.
+async fn replay_stripe_event(
+    app: &Router,
+    fixture: &str,
+    customer_id: &str,
+    subscription_id: &str,
+    run: &str,
+    secret: &str,
+) -> Result<(StatusCode, serde_json::Value), Box<dyn Error>> {
+    let mut event: serde_json::Value = serde_json::from_str(fixture)?;
+    event["id"] = json!(format!("{}_{}", event["id"].as_str().unwrap_or_default(), run));
+    let payload = event
+        .to_string()
+        .replace("cus_fixture", customer_id)
+        .replace("sub_fixture", subscription_id);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/webhooks/stripe")
+        .header("Content-Type", "application/json")
+        .header(
+            "Stripe-Signature",
+            sign_stripe_payload(payload.as_bytes(), secret, Utc::now().timestamp()),
+        )
+        .body(Body::from(payload))?;
+
+    let response = app.clone().oneshot(request).await?;
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+    let body: serde_json::Value = serde_json::from_slice(&bytes)?;
+
+    Ok((status, body["data"].clone()))
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn stripe_webhooks_keep_the_subscription_and_tier_in_sync() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+    let run = Uuid::new_v4().simple().to_string();
+    let customer_id = format!("cus_{}", run);
+    let subscription_id = format!("sub_{}", run);
+    let now = Utc::now().naive_utc();
+
+    organizations::ActiveModel {
+        id: Set(fixture.organization_id),
+        stripe_customer_id: Set(Some(customer_id.clone())),
+        stripe_subscription_id: Set(Some(subscription_id.clone())),
+        stripe_subscription_item_id: Set(Some("si_fixture_basic".into())),
+        ..Default::default()
+    }
+    .update(&db)
+    .await?;
+    organization_tiers::ActiveModel {
+        organization_id: Set(fixture.organization_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        monthly_request_limit: Set(50_000),
+        current_month_usage: Set(0),
+        last_reset_at: Set(Utc::now().fixed_offset()),
+        tier: Set(OrganizationTier::Basic),
+    }
+    .insert(&db)
+    .await?;
+
+    let state_of = |db: DatabaseConnection| async move {
+        let organization = organizations::Entity::find_by_id(fixture.organization_id)
+            .one(&db)
+            .await?
+            .ok_or("organization not found")?;
+        let tier = organization_tiers::Entity::find_by_id(fixture.organization_id)
+            .one(&db)
+            .await?
+            .ok_or("tier not found")?;
+        Ok::<_, Box<dyn Error>>((
+            organization.stripe_subscription_id,
+            organization.stripe_subscription_item_id,
+            organization.stripe_subscription_status,
+            tier.tier,
+            tier.monthly_request_limit,
+        ))
+    };
+    let replay = |fixture: &'static str, secret: &'static str| {
+        let app = app.clone();
+        let customer_id = customer_id.clone();
+        let subscription_id = subscription_id.clone();
+        let run = run.clone();
+        async move {
+            replay_stripe_event(&app, fixture, &customer_id, &subscription_id, &run, secret).await
+        }
+    };
+
+    let updated_fixture = include_str!("../../tests/fixtures/stripe/customer.subscription.updated.json");
+    let deleted_fixture = include_str!("../../tests/fixtures/stripe/customer.subscription.deleted.json");
+    let failed_fixture = include_str!("../../tests/fixtures/stripe/invoice.payment_failed.json");
+    let paid_fixture = include_str!("../../tests/fixtures/stripe/invoice.paid.json");
+    let unpaid_fixture =
+        include_str!("../../tests/fixtures/stripe/customer.subscription.updated.unpaid.json");
+    let portal_fixture =
+        include_str!("../../tests/fixtures/stripe/customer.subscription.updated.portal.json");
+
+    let forged = replay(updated_fixture, "whsec_forged").await?;
+    let after_forged = state_of(db.clone()).await?;
+
+    let updated = replay(updated_fixture, TEST_STRIPE_WEBHOOK_SECRET).await?;
+    let after_updated = state_of(db.clone()).await?;
+    let redelivered = replay(updated_fixture, TEST_STRIPE_WEBHOOK_SECRET).await?;
+
+    let failed = replay(failed_fixture, TEST_STRIPE_WEBHOOK_SECRET).await?;
+    let after_failed = state_of(db.clone()).await?;
+    let paid = replay(paid_fixture, TEST_STRIPE_WEBHOOK_SECRET).await?;
+    let after_paid = state_of(db.clone()).await?;
+    let unpaid = replay(unpaid_fixture, TEST_STRIPE_WEBHOOK_SECRET).await?;
+    let after_unpaid = state_of(db.clone()).await?;
+    let portal = replay(portal_fixture, TEST_STRIPE_WEBHOOK_SECRET).await?;
+    let after_portal = state_of(db.clone()).await?;
+
+    let deleted = replay(deleted_fixture, TEST_STRIPE_WEBHOOK_SECRET).await?;
+    let after_deleted = state_of(db.clone()).await?;
+    // Stripe retries the earlier update after the subscription was deleted
+    let late_run = format!("{}late", run);
+    let late = replay_stripe_event(
+        &app,
+        updated_fixture,
+        &customer_id,
+        &subscription_id,
+        &late_run,
+        TEST_STRIPE_WEBHOOK_SECRET,
+    )
+    .await?;
+    let after_late = state_of(db.clone()).await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(forged.0, StatusCode::BAD_REQUEST);
+    assert_eq!(after_forged.3, OrganizationTier::Basic);
+
+    assert_eq!(updated.0, StatusCode::OK);
+    assert_eq!(updated.1["outcome"], "processed");
+    assert_eq!(
+        after_updated,
+        (
+            Some(subscription_id.clone()),
+            Some("si_fixture_pro".to_string()),
+            Some("active".to_string()),
+            OrganizationTier::Pro,
+            500_000,
+        )
+    );
+    assert_eq!(redelivered.0, StatusCode::OK);
+    assert_eq!(redelivered.1["outcome"], "duplicate");
+
+    assert_eq!(failed.1["outcome"], "processed");
+    assert_eq!(after_failed.2.as_deref(), Some("past_due"));
+    // Newer API versions reference the subscription through `parent`
+    assert_eq!(paid.1["outcome"], "processed");
+    assert_eq!(after_paid.2.as_deref(), Some("active"));
+    // Stripe gave up on the payment, the subscription stays but the paid tier goes
+    assert_eq!(unpaid.1["outcome"], "processed");
+    assert_eq!(
+        after_unpaid,
+        (
+            Some(subscription_id.clone()),
+            Some("si_fixture_pro".to_string()),
+            Some("unpaid".to_string()),
+            OrganizationTier::Free,
+            5_000,
+        )
+    );
+    // A plan change in the Billing Portal swaps the price but keeps the old `tier` metadata
+    assert_eq!(portal.1["outcome"], "processed");
+    assert_eq!(
+        after_portal,
+        (
+            Some(subscription_id.clone()),
+            Some("si_fixture_basic".to_string()),
+            Some("active".to_string()),
+            OrganizationTier::Basic,
+            50_000,
+        )
+    );
+
+    assert_eq!(deleted.1["outcome"], "processed");
+    assert_eq!(
+        after_deleted,
+        (None, None, Some("canceled".to_string()), OrganizationTier::Free, 5_000)
+    );
+    assert_eq!(late.0, StatusCode::OK);
+    assert_eq!(late.1["outcome"], "outdated");
+    assert_eq!(after_late, after_deleted);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn owners_see_plans_and_cant_switch_to_unavailable_tiers() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+    let token = dashboard_token(&db, &fixture).await?;
+    let billing_path = format!("/api/organizations/{}/billing", fixture.organization_id);
+    let now = Utc::now().naive_utc();
+
+    organization_tiers::ActiveModel {
+        organization_id: Set(fixture.organization_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        monthly_request_limit: Set(50_000),
+        current_month_usage: Set(120),
+        last_reset_at: Set(Utc::now().fixed_offset()),
+        tier: Set(OrganizationTier::Basic),
+    }
+    .insert(&db)
+    .await?;
+
+    let (plans_status, plans) =
+        send_dashboard(&app, Method::GET, format!("{}/plans", billing_path), &token, None).await?;
+
+    let mut previews = Vec::new();
+    for tier in ["gold", "basic", "enterprise", "pro"] {
+        let (status, _) = send_dashboard(
+            &app,
+            Method::POST,
+            format!("{}/preview", billing_path),
+            &token,
+            Some(json!({ "tier": tier })),
+        )
+        .await?;
+        previews.push((tier, status));
+    }
+
+    let (switch_status, _) = send_dashboard(
+        &app,
+        Method::PUT,
+        format!("{}/tier", billing_path),
+        &token,
+        Some(json!({ "tier": "pro" })),
+    )
+    .await?;
+    let (checkout_status, _) = send_dashboard(
+        &app,
+        Method::POST,
+        format!("{}/checkout", billing_path),
+        &token,
+        Some(json!({
+            "tier": "pro",
+            "success_url": "javascript:alert(1)",
+            "cancel_url": "https://example.com/billing",
+        })),
+    )
+    .await?;
+    let (portal_status, _) = send_dashboard(
+        &app,
+        Method::POST,
+        format!("{}/portal", billing_path),
+        &token,
+        Some(json!({ "return_url": "https://example.com/billing" })),
+    )
+    .await?;
+    let tier = organization_tiers::Entity::find_by_id(fixture.organization_id)
+        .one(&db)
+        .await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(plans_status, StatusCode::OK);
+    assert_eq!(plans["current_tier"], "basic");
+    assert_eq!(plans["current_month_usage"], 120);
+    let plan = |name: &str| {
+        plans["plans"]
+            .as_array()
+            .and_then(|plans| plans.iter().find(|plan| plan["tier"] == name).cloned())
+            .unwrap_or_default()
+    };
+    assert_eq!(plan("basic")["current"], true);
+    assert_eq!(plan("pro")["available"], true);
+    assert_eq!(plan("pro")["monthly_request_limit"], 500_000);
+    assert_eq!(plan("enterprise")["available"], false);
+
+    // Unknown, current and unpriced tiers, then a real one without a subscription to change
+    for (tier, status) in previews {
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", tier);
+    }
+    assert_eq!(switch_status, StatusCode::BAD_REQUEST);
+    assert_eq!(checkout_status, StatusCode::BAD_REQUEST);
+    // No Stripe customer to open the portal for
+    assert_eq!(portal_status, StatusCode::BAD_REQUEST);
+    assert!(tier.is_some_and(|tier| tier.tier == OrganizationTier::Basic && tier.monthly_request_limit == 50_000));
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn stripe_usage_is_claimed_once_and_kept_until_stripe_accepts_it() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let billing = InMemoryBilling::default();
+    let state = test_state_with_billing(db.clone(), &billing)?;
+    let item_id = format!("si_{}", Uuid::new_v4().simple());
+
+    let reports_of = |db: DatabaseConnection, item_id: String| async move {
+        stripe_usage_reports::Entity::find()
+            .filter(stripe_usage_reports::Column::SubscriptionItemId.eq(item_id))
+            .order_by_asc(stripe_usage_reports::Column::CreatedAt)
+            .all(&db)
+            .await
+    };
+
+    for _ in 0..3 {
+        record_stripe_usage(&db, fixture.organization_id, &item_id).await?;
+    }
+    claim_pending_usage(&db).await?;
+    let after_claim = reports_of(db.clone(), item_id.clone()).await?;
+    let counter = stripe_usage_counters::Entity::find_by_id(item_id.clone())
+        .one(&db)
+        .await?;
+
+    // Nothing new was counted, so a second claim adds no report
+    claim_pending_usage(&db).await?;
+    let after_second_claim = reports_of(db.clone(), item_id.clone()).await?;
+
+    billing.set_unavailable(true);
+    record_stripe_usage(&db, fixture.organization_id, &item_id).await?;
+    report_stripe_usage(&state).await?;
+    let after_failed_send = reports_of(db.clone(), item_id.clone()).await?;
+
+    // Failed reports wait for their next attempt
+    report_stripe_usage(&state).await?;
+    let after_early_retry = reports_of(db.clone(), item_id.clone()).await?;
+
+    billing.set_unavailable(false);
+    stripe_usage_reports::Entity::update_many()
+        .col_expr(
+            stripe_usage_reports::Column::NextAttemptAt,
+            Utc::now().naive_utc().into(),
+        )
+        .filter(stripe_usage_reports::Column::SubscriptionItemId.eq(item_id.as_str()))
+        .exec(&db)
+        .await?;
+    report_stripe_usage(&state).await?;
+    let after_retry = reports_of(db.clone(), item_id.clone()).await?;
+
+    // As if the process died before marking the reports as sent
+    stripe_usage_reports::Entity::update_many()
+        .col_expr(
+            stripe_usage_reports::Column::ReportedAt,
+            Option::<chrono::NaiveDateTime>::None.into(),
+        )
+        .filter(stripe_usage_reports::Column::SubscriptionItemId.eq(item_id.as_str()))
+        .exec(&db)
+        .await?;
+    report_stripe_usage(&state).await?;
+    let billed = billing.get_usage(&item_id).await?.quantity;
+
+    // A report the reporter gave up on is only sent again once it is retried
+    record_stripe_usage(&db, fixture.organization_id, &item_id).await?;
+    record_stripe_usage(&db, fixture.organization_id, &item_id).await?;
+    claim_pending_usage(&db).await?;
+    stripe_usage_reports::Entity::update_many()
+        .col_expr(stripe_usage_reports::Column::Attempts, Expr::value(8))
+        .col_expr(stripe_usage_reports::Column::FailedAt, Some(Utc::now().naive_utc()).into())
+        .filter(stripe_usage_reports::Column::SubscriptionItemId.eq(item_id.as_str()))
+        .filter(stripe_usage_reports::Column::ReportedAt.is_null())
+        .exec(&db)
+        .await?;
+    let failed = reports_of(db.clone(), item_id.clone()).await?;
+    report_stripe_usage(&state).await?;
+    let billed_before_retry = billing.get_usage(&item_id).await?.quantity;
+
+    let retried = retry_failed_reports(&db, fixture.organization_id).await?;
+    report_stripe_usage(&state).await?;
+    let after_failed_retry = reports_of(db.clone(), item_id.clone()).await?;
+    let billed_after_retry = billing.get_usage(&item_id).await?.quantity;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(after_claim.len(), 1);
+    assert_eq!(after_claim[0].quantity, 3);
+    assert!(counter.is_some_and(|counter| counter.pending_quantity == 0));
+    assert_eq!(after_second_claim, after_claim);
+
+    let quantities: Vec<i64> = after_failed_send.iter().map(|report| report.quantity).collect();
+    assert_eq!(quantities, vec![3, 1]);
+    for report in &after_failed_send {
+        assert_eq!(report.attempts, 1);
+        assert!(report.reported_at.is_none() && report.failed_at.is_none());
+        assert!(report.last_error.is_some());
+        assert!(report.next_attempt_at > Utc::now().naive_utc());
+    }
+    assert_eq!(after_early_retry, after_failed_send);
+
+    for report in &after_retry {
+        assert_eq!(report.attempts, 2);
+        assert!(report.reported_at.is_some() && report.last_error.is_none());
+    }
+    // Resent reports reuse their idempotency keys, so nothing is billed twice
+    assert_eq!(billed, 4);
+
+    assert_eq!(billed_before_retry, 4);
+    assert_eq!(retried, 1);
+    let failed_report = failed.iter().find(|report| report.failed_at.is_some());
+    let retried_report = after_failed_retry.iter().find(|report| report.quantity == 2);
+    assert!(failed_report.is_some());
+    // A new id, and with it a new idempotency key
+    assert!(retried_report.is_some_and(|report| {
+        failed_report.is_some_and(|failed| failed.id != report.id)
+            && report.failed_at.is_none()
+            && report.reported_at.is_some()
+            && report.attempts == 1
+    }));
+    assert_eq!(billed_after_retry, 6);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn switching_tiers_moves_the_subscription_and_the_limit_together() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let billing = InMemoryBilling::default();
+    let app = api_router().with_state(test_state_with_billing(db.clone(), &billing)?);
+    let token = dashboard_token(&db, &fixture).await?;
+    let billing_path = format!("/api/organizations/{}/billing", fixture.organization_id);
+    let run = Uuid::new_v4().simple().to_string();
+    let subscription_id = format!("sub_{}", run);
+    let now = Utc::now().naive_utc();
+
+    billing.add_subscription(&format!("cus_{}", run), &subscription_id, &format!("si_{}", run));
+    organizations::ActiveModel {
+        id: Set(fixture.organization_id),
+        stripe_customer_id: Set(Some(format!("cus_{}", run))),
+        stripe_subscription_id: Set(Some(subscription_id.clone())),
+        stripe_subscription_item_id: Set(Some(format!("si_{}", run))),
+        ..Default::default()
+    }
+    .update(&db)
+    .await?;
+    organization_tiers::ActiveModel {
+        organization_id: Set(fixture.organization_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        monthly_request_limit: Set(50_000),
+        current_month_usage: Set(0),
+        last_reset_at: Set(Utc::now().fixed_offset()),
+        tier: Set(OrganizationTier::Basic),
+    }
+    .insert(&db)
+    .await?;
+
+    let tier_of = |db: DatabaseConnection| async move {
+        organization_tiers::Entity::find_by_id(fixture.organization_id)
+            .one(&db)
+            .await?
+            .map(|tier| (tier.tier, tier.monthly_request_limit))
+            .ok_or_else(|| Box::<dyn Error>::from("tier not found"))
+    };
+    let change_tier = |tier: &'static str| {
+        send_dashboard(
+            &app,
+            Method::PUT,
+            format!("{}/tier", billing_path),
+            &token,
+            Some(json!({ "tier": tier })),
+        )
+    };
+
+    let (preview_status, preview) = send_dashboard(
+        &app,
+        Method::POST,
+        format!("{}/preview", billing_path),
+        &token,
+        Some(json!({ "tier": "pro" })),
+    )
+    .await?;
+
+    let (upgrade_status, upgraded) = change_tier("pro").await?;
+    let after_upgrade = tier_of(db.clone()).await?;
+    let price_after_upgrade = billing.subscription_price(&subscription_id);
+
+    // The tier only changes if the billing provider took the new price
+    billing.set_unavailable(true);
+    let (outage_status, _) = change_tier("basic").await?;
+    let after_outage = tier_of(db.clone()).await?;
+    billing.set_unavailable(false);
+
+    let (checkout_status, _) = send_dashboard(
+        &app,
+        Method::POST,
+        format!("{}/checkout", billing_path),
+        &token,
+        Some(json!({
+            "tier": "basic",
+            "success_url": "https://example.com/billing?checkout=success",
+            "cancel_url": "https://example.com/billing",
+        })),
+    )
+    .await?;
+    let (portal_status, portal) = send_dashboard(
+        &app,
+        Method::POST,
+        format!("{}/portal", billing_path),
+        &token,
+        Some(json!({ "return_url": "https://example.com/billing" })),
+    )
+    .await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(preview_status, StatusCode::OK);
+    assert_eq!(preview["new_tier"], "pro");
+    assert_eq!(preview["new_monthly_request_limit"], 500_000);
+    assert_eq!(preview["usage_exceeds_new_limit"], false);
+    assert_eq!(preview["new_price"]["price_id"], "price_test_pro");
+
+    assert_eq!(upgrade_status, StatusCode::OK);
+    assert_eq!(upgraded["tier"], "pro");
+    assert_eq!(after_upgrade, (OrganizationTier::Pro, 500_000));
+    assert_eq!(price_after_upgrade.as_deref(), Some("price_test_pro"));
+
+    assert_eq!(outage_status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(after_outage, (OrganizationTier::Pro, 500_000));
+
+    // Organizations with a subscription change its tier instead of starting another one
+    assert_eq!(checkout_status, StatusCode::BAD_REQUEST);
+    assert_eq!(portal_status, StatusCode::OK);
+    assert_eq!(portal["url"], "https://example.com/billing");
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn usage_counters_are_reconciled_with_the_messages() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let billing = InMemoryBilling::default();
+    let state = test_state_with_billing(db.clone(), &billing)?;
+    let app = api_router().with_state(state.clone());
+    let token = dashboard_token(&db, &fixture).await?;
+    let item_id = format!("si_{}", Uuid::new_v4().simple());
+    let now = Utc::now().naive_utc();
+
+    messages::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        content: Set("deleted, but still billed".into()),
+        channel_id: Set(fixture.channel_id),
+        participant_id: Set(fixture.participant_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        edited_at: Set(None),
+        deleted_at: Set(Some(now)),
+        parent_message_id: Set(None),
+    }
+    .insert(&db)
+    .await?;
+    organizations::ActiveModel {
+        id: Set(fixture.organization_id),
+        stripe_subscription_item_id: Set(Some(item_id.clone())),
+        ..Default::default()
+    }
+    .update(&db)
+    .await?;
+    organization_tiers::ActiveModel {
+        organization_id: Set(fixture.organization_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        monthly_request_limit: Set(1_000),
+        current_month_usage: Set(7),
+        last_reset_at: Set(Utc::now().fixed_offset()),
+        tier: Set(OrganizationTier::Free),
+    }
+    .insert(&db)
+    .await?;
+    // One message billed, one still waiting for the usage reporter
+    billing.report_usage(&item_id, 1, 0, "already-billed").await?;
+    record_stripe_usage(&db, fixture.organization_id, &item_id).await?;
+
+    let usage_of = |db: DatabaseConnection| async move {
+        organization_tiers::Entity::find_by_id(fixture.organization_id)
+            .one(&db)
+            .await?
+            .map(|tier| tier.current_month_usage)
+            .ok_or_else(|| Box::<dyn Error>::from("tier not found"))
+    };
+
+    let (dry_run_status, dry_run) = send_dashboard(
+        &app,
+        Method::GET,
+        format!("/api/organizations/{}/usage/reconciliation", fixture.organization_id),
+        &token,
+        None,
+    )
+    .await?;
+    let after_dry_run = usage_of(db.clone()).await?;
+
+    // Like two pods running the job at once
+    let (repaired, repaired_again) = tokio::join!(
+        reconcile_organization(&state, fixture.organization_id, false),
+        reconcile_organization(&state, fixture.organization_id, false),
+    );
+    let (repaired, repaired_again) = (repaired?, repaired_again?);
+    let after_repair = usage_of(db.clone()).await?;
+    let rechecked = reconcile_organization(&state, fixture.organization_id, true).await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(dry_run_status, StatusCode::OK);
+    assert_eq!(dry_run["messages"], 2);
+    assert_eq!(dry_run["database_usage"], 7);
+    assert_eq!(dry_run["database_drift"], 5);
+    assert_eq!(dry_run["stripe"]["billed"], 1);
+    assert_eq!(dry_run["stripe"]["pending"], 1);
+    assert_eq!(dry_run["stripe"]["drift"], 0);
+    assert_eq!(dry_run["repaired"], false);
+    assert_eq!(after_dry_run, 7);
+
+    assert!(repaired.is_some_and(|result| result.repaired));
+    assert!(repaired_again.is_some());
+    assert_eq!(after_repair, 2);
+    assert!(rechecked.is_some_and(|result| result.database_drift == 0 && !result.repaired));
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn deleted_participants_are_anonymized_and_keep_their_messages() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+    let (_, admin) = &fixture.keys[2];
+    let participant_path = format!(
+        "/api/organizations/{}/participants/{}",
+        fixture.organization_id, fixture.participant_id
+    );
+    let route = |method: Method| RouteCase {
+        method,
+        path: participant_path.clone(),
+        body: None,
+        required: ApiKeyType::Admin,
+    };
+
+    let (deleted_status, _) = send(&app, &route(Method::DELETE), admin).await?;
+    let (get_status, _) = send(&app, &route(Method::GET), admin).await?;
+    let (again_status, _) = send(&app, &route(Method::DELETE), admin).await?;
+    let participant = participant::Entity::find_by_id(fixture.participant_id).one(&db).await?;
+    let message = messages::Entity::find_by_id(fixture.message_id).one(&db).await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(deleted_status, StatusCode::OK);
+    assert_eq!(get_status, StatusCode::NOT_FOUND);
+    assert_eq!(again_status, StatusCode::NOT_FOUND);
+    assert!(participant.is_some_and(|participant| participant.deleted_at.is_some()
+        && participant.external_id.is_none()
+        && participant.name == "Deleted participant"));
+    assert!(message.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn open_sockets_lose_access_with_their_membership_or_credentials() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let state = test_state(db.clone())?;
+    let now = Utc::now().naive_utc();
+
+    let channel = channels::ActiveModel {
+        id: Set(fixture.channel_id),
+        is_private: Set(true),
+        ..Default::default()
+    }
+    .update(&db)
+    .await?;
+    channel_participant::ActiveModel {
+        channel_id: Set(fixture.channel_id),
+        participant_id: Set(fixture.participant_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+    }
+    .insert(&db)
+    .await?;
+
+    let (key_type, key) = &fixture.keys[0];
+    let key_hmac = hash_api_key(key, TEST_API_KEY_PEPPER)?;
+    let key_id = api_keys::Entity::find()
+        .filter(api_keys::Column::KeyHmac.eq(key_hmac.as_str()))
+        .one(&db)
+        .await?
+        .map(|key| key.id)
+        .ok_or_else(|| Box::<dyn Error>::from("key not found"))?;
+
+    let socket = |credential: ClientCredential| SocketAuthorizer {
+        organization_id: fixture.organization_id,
+        channel: channel.clone(),
+        credential,
+    };
+    let participant_socket = |expires_at: i64| {
+        socket(ClientCredential::Participant {
+            participant_id: fixture.participant_id,
+            channel_ids: None,
+            expires_at,
+        })
+    };
+    let key_socket = socket(ClientCredential::ApiKey {
+        key_id,
+        key_hmac,
+        key_type: key_type.clone(),
+        scopes: None,
+        channel_ids: None,
+    });
+    let in_an_hour = (Utc::now() + chrono::Duration::hours(1)).timestamp();
+    let is_revoked = |result: Result<(), ErrorPayload>| {
+        result.is_err_and(|e| matches!(e.code, ErrorCode::AccessRevoked))
+    };
+
+    let member = check_access(&state, &participant_socket(in_an_hour)).await;
+    let expired = check_access(&state, &participant_socket(Utc::now().timestamp() - 1)).await;
+    let valid_key = check_access(&state, &key_socket).await;
+
+    channel_participant::Entity::delete_by_id((fixture.channel_id, fixture.participant_id))
+        .exec(&db)
+        .await?;
+    api_keys::ActiveModel {
+        id: Set(key_id),
+        revoked_at: Set(Some(now)),
+        ..Default::default()
+    }
+    .update(&db)
+    .await?;
+    let removed = check_access(&state, &participant_socket(in_an_hour)).await;
+    let revoked_key = check_access(&state, &key_socket).await;
+
+    cleanup(&db, &fixture).await?;
+
+    assert!(member.is_ok());
+    assert!(is_revoked(expired));
+    assert!(valid_key.is_ok());
+    assert!(is_revoked(removed));
+    assert!(is_revoked(revoked_key));
+    Ok(())
+}
+
+fn participant_token(
+    organization_id: Uuid,
+    participant_id: Uuid,
+    channel_ids: Option<Vec<Uuid>>,
+) -> Result<String, Box<dyn Error>> {
+    let now = Utc::now().timestamp();
+    let claims = ParticipantClaims {
+        sub: participant_id,
+        organization_id,
+        aud: PARTICIPANT_TOKEN_AUDIENCE.into(),
+        exp: now + 3600,
+        iat: now,
+        channel_ids,
+    };
+    Ok(encode_participant_token(&claims, &std::env::var("JWT_SECRET")?)?)
+}
+
+// `oneshot` requests can't be upgraded, so WebSocket handshakes go to a real server.
+// Returns the status the server answered the handshake with.
+async fn ws_handshake(addr: SocketAddr, path: &str, headers: &[(&str, &str)]) -> Result<u16, Box<dyn Error>> {
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+         Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n",
+        path, addr
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await?;
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 512];
+    while !response.windows(2).any(|window| window == b"\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            return Err("connection closed before the status line".into());
+        }
+        response.extend_from_slice(&buf[..read]);
+    }
+
+    let status_line = String::from_utf8_lossy(&response).into_owned();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or("malformed status line")?
+        .parse()?;
+    Ok(status)
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn socket_handshakes_need_credentials_for_the_channel() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let other = seed(&db).await?;
+    let now = Utc::now().naive_utc();
+
+    channels::ActiveModel {
+        id: Set(fixture.channel_id),
+        is_private: Set(true),
+        ..Default::default()
+    }
+    .update(&db)
+    .await?;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let app = api_router().with_state(test_state(db.clone())?);
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
+    });
+
+    let room = format!("/ws/chat/{}", fixture.channel_id);
+    let token = participant_token(fixture.organization_id, fixture.participant_id, None)?;
+    let other_org_token = participant_token(other.organization_id, other.participant_id, None)?;
+    let other_channel_token =
+        participant_token(fixture.organization_id, fixture.participant_id, Some(vec![Uuid::new_v4()]))?;
+    let read_only_key = fixture.keys[0].1.as_str();
+    let other_org_key = other.keys[2].1.as_str();
+
+    let missing = ws_handshake(addr, &room, &[]).await?;
+    let invalid_key = ws_handshake(addr, &room, &[("X-API-Key", "not-a-key")]).await?;
+    let invalid_token = ws_handshake(addr, &format!("{}?token=not-a-token", room), &[]).await?;
+    let foreign_key = ws_handshake(addr, &room, &[("X-API-Key", other_org_key)]).await?;
+    let foreign_token = ws_handshake(addr, &format!("{}?token={}", room, other_org_token), &[]).await?;
+    let unknown_room = format!("/ws/chat/{}", Uuid::new_v4());
+    let unknown_room = ws_handshake(addr, &unknown_room, &[("X-API-Key", read_only_key)]).await?;
+    let restricted_token = ws_handshake(addr, &format!("{}?token={}", room, other_channel_token), &[]).await?;
+    let non_member = ws_handshake(addr, &format!("{}?token={}", room, token), &[]).await?;
+    let key = ws_handshake(addr, &room, &[("X-API-Key", read_only_key)]).await?;
+
+    channel_participant::ActiveModel {
+        channel_id: Set(fixture.channel_id),
+        participant_id: Set(fixture.participant_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+    }
+    .insert(&db)
+    .await?;
+    let authorization = format!("Bearer {}", token);
+    let member = ws_handshake(addr, &room, &[("Authorization", authorization.as_str())]).await?;
+
+    server.abort();
+    cleanup(&db, &fixture).await?;
+    cleanup(&db, &other).await?;
+
+    assert_eq!(missing, 401);
+    assert_eq!(invalid_key, 401);
+    assert_eq!(invalid_token, 401);
+    assert_eq!(foreign_key, 401);
+    assert_eq!(foreign_token, 401);
+    assert_eq!(unknown_room, 404);
+    assert_eq!(restricted_token, 403);
+    assert_eq!(non_member, 403);
+    // Keys name a participant per frame, so they may listen to private channels
+    assert_eq!(key, 101);
+    assert_eq!(member, 101);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn participant_tokens_only_work_in_their_organization() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let other = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+
+    let read_messages = |token: String| -> Result<Request<Body>, Box<dyn Error>> {
+        Ok(Request::builder()
+            .uri(format!(
+                "/api/organizations/{}/messages/{}",
+                fixture.organization_id, fixture.channel_id
+            ))
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?)
+    };
+    let status_of = |request: Request<Body>| {
+        let app = app.clone();
+        async move { Ok::<_, Box<dyn Error>>(app.oneshot(request).await?.status()) }
+    };
+
+    let now = Utc::now().timestamp();
+    let expired_claims = ParticipantClaims {
+        sub: fixture.participant_id,
+        organization_id: fixture.organization_id,
+        aud: PARTICIPANT_TOKEN_AUDIENCE.into(),
+        exp: now - 3600,
+        iat: now - 7200,
+        channel_ids: None,
+    };
+    let expired = encode_participant_token(&expired_claims, &std::env::var("JWT_SECRET")?)?;
+
+    let own = participant_token(fixture.organization_id, fixture.participant_id, None)?;
+    let foreign = participant_token(other.organization_id, other.participant_id, None)?;
+
+    let own = status_of(read_messages(own)?).await?;
+    let foreign = status_of(read_messages(foreign)?).await?;
+    let expired = status_of(read_messages(expired)?).await?;
+
+    cleanup(&db, &fixture).await?;
+    cleanup(&db, &other).await?;
+
+    assert_eq!(own, StatusCode::OK);
+    assert_eq!(foreign, StatusCode::UNAUTHORIZED);
+    assert_eq!(expired, StatusCode::UNAUTHORIZED);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn message_pages_follow_their_cursors_without_gaps_or_repeats() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let other = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+
+    // Five messages per second, so pages have to break up messages sharing a timestamp
+    let start = (Utc::now() - chrono::Duration::days(1)).naive_utc();
+    let seeded: Vec<messages::ActiveModel> = (0..205)
+        .map(|i| {
+            let created_at = start + chrono::Duration::seconds(i / 5);
+            messages::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                content: Set(format!("message {}", i)),
+                channel_id: Set(fixture.channel_id),
+                participant_id: Set(fixture.participant_id),
+                created_at: Set(created_at),
+                updated_at: Set(created_at),
+                edited_at: Set(None),
+                deleted_at: Set(None),
+                parent_message_id: Set(None),
+            }
+        })
+        .collect();
+    messages::Entity::insert_many(seeded).exec(&db).await?;
+
+    let mut newest_first: Vec<(chrono::NaiveDateTime, Uuid)> = messages::Entity::find()
+        .filter(messages::Column::ChannelId.eq(fixture.channel_id))
+        .all(&db)
+        .await?
+        .into_iter()
+        .map(|message| (message.created_at, message.id))
+        .collect();
+    newest_first.sort_by(|a, b| b.cmp(a));
+    let newest_first: Vec<String> = newest_first.into_iter().map(|(_, id)| id.to_string()).collect();
+    let oldest = newest_first.last().cloned().unwrap_or_default();
+
+    let key = fixture.keys[0].1.clone();
+    let list = |query: String| {
+        let app = app.clone();
+        let key = key.clone();
+        let path = format!(
+            "/api/organizations/{}/messages/{}?{}",
+            fixture.organization_id, fixture.channel_id, query
+        );
+        async move {
+            let route = RouteCase {
+                method: Method::GET,
+                path,
+                body: None,
+                required: ApiKeyType::ReadOnly,
+            };
+            let (status, body) = send(&app, &route, &key).await?;
+            let body: serde_json::Value = serde_json::from_str(&body)?;
+            let ids: Vec<String> = body["data"]
+                .as_array()
+                .map(|messages| {
+                    messages
+                        .iter()
+                        .filter_map(|message| message["id"].as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let next_cursor = body["next_cursor"].as_str().map(String::from);
+            Ok::<_, Box<dyn Error>>((status, ids, next_cursor))
+        }
+    };
+
+    let (_, default_page, default_next) = list(String::new()).await?;
+    let (_, clamped_up, _) = list("limit=1000".into()).await?;
+    let (_, clamped_down, _) = list("limit=0".into()).await?;
+
+    // Newest to oldest with `before`, then back again with `after`
+    let mut backwards = Vec::new();
+    let mut cursor = None;
+    loop {
+        let query = match &cursor {
+            Some(cursor) => format!("limit=7&before={}", cursor),
+            None => "limit=7".to_string(),
+        };
+        let (_, ids, next_cursor) = list(query).await?;
+        backwards.extend(ids);
+        match next_cursor {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+    let mut forwards = Vec::new();
+    let mut cursor = oldest.clone();
+    loop {
+        let (_, ids, next_cursor) = list(format!("limit=7&after={}", cursor)).await?;
+        forwards.extend(ids);
+        match next_cursor {
+            Some(next) => cursor = next,
+            None => break,
+        }
+    }
+
+    // A timestamp cursor only has the first second of messages before it
+    let second = (start + chrono::Duration::seconds(1)).and_utc().to_rfc3339();
+    let (_, before_second, _) = list(format!("before={}", second.replace('+', "%2B"))).await?;
+
+    let (both_status, _, _) = list(format!("before={}&after={}", oldest, oldest)).await?;
+    let (invalid_status, _, _) = list("before=yesterday".into()).await?;
+    let (foreign_status, _, _) = list(format!("before={}", other.message_id)).await?;
+
+    cleanup(&db, &fixture).await?;
+    cleanup(&db, &other).await?;
+
+    assert_eq!(newest_first.len(), 206);
+    assert_eq!(default_page, newest_first[..50]);
+    assert_eq!(default_next.as_deref(), Some(newest_first[49].as_str()));
+    assert_eq!(clamped_up, newest_first[..200]);
+    assert_eq!(clamped_down, newest_first[..1]);
+
+    assert_eq!(backwards, newest_first);
+    let mut oldest_first = newest_first.clone();
+    oldest_first.reverse();
+    assert_eq!(forwards, oldest_first[1..]);
+    assert_eq!(before_second, newest_first[newest_first.len() - 5..]);
+
+    assert_eq!(both_status, StatusCode::BAD_REQUEST);
+    assert_eq!(invalid_status, StatusCode::BAD_REQUEST);
+    assert_eq!(foreign_status, StatusCode::BAD_REQUEST);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn concurrent_creates_with_one_external_id_make_one_participant() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+
+    let create = RouteCase {
+        method: Method::POST,
+        path: format!("/api/organizations/{}/participants", fixture.organization_id),
+        body: Some(json!({ "name": "Grace", "external_id": "ext-concurrent" })),
+        required: ApiKeyType::ReadWrite,
+    };
+    let key = fixture.keys[1].1.as_str();
+    let responses = futures::future::join_all((0..8).map(|_| send(&app, &create, key))).await;
+
+    let created = participant::Entity::find()
+        .filter(participant::Column::OrganizationId.eq(fixture.organization_id))
+        .filter(participant::Column::ExternalId.eq("ext-concurrent"))
+        .count(&db)
+        .await?;
+
+    cleanup(&db, &fixture).await?;
+
+    let mut statuses = Vec::new();
+    for response in responses {
+        statuses.push(response?.0);
+    }
+    statuses.sort();
+    let mut expected = vec![StatusCode::BAD_REQUEST; 7];
+    expected.insert(0, StatusCode::CREATED);
+    assert_eq!(statuses, expected);
+    assert_eq!(created, 1);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn reactions_with_and_without_the_presentation_selector_are_the_same() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+
+    let reactions = format!(
+        "/api/organizations/{}/messages/{}/reactions",
+        fixture.organization_id, fixture.message_id
+    );
+    let react = |emoji: &str| RouteCase {
+        method: Method::POST,
+        path: reactions.clone(),
+        body: Some(json!({ "emoji": emoji, "participant_id": fixture.participant_id })),
+        required: ApiKeyType::ReadWrite,
+    };
+    let key = fixture.keys[1].1.as_str();
+
+    let (with_selector, body) = send(&app, &react("❤\u{FE0F}\u{200D}🔥"), key).await?;
+    let (without_selector, _) = send(&app, &react("❤\u{200D}🔥"), key).await?;
+    let stored: Vec<String> = message_reactions::Entity::find()
+        .filter(message_reactions::Column::MessageId.eq(fixture.message_id))
+        .all(&db)
+        .await?
+        .into_iter()
+        .map(|reaction| reaction.emoji)
+        .collect();
+
+    // ❤‍🔥 without U+FE0F, percent-encoded
+    let remove = RouteCase {
+        method: Method::DELETE,
+        path: format!(
+            "{}/%E2%9D%A4%E2%80%8D%F0%9F%94%A5?participant_id={}",
+            reactions, fixture.participant_id
+        ),
+        body: None,
+        required: ApiKeyType::ReadWrite,
+    };
+    let (removed, _) = send(&app, &remove, key).await?;
+    let left = message_reactions::Entity::find()
+        .filter(message_reactions::Column::MessageId.eq(fixture.message_id))
+        .count(&db)
+        .await?;
+
+    cleanup(&db, &fixture).await?;
+
+    assert_eq!(with_selector, StatusCode::CREATED, "{}", body);
+    assert_eq!(without_selector, StatusCode::OK);
+    assert_eq!(stored, vec!["❤\u{FE0F}\u{200D}🔥".to_string()]);
+    assert_eq!(removed, StatusCode::OK);
+    assert_eq!(left, 0);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn deleted_thread_parents_stay_listed_while_they_have_replies() -> TestResult {
+    let db = connect().await?;
+
+    let fixture = seed(&db).await?;
+    let app = api_router().with_state(test_state(db.clone())?);
+
+    let now = Utc::now().naive_utc();
+    let reply = |id: Uuid, parent_message_id: Uuid, deleted: bool| messages::ActiveModel {
+        id: Set(id),
+        content: Set("reply".into()),
+        channel_id: Set(fixture.channel_id),
+        participant_id: Set(fixture.participant_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        edited_at: Set(None),
+        deleted_at: Set(deleted.then_some(now)),
+        parent_message_id: Set(Some(parent_message_id)),
+    };
+    let live_reply = Uuid::new_v4();
+    let lonely_parent = Uuid::new_v4();
+    messages::Entity::insert_many([
+        reply(live_reply, fixture.message_id, false),
+        reply(Uuid::new_v4(), fixture.message_id, true),
+    ])
+    .exec(&db)
+    .await?;
+    messages::ActiveModel {
+        id: Set(lonely_parent),
+        content: Set("no replies".into()),
+        channel_id: Set(fixture.channel_id),
+        participant_id: Set(fixture.participant_id),
+        created_at: Set(now),
+        updated_at: Set(now),
+        edited_at: Set(None),
+        deleted_at: Set(None),
+        parent_message_id: Set(None),
+    }
+    .insert(&db)
+    .await?;
+
+    let org = format!("/api/organizations/{}", fixture.organization_id);
+    let route = |method: Method, path: String| RouteCase {
+        method,
+        path: format!("{}{}", org, path),
+        body: None,
+        required: ApiKeyType::ReadWrite,
+    };
+    let key = fixture.keys[1].1.as_str();
+
+    for message_id in [fixture.message_id, lonely_parent] {
+        send(&app, &route(Method::DELETE, format!("/messages/{}", message_id)), key).await?;
+    }
+    let (_, listing) = send(&app, &route(Method::GET, format!("/messages/{}", fixture.channel_id)), key).await?;
+    let (replies_status, replies) =
+        send(&app, &route(Method::GET, format!("/messages/{}/replies", fixture.message_id)), key).await?;
+
+    cleanup(&db, &fixture).await?;
+
+    let listing: serde_json::Value = serde_json::from_str(&listing)?;
+    let listed = listing["data"].as_array().cloned().unwrap_or_default();
+    assert_eq!(listed.len(), 1, "{:?}", listed);
+    assert_eq!(listed[0]["id"], json!(fixture.message_id));
+    assert_eq!(listed[0]["content"], "");
+    assert!(listed[0]["deleted_at"].is_string());
+    assert_eq!(listed[0]["reply_count"], 1);
+
+    assert_eq!(replies_status, StatusCode::OK);
+    let replies: serde_json::Value = serde_json::from_str(&replies)?;
+    let reply_ids: Vec<serde_json::Value> = replies["data"]
+        .as_array()
+        .map(|replies| replies.iter().map(|reply| reply["id"].clone()).collect())
+        .unwrap_or_default();
+    assert_eq!(reply_ids, vec![json!(live_reply)]);
+    Ok(())
+}
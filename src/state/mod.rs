@@ -1,22 +1,35 @@
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use crate::config::{Database, RedisStore, StripeClient};
+use crate::config::{ApiKeyPepper, BillingProvider, Database, RedisStore};
+use crate::middleware::api_key_authorizer::KeyDenialBuffer;
+use crate::middleware::usage_tracker::KeyUsageBuffer;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Database,
     pub redis: RedisStore,
-    pub stripe: StripeClient,
-    pub active_users: Arc<RwLock<i64>>  // This is now tokio's RwLock
+    pub billing: Arc<dyn BillingProvider>,
+    pub api_key_pepper: ApiKeyPepper,
+    pub active_users: Arc<RwLock<i64>>,  // This is now tokio's RwLock
+    pub key_usage: KeyUsageBuffer,
+    pub key_denials: KeyDenialBuffer,
 }
 
 impl AppState {
-    pub fn new(db: Database, redis: RedisStore, stripe: StripeClient) -> Self {
+    pub fn new(
+        db: Database,
+        redis: RedisStore,
+        billing: Arc<dyn BillingProvider>,
+        api_key_pepper: ApiKeyPepper,
+    ) -> Self {
         Self {
             db,
             redis,
-            stripe,
+            billing,
+            api_key_pepper,
             active_users: Arc::new(RwLock::new(0)),
+            key_usage: KeyUsageBuffer::default(),
+            key_denials: KeyDenialBuffer::default(),
         }
     }
 }
@@ -1,5 +1,8 @@
+use hmac::{Hmac, Mac};
 use sha2::{Digest, Sha256};
 
+const API_KEY_SECRET_BYTES: usize = 32;
+
 pub fn generate_api_key_prefix(api_key: &str) -> String {
     let prefix = if api_key.len() >= 8 {
         &api_key[..8]
@@ -11,3 +14,18 @@ pub fn generate_api_key_prefix(api_key: &str) -> String {
     hasher.update(prefix.as_bytes());
     hex::encode(hasher.finalize())
 }
+
+// 256 random bits, so a fast hash is enough to store them safely
+pub fn generate_api_key() -> Result<String, getrandom::Error> {
+    let mut secret = [0u8; API_KEY_SECRET_BYTES];
+    getrandom::getrandom(&mut secret)?;
+    Ok(format!("sk_{}", hex::encode(secret)))
+}
+
+// HMAC-SHA256 of the key with the server's pepper. It's deterministic, so keys
+// are looked up by it directly instead of verifying every candidate.
+pub fn hash_api_key(api_key: &str, pepper: &str) -> Result<String, hmac::digest::InvalidLength> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(pepper.as_bytes())?;
+    mac.update(api_key.as_bytes());
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
@@ -1,10 +1,20 @@
 mod bcrypt_helpers;
 mod response;
 mod api_keys_helpers;
+mod participant_token_helpers;
+mod serde_helpers;
+mod stripe_webhook_helpers;
 mod setup_logging;
 
-pub use response::{GeneralError, ServerResponse};
+pub use response::ServerResponse;
 
 pub use bcrypt_helpers::{hash_password_and_salt, verify_password};
-pub use api_keys_helpers::{generate_api_key_prefix};
+pub use api_keys_helpers::{generate_api_key, generate_api_key_prefix, hash_api_key};
+pub use participant_token_helpers::{
+    decode_participant_token, encode_participant_token, ParticipantClaims, PARTICIPANT_TOKEN_AUDIENCE,
+};
+pub use serde_helpers::deserialize_some;
+pub use stripe_webhook_helpers::verify_stripe_signature;
+#[cfg(test)]
+pub use stripe_webhook_helpers::sign_stripe_payload;
 pub use setup_logging::setup_logging;
\ No newline at end of file
@@ -0,0 +1,109 @@
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Audience claim that separates participant tokens from dashboard JWTs
+// signed with the same secret
+pub const PARTICIPANT_TOKEN_AUDIENCE: &str = "participant";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParticipantClaims {
+    pub sub: Uuid, // Participant ID
+    pub organization_id: Uuid,
+    // Defaulted so a missing audience is rejected by the validation, not by chance
+    // by deserializing
+    #[serde(default)]
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    // When set, the token can only be used for these channels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_ids: Option<Vec<Uuid>>,
+}
+
+pub fn encode_participant_token(
+    claims: &ParticipantClaims,
+    secret: &str,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    encode(
+        &Header::default(),
+        claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+}
+
+pub fn decode_participant_token(
+    token: &str,
+    secret: &str,
+) -> Result<ParticipantClaims, jsonwebtoken::errors::Error> {
+    // `aud` is only checked when the token has one, so it has to be required as well
+    let mut validation = Validation::default();
+    validation.set_audience(&[PARTICIPANT_TOKEN_AUDIENCE]);
+    validation.set_required_spec_claims(&["exp", "aud"]);
+
+    decode::<ParticipantClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .map(|data| data.claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::errors::ErrorKind;
+
+    const SECRET: &str = "participant-token-test-secret";
+
+    fn encode_claims(claims: &serde_json::Value) -> Result<String, jsonwebtoken::errors::Error> {
+        encode(&Header::default(), claims, &EncodingKey::from_secret(SECRET.as_bytes()))
+    }
+
+    fn claims(exp: i64) -> serde_json::Value {
+        serde_json::json!({
+            "sub": Uuid::new_v4(),
+            "organization_id": Uuid::new_v4(),
+            "aud": PARTICIPANT_TOKEN_AUDIENCE,
+            "exp": exp,
+            "iat": exp - 3600,
+        })
+    }
+
+    fn error_kind(token: &str) -> Option<ErrorKind> {
+        decode_participant_token(token, SECRET).err().map(|e| e.into_kind())
+    }
+
+    #[test]
+    fn only_unexpired_participant_tokens_are_accepted() -> Result<(), jsonwebtoken::errors::Error> {
+        let in_an_hour = chrono::Utc::now().timestamp() + 3600;
+        let an_hour_ago = in_an_hour - 7200;
+
+        let valid = encode_claims(&claims(in_an_hour))?;
+        let expired = encode_claims(&claims(an_hour_ago))?;
+
+        assert!(decode_participant_token(&valid, SECRET).is_ok());
+        assert!(matches!(error_kind(&expired), Some(ErrorKind::ExpiredSignature)));
+        Ok(())
+    }
+
+    #[test]
+    fn tokens_need_the_participant_audience() -> Result<(), jsonwebtoken::errors::Error> {
+        let in_an_hour = chrono::Utc::now().timestamp() + 3600;
+
+        let mut dashboard = claims(in_an_hour);
+        dashboard["aud"] = serde_json::json!("dashboard");
+        let mut without_audience = claims(in_an_hour);
+        without_audience.as_object_mut().map(|claims| claims.remove("aud"));
+
+        let wrong_audience = encode_claims(&dashboard)?;
+        let missing_audience = encode_claims(&without_audience)?;
+
+        assert!(matches!(error_kind(&wrong_audience), Some(ErrorKind::InvalidAudience)));
+        assert!(matches!(
+            error_kind(&missing_audience),
+            Some(ErrorKind::MissingRequiredClaim(claim)) if claim == "aud"
+        ));
+        Ok(())
+    }
+}
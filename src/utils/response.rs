@@ -26,6 +26,10 @@ pub enum GeneralError {
 
     #[error("Rate limit exceeded: {0}")]
     RateLimit(String),
+
+    // The API key is valid but not allowed from this address or origin
+    #[error("Client not allowed: {0}")]
+    ClientNotAllowed(String),
 }
 
 #[derive(Debug, Serialize)]
@@ -33,6 +37,11 @@ pub struct ApiResponse<T: Serialize> {
     pub data: Option<T>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<GeneralError>,
+    // Only set by paginated endpoints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_cursor: Option<String>,
 }
 
 pub struct ServerResponse;
@@ -44,6 +53,8 @@ impl ServerResponse {
             Json(ApiResponse {
                 data: Some(data),
                 error: None,
+                next_cursor: None,
+                prev_cursor: None,
             }),
         )
             .into_response()
@@ -55,6 +66,25 @@ impl ServerResponse {
             Json(ApiResponse {
                 data: Some(data),
                 error: None,
+                next_cursor: None,
+                prev_cursor: None,
+            }),
+        )
+            .into_response()
+    }
+
+    pub fn paginated<T: Serialize>(
+        data: T,
+        next_cursor: Option<String>,
+        prev_cursor: Option<String>,
+    ) -> Response {
+        (
+            StatusCode::OK,
+            Json(ApiResponse {
+                data: Some(data),
+                error: None,
+                next_cursor,
+                prev_cursor,
             }),
         )
             .into_response()
@@ -66,6 +96,8 @@ impl ServerResponse {
             Json(ApiResponse::<()> {
                 data: None,
                 error: Some(GeneralError::BadRequest(detail.into())),
+                next_cursor: None,
+                prev_cursor: None,
             }),
         )
             .into_response()
@@ -77,6 +109,8 @@ impl ServerResponse {
             Json(ApiResponse::<()> {
                 data: None,
                 error: Some(GeneralError::Authentication(detail.into())),
+                next_cursor: None,
+                prev_cursor: None,
             }),
         )
             .into_response()
@@ -88,6 +122,21 @@ impl ServerResponse {
             Json(ApiResponse::<()> {
                 data: None,
                 error: Some(GeneralError::Authorization(detail.into())),
+                next_cursor: None,
+                prev_cursor: None,
+            }),
+        )
+            .into_response()
+    }
+
+    pub fn client_not_allowed(detail: impl Into<String>) -> Response {
+        (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()> {
+                data: None,
+                error: Some(GeneralError::ClientNotAllowed(detail.into())),
+                next_cursor: None,
+                prev_cursor: None,
             }),
         )
             .into_response()
@@ -99,6 +148,8 @@ impl ServerResponse {
             Json(ApiResponse::<()> {
                 data: None,
                 error: Some(GeneralError::NotFound(detail.into())),
+                next_cursor: None,
+                prev_cursor: None,
             }),
         )
             .into_response()
@@ -111,6 +162,8 @@ impl ServerResponse {
             Json(ApiResponse::<()> {
                 data: None,
                 error: Some(GeneralError::Internal(detail.into())),
+                next_cursor: None,
+                prev_cursor: None,
             }),
         )
             .into_response()
@@ -0,0 +1,10 @@
+use serde::{Deserialize, Deserializer};
+
+// Tells a missing field apart from an explicit `null`, use together with `#[serde(default)]`
+pub fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}